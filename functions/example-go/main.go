@@ -4,6 +4,7 @@ import (
     "encoding/json"
     "fmt"
     "net/http"
+    "os"
 )
 
 type sortRequest struct {
@@ -75,7 +76,11 @@ func main() {
     http.Handle("/", handleQuickSort)
     http.Handle("/ok", handleOk)
     http.Handle("/notfound", handleNotFound)
-    if err := http.ListenAndServe(":8080", nil); err != nil {
+    port := os.Getenv("PORT")
+    if port == "" {
+        port = "8080"
+    }
+    if err := http.ListenAndServe(":"+port, nil); err != nil {
         fmt.Printf("Server error: %v\n", err)
     }
 }
\ No newline at end of file
@@ -0,0 +1,264 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+use crate::errors::limit_error::LimitError;
+
+/// Admission settings of a function, with `capacity` derived from its
+/// current replica count.
+#[derive(Debug, Clone, Copy)]
+pub struct AdmissionLimits {
+    pub per_replica: usize,
+    pub capacity: usize,
+    pub max_queue_length: usize,
+    pub queue_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    in_flight: usize,
+    capacity: usize,
+    per_replica: usize,
+    max_queue_length: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+    queued_total: u64,
+    rejected_total: u64,
+    timed_out_total: u64,
+    max_depth: usize,
+}
+
+impl QueueState {
+    fn configure(&mut self, limits: AdmissionLimits) {
+        self.capacity = limits.capacity;
+        self.per_replica = limits.per_replica.max(1);
+        self.max_queue_length = limits.max_queue_length;
+        self.waiters.retain(|waiter| !waiter.is_closed());
+        self.hand_off();
+    }
+
+    /// Passes free slots to waiters in arrival order. Waiters that already
+    /// gave up are skipped.
+    fn hand_off(&mut self) {
+        while self.in_flight < self.capacity {
+            let Some(waiter) = self.waiters.pop_front() else {
+                break;
+            };
+            if waiter.send(()).is_ok() {
+                self.in_flight += 1;
+            }
+        }
+    }
+}
+
+/// Queue depth and throughput counters of one function, for `GET /metrics`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub depth: usize,
+    #[serde(rename = "inFlight")]
+    pub in_flight: usize,
+    pub capacity: usize,
+    #[serde(rename = "maxQueueLength")]
+    pub max_queue_length: usize,
+    #[serde(rename = "maxDepth")]
+    pub max_depth: usize,
+    #[serde(rename = "queuedTotal")]
+    pub queued_total: u64,
+    #[serde(rename = "rejectedTotal")]
+    pub rejected_total: u64,
+    #[serde(rename = "timedOutTotal")]
+    pub timed_out_total: u64,
+    /// Replicas needed to run everything admitted and queued right now; the
+    /// scaling signal for an autoscaler.
+    #[serde(rename = "desiredReplicas")]
+    pub desired_replicas: usize,
+}
+
+/// Bounded FIFO admission queue in front of a function's replicas.
+#[derive(Debug, Default)]
+pub struct AdmissionQueue(Mutex<QueueState>);
+
+impl AdmissionQueue {
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn configure(&self, limits: AdmissionLimits) {
+        self.state().configure(limits);
+    }
+
+    /// Takes a slot right away, or waits in line for up to the queue timeout.
+    /// A full queue is rejected immediately.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        function_name: &str,
+        limits: AdmissionLimits,
+    ) -> Result<AdmissionPermit, LimitError> {
+        let retry_after_secs = limits.queue_timeout.as_secs().max(1);
+        let mut admitted = {
+            let mut state = self.state();
+            state.configure(limits);
+            if state.in_flight < state.capacity && state.waiters.is_empty() {
+                state.in_flight += 1;
+                return Ok(AdmissionPermit(Arc::clone(self)));
+            }
+            if state.waiters.len() >= state.max_queue_length {
+                state.rejected_total += 1;
+                return Err(LimitError::QueueFull {
+                    function: function_name.to_string(),
+                    retry_after_secs,
+                });
+            }
+            let (waiter, admitted) = oneshot::channel();
+            state.waiters.push_back(waiter);
+            state.queued_total += 1;
+            state.max_depth = state.max_depth.max(state.waiters.len());
+            admitted
+        };
+
+        match tokio::time::timeout(limits.queue_timeout, &mut admitted).await {
+            Ok(Ok(())) => return Ok(AdmissionPermit(Arc::clone(self))),
+            Ok(Err(_)) => {}
+            Err(_) => {
+                // A slot may have been handed over right as the timeout fired.
+                admitted.close();
+                if admitted.try_recv().is_ok() {
+                    return Ok(AdmissionPermit(Arc::clone(self)));
+                }
+            }
+        }
+        self.state().timed_out_total += 1;
+        Err(LimitError::QueueTimeout {
+            function: function_name.to_string(),
+            retry_after_secs,
+        })
+    }
+
+    fn release(&self) {
+        let mut state = self.state();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.hand_off();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let mut state = self.state();
+        state.waiters.retain(|waiter| !waiter.is_closed());
+        let depth = state.waiters.len();
+        QueueStats {
+            depth,
+            in_flight: state.in_flight,
+            capacity: state.capacity,
+            max_queue_length: state.max_queue_length,
+            max_depth: state.max_depth,
+            queued_total: state.queued_total,
+            rejected_total: state.rejected_total,
+            timed_out_total: state.timed_out_total,
+            desired_replicas: (state.in_flight + depth).div_ceil(state.per_replica.max(1)),
+        }
+    }
+}
+
+/// A slot in a function's admission queue, freed when dropped.
+#[derive(Debug)]
+pub struct AdmissionPermit(Arc<AdmissionQueue>);
+
+impl Drop for AdmissionPermit {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// Admission queues of all functions that set `maxConcurrencyPerReplica`.
+#[derive(Debug, Default)]
+pub struct Admission(Mutex<HashMap<String, Arc<AdmissionQueue>>>);
+
+impl Admission {
+    pub fn queue(&self, function_name: &str) -> Arc<AdmissionQueue> {
+        let mut queues = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(queues.entry(function_name.to_string()).or_default())
+    }
+
+    pub fn existing_queue(&self, function_name: &str) -> Option<Arc<AdmissionQueue>> {
+        let queues = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        queues.get(function_name).cloned()
+    }
+
+    pub fn stats(&self) -> HashMap<String, QueueStats> {
+        let queues = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        queues
+            .iter()
+            .map(|(function_name, queue)| (function_name.clone(), queue.stats()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::errors::limit_error::LimitError;
+
+    use super::{AdmissionLimits, AdmissionQueue};
+
+    fn limits(capacity: usize, max_queue_length: usize, queue_timeout_ms: u64) -> AdmissionLimits {
+        AdmissionLimits {
+            per_replica: 1,
+            capacity,
+            max_queue_length,
+            queue_timeout: Duration::from_millis(queue_timeout_ms),
+        }
+    }
+
+    #[tokio::test]
+    async fn waiters_get_released_slots_and_overflow_is_rejected() {
+        let queue = Arc::new(AdmissionQueue::default());
+        let first = queue.acquire("example", limits(1, 1, 1000)).await.expect("free slot");
+
+        let waiting_queue = Arc::clone(&queue);
+        let waiting = tokio::spawn(async move {
+            waiting_queue.acquire("example", limits(1, 1, 1000)).await.map(|_| ())
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.stats().depth, 1);
+
+        let overflow = queue.acquire("example", limits(1, 1, 1000)).await;
+        assert!(matches!(overflow, Err(LimitError::QueueFull { .. })));
+
+        drop(first);
+        waiting.await.expect("task").expect("queued request should be admitted");
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.in_flight), (0, 0));
+        assert_eq!((stats.queued_total, stats.rejected_total), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn queued_requests_time_out_without_leaking_slots() {
+        let queue = Arc::new(AdmissionQueue::default());
+        let _busy = queue.acquire("example", limits(1, 4, 20)).await.expect("free slot");
+
+        let timed_out = queue.acquire("example", limits(1, 4, 20)).await;
+        assert!(matches!(timed_out, Err(LimitError::QueueTimeout { .. })));
+
+        let stats = queue.stats();
+        assert_eq!((stats.depth, stats.in_flight, stats.timed_out_total), (0, 1, 1));
+        assert_eq!(stats.desired_replicas, 1);
+    }
+
+    #[tokio::test]
+    async fn added_capacity_admits_waiters() {
+        let queue = Arc::new(AdmissionQueue::default());
+        let waiting_queue = Arc::clone(&queue);
+        let waiting = tokio::spawn(async move {
+            waiting_queue.acquire("example", limits(0, 4, 1000)).await.map(|_| ())
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(queue.stats().desired_replicas, 1);
+
+        queue.configure(limits(2, 4, 1000));
+        waiting.await.expect("task").expect("waiter should be admitted");
+    }
+}
@@ -0,0 +1,389 @@
+use std::{fmt::Display, sync::Arc};
+
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, Method, header::AUTHORIZATION},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AppState,
+    errors::{auth_error::AuthError, serialize_err},
+    jwt::VerifiedClaims,
+    limits::InvokeLimits,
+    workflows::definition::WorkflowDefinition,
+};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+const ADMIN_KEY_ENV: &str = "SERVERLESS_ADMIN_KEY";
+const BOOTSTRAP_KEY_ID: &str = "bootstrap-admin";
+
+/// What an API key is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scope {
+    /// Everything, including key management.
+    Admin,
+    /// Deploy, reconfigure and stop functions.
+    Deploy,
+    /// Invoke one function, or every function with `*`.
+    Invoke(String),
+}
+
+impl Scope {
+    pub fn parse(value: &str) -> Result<Self, AuthError> {
+        match value.trim() {
+            "admin" => Ok(Self::Admin),
+            "deploy" => Ok(Self::Deploy),
+            scope => match scope.strip_prefix("invoke:") {
+                Some(function) if !function.is_empty() => Ok(Self::Invoke(function.to_string())),
+                _ => Err(AuthError::InvalidScope(value.to_string())),
+            },
+        }
+    }
+
+    pub fn allows(&self, required: &Scope) -> bool {
+        match (self, required) {
+            (Self::Admin, _) => true,
+            (Self::Deploy, Self::Deploy) => true,
+            (Self::Invoke(granted), Self::Invoke(function)) => granted == "*" || granted == function,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Admin => write!(f, "admin"),
+            Self::Deploy => write!(f, "deploy"),
+            Self::Invoke(function) => write!(f, "invoke:{function}"),
+        }
+    }
+}
+
+/// Access a route requires.
+#[derive(Debug, PartialEq, Eq)]
+enum Access {
+    /// Any valid key, used for read-only endpoints.
+    Authenticated,
+    /// Nested calls and state access from replicas, authenticated by their
+    /// invoke token in the handler.
+    Internal,
+    Scope(Scope),
+    /// Starting a workflow execution, which invokes the workflow's functions.
+    Workflow(String),
+}
+
+/// Route parameters reach handlers percent-decoded, so names taken from the
+/// path have to be decoded the same way before they are authorized.
+fn decode_segment(segment: &str) -> String {
+    percent_decode_str(segment).decode_utf8_lossy().into_owned()
+}
+
+fn required_access(method: &Method, path: &str) -> Access {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["invoke", function, ..] => Access::Scope(Scope::Invoke(decode_segment(function))),
+        ["internal", ..] => Access::Internal,
+        ["admin", ..] => Access::Scope(Scope::Admin),
+        ["workflows", workflow, "executions"] if method == Method::POST => {
+            Access::Workflow(decode_segment(workflow))
+        }
+        _ if method == Method::GET => Access::Authenticated,
+        _ => Access::Scope(Scope::Deploy),
+    }
+}
+
+/// Stored form of an API key; the key itself is only known by its hash.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    #[serde(rename = "createdAtUnixMs")]
+    pub created_at_unix_ms: u128,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<InvokeLimits>,
+}
+
+/// The caller resolved from `X-Api-Key`, attached to the request extensions.
+#[derive(Debug, Clone)]
+pub struct ApiKeyPrincipal {
+    pub key_id: String,
+    pub scopes: Vec<Scope>,
+    pub limits: Option<InvokeLimits>,
+}
+
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Returns a new `(id, key)` pair. The key is shown to the caller once.
+pub fn generate_key() -> (String, String) {
+    let secret: [u8; 32] = rand::random();
+    let key = secret.iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+    (uuid::Uuid::now_v7().simple().to_string(), format!("sk_{key}"))
+}
+
+/// Authentication is enforced once a bootstrap admin key is configured via
+/// `SERVERLESS_ADMIN_KEY`; without it every route stays open.
+#[derive(Debug, Default)]
+pub struct AuthConfig {
+    admin_key_hash: Option<String>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let admin_key_hash = std::env::var(ADMIN_KEY_ENV)
+            .ok()
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| hash_key(key.trim()));
+        if admin_key_hash.is_none() {
+            warn!("{ADMIN_KEY_ENV} is not set, API authentication is disabled");
+        }
+        Self { admin_key_hash }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.admin_key_hash.is_some()
+    }
+}
+
+fn lookup_key(state: &AppState, key: &str) -> Result<ApiKeyPrincipal> {
+    let key_hash = hash_key(key);
+    if state.auth.admin_key_hash.as_deref() == Some(key_hash.as_str()) {
+        return Ok(ApiKeyPrincipal {
+            key_id: BOOTSTRAP_KEY_ID.to_string(),
+            scopes: vec![Scope::Admin],
+            limits: None,
+        });
+    }
+
+    let record = state
+        .redis_manager
+        .get_api_key(&key_hash)?
+        .ok_or(AuthError::InvalidKey)?;
+    let record: ApiKeyRecord = serde_json::from_str(&record)?;
+    Ok(ApiKeyPrincipal {
+        key_id: record.id,
+        scopes: record
+            .scopes
+            .iter()
+            .filter_map(|scope| Scope::parse(scope).ok())
+            .collect(),
+        limits: record.limits,
+    })
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer ").or(value.strip_prefix("bearer ")))
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// Who made the request: an API key, a verified bearer token, or neither
+/// for open routes.
+#[derive(Debug, Default)]
+struct Authorization {
+    principal: Option<ApiKeyPrincipal>,
+    claims: Option<VerifiedClaims>,
+}
+
+/// Scopes needed to start a workflow execution: invoke access to every
+/// function its steps call. A definition that does not load is reported by
+/// the handler, so it only needs a valid key here.
+async fn workflow_scopes(workflow_name: &str) -> Vec<Scope> {
+    match WorkflowDefinition::load(workflow_name).await {
+        Ok(definition) => definition
+            .functions()
+            .into_iter()
+            .map(|function| Scope::Invoke(function.to_string()))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Functions with a `jwt` config require a valid bearer token on invoke,
+/// whether or not API keys are enabled; an API key with the invoke scope is
+/// accepted instead for backend callers. A token is issued for one function,
+/// so workflows calling such functions need an API key.
+async fn authorize(
+    state: &AppState,
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+) -> Result<Authorization> {
+    let access = required_access(method, path);
+    let direct_invoke = matches!(access, Access::Scope(Scope::Invoke(_)));
+    let required = match access {
+        Access::Internal => return Ok(Authorization::default()),
+        Access::Authenticated => Vec::new(),
+        Access::Scope(scope) => vec![scope],
+        Access::Workflow(workflow_name) => workflow_scopes(&workflow_name).await,
+    };
+    let invoked_functions = required
+        .iter()
+        .filter_map(|scope| match scope {
+            Scope::Invoke(function) => Some(function.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut jwt_configs = Vec::new();
+    for function in &invoked_functions {
+        if let Some(jwt) = state.function_manager.jwt_config(function).await {
+            jwt_configs.push((*function, jwt));
+        }
+    }
+
+    if direct_invoke
+        && let [(function, jwt)] = jwt_configs.as_slice()
+        && let Some(token) = bearer_token(headers)
+    {
+        let claims = state.jwt_verifier.verify(function, jwt, token).await?;
+        return Ok(Authorization {
+            principal: None,
+            claims: Some(claims),
+        });
+    }
+    if jwt_configs.is_empty() && !state.auth.enabled() {
+        return Ok(Authorization::default());
+    }
+
+    let key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|key| !key.is_empty());
+
+    let Some(key) = key else {
+        if !jwt_configs.is_empty() {
+            return Err(AuthError::MissingCredentials.into());
+        }
+        let mut anonymous = !invoked_functions.is_empty();
+        for function in &invoked_functions {
+            anonymous = anonymous && state.function_manager.allows_anonymous_invoke(function).await;
+        }
+        if anonymous {
+            return Ok(Authorization::default());
+        }
+        return Err(AuthError::Unauthorized.into());
+    };
+
+    let principal = lookup_key(state, key)?;
+    if let Some(missing) = required
+        .iter()
+        .find(|required| !principal.scopes.iter().any(|scope| scope.allows(required)))
+    {
+        return Err(AuthError::Forbidden(missing.to_string()).into());
+    }
+    Ok(Authorization {
+        principal: Some(principal),
+        claims: None,
+    })
+}
+
+/// Middleware enforcing API key scopes and per-function bearer tokens.
+pub async fn require_api_key(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match authorize(&state, request.method(), request.uri().path(), request.headers()).await {
+        Ok(authorization) => {
+            if let Some(principal) = authorization.principal {
+                request.extensions_mut().insert(principal);
+            }
+            if let Some(claims) = authorization.claims {
+                request.extensions_mut().insert(claims);
+            }
+            next.run(request).await
+        }
+        Err(error) => serialize_err(error).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Method;
+
+    use super::{Access, Scope, hash_key, required_access};
+
+    #[test]
+    fn scopes_grant_only_what_they_name() {
+        let invoke_one = Scope::parse("invoke:example").expect("scope should parse");
+        let invoke_all = Scope::parse("invoke:*").expect("scope should parse");
+        let example = Scope::Invoke("example".to_string());
+        let other = Scope::Invoke("other".to_string());
+
+        assert!(invoke_one.allows(&example));
+        assert!(!invoke_one.allows(&other));
+        assert!(invoke_all.allows(&other));
+        assert!(!invoke_all.allows(&Scope::Deploy));
+        assert!(!Scope::Deploy.allows(&Scope::Admin));
+        assert!(Scope::Admin.allows(&Scope::Deploy));
+        assert!(Scope::parse("invoke:").is_err());
+        assert!(Scope::parse("root").is_err());
+    }
+
+    #[test]
+    fn routes_map_to_required_scopes() {
+        assert_eq!(
+            required_access(&Method::POST, "/invoke/example"),
+            Access::Scope(Scope::Invoke("example".to_string()))
+        );
+        assert_eq!(
+            required_access(&Method::POST, "/invoke/ex%61mple"),
+            Access::Scope(Scope::Invoke("example".to_string()))
+        );
+        assert_eq!(
+            required_access(&Method::POST, "/%69nternal/invoke/example"),
+            Access::Scope(Scope::Deploy)
+        );
+        assert_eq!(
+            required_access(&Method::POST, "/deploy/example"),
+            Access::Scope(Scope::Deploy)
+        );
+        assert_eq!(
+            required_access(&Method::PATCH, "/functions/example"),
+            Access::Scope(Scope::Deploy)
+        );
+        assert_eq!(required_access(&Method::GET, "/functions"), Access::Authenticated);
+        assert_eq!(
+            required_access(&Method::POST, "/internal/invoke/example"),
+            Access::Internal
+        );
+        assert_eq!(
+            required_access(&Method::DELETE, "/internal/state/counter"),
+            Access::Internal
+        );
+        assert_eq!(
+            required_access(&Method::POST, "/workflows/pipeline/executions"),
+            Access::Workflow("pipeline".to_string())
+        );
+        assert_eq!(
+            required_access(&Method::GET, "/workflows/pipeline/executions"),
+            Access::Authenticated
+        );
+        assert_eq!(
+            required_access(&Method::GET, "/admin/keys"),
+            Access::Scope(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn keys_are_stored_as_sha256_hex() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
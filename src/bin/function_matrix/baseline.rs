@@ -0,0 +1,256 @@
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{net::TcpStream, process::Command, time::sleep};
+
+const BASELINE_LABEL: &str = "serverless.baseline=true";
+const READINESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Percentiles reported for the platform overhead, as named in bench reports.
+const PERCENTILES: &[&str] = &["p50", "p95", "p99", "p99_9"];
+
+/// A long-running instance of the function image started with plain
+/// `docker run`, outside the platform and its control plane.
+#[derive(Debug)]
+pub struct DirectInstance {
+    container_id: String,
+    pub image: String,
+    pub inner_port: u16,
+    pub host_port: u16,
+}
+
+impl DirectInstance {
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/", self.host_port)
+    }
+
+    /// Resolves image and port from the deployed function (unless overridden)
+    /// and starts one container with the inner port published on loopback.
+    pub async fn start(
+        client: &Client,
+        base_url: &str,
+        function_name: &str,
+        image: Option<String>,
+        inner_port: Option<u16>,
+    ) -> Result<Self> {
+        let (image, inner_port) = match (image, inner_port) {
+            (Some(image), Some(inner_port)) => (image, inner_port),
+            (image, inner_port) => {
+                let (deployed_image, deployed_port) =
+                    deployed_image(client, base_url, function_name).await?;
+                (
+                    image.unwrap_or(deployed_image),
+                    inner_port.unwrap_or(deployed_port),
+                )
+            }
+        };
+
+        let output = Command::new("docker")
+            .args(["run", "-d", "--rm", "--label", BASELINE_LABEL, "-p"])
+            .arg(format!("127.0.0.1::{inner_port}"))
+            .arg("-e")
+            .arg(format!("PORT={inner_port}"))
+            .arg(&image)
+            .output()
+            .await
+            .context("failed to run docker")?;
+        if !output.status.success() {
+            bail!(
+                "docker run {image} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let container_id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        let mut instance = Self {
+            container_id,
+            image,
+            inner_port,
+            host_port: 0,
+        };
+        match published_port(&instance.container_id, inner_port).await {
+            Ok(host_port) => instance.host_port = host_port,
+            Err(e) => {
+                instance.stop().await;
+                return Err(e);
+            }
+        }
+        if let Err(e) = wait_until_ready(instance.host_port).await {
+            instance.stop().await;
+            return Err(e);
+        }
+        println!(
+            "Direct baseline instance {} of {} listening on {}",
+            &instance.container_id[..instance.container_id.len().min(12)],
+            instance.image,
+            instance.url()
+        );
+        Ok(instance)
+    }
+
+    pub async fn stop(&self) {
+        let _ = Command::new("docker")
+            .args(["rm", "-f", &self.container_id])
+            .output()
+            .await;
+    }
+}
+
+async fn deployed_image(client: &Client, base_url: &str, function_name: &str) -> Result<(String, u16)> {
+    let functions = client
+        .get(format!("{base_url}/functions"))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+    let config = functions
+        .pointer(&format!("/functions/{function_name}/config"))
+        .ok_or_else(|| anyhow!("function {function_name} is not deployed"))?;
+    let version = config
+        .get("version")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("function {function_name} has no version"))?;
+    let inner_port = config
+        .get("innerPort")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("function {function_name} has no innerPort"))?;
+    Ok((format!("{function_name}:{version}"), inner_port as u16))
+}
+
+async fn published_port(container_id: &str, inner_port: u16) -> Result<u16> {
+    let output = Command::new("docker")
+        .args(["port", container_id, &format!("{inner_port}/tcp")])
+        .output()
+        .await?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.trim().rsplit(':').next()?.parse::<u16>().ok())
+        .next()
+        .ok_or_else(|| anyhow!("no published port for {container_id}:{inner_port}"))
+}
+
+async fn wait_until_ready(host_port: u16) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + READINESS_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if TcpStream::connect(("127.0.0.1", host_port)).await.is_ok() {
+            return Ok(());
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    bail!("direct instance on port {host_port} did not become ready")
+}
+
+#[derive(Debug, Serialize)]
+pub struct PercentileOverhead {
+    direct_ms: f64,
+    platform_ms: f64,
+    overhead_ms: f64,
+    overhead_percent: Option<f64>,
+    /// Client to platform hop, HTTP routing and response wrapping:
+    /// `platform - upstream - balancer`.
+    routing_ms: Option<f64>,
+    /// Balancer lookup and replica selection, from `Server-Timing`.
+    balancer_ms: Option<f64>,
+    /// Extra cost of the platform to replica hop: `upstream - direct`.
+    proxy_hop_ms: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OverheadReport {
+    direct_report_path: String,
+    percentiles: BTreeMap<String, PercentileOverhead>,
+}
+
+fn latency(report: &Value, percentile: &str) -> Option<f64> {
+    report.pointer(&format!("/latency_ms/{percentile}"))?.as_f64()
+}
+
+fn server_timing(report: &Value, name: &str, percentile: &str) -> Option<f64> {
+    report
+        .pointer(&format!("/server_timing_ms/{name}/{percentile}"))?
+        .as_f64()
+}
+
+/// Per-percentile platform overhead of one matrix run against the direct
+/// baseline of the same bench pattern. Percentiles do not add up in general,
+/// so the components are split so that they sum to `overhead_ms` exactly.
+pub fn overhead_report(platform: &Value, direct: &Value, direct_report_path: String) -> OverheadReport {
+    let percentiles = PERCENTILES
+        .iter()
+        .filter_map(|&percentile| {
+            let direct_ms = latency(direct, percentile)?;
+            let platform_ms = latency(platform, percentile)?;
+            let balancer_ms = server_timing(platform, "balancer", percentile);
+            let upstream_ms = server_timing(platform, "upstream", percentile);
+            let overhead_ms = platform_ms - direct_ms;
+            Some((
+                percentile.to_string(),
+                PercentileOverhead {
+                    direct_ms,
+                    platform_ms,
+                    overhead_ms,
+                    overhead_percent: (direct_ms > f64::EPSILON)
+                        .then(|| overhead_ms / direct_ms * 100.0),
+                    routing_ms: upstream_ms
+                        .zip(balancer_ms)
+                        .map(|(upstream, balancer)| platform_ms - upstream - balancer),
+                    balancer_ms,
+                    proxy_hop_ms: upstream_ms.map(|upstream| upstream - direct_ms),
+                },
+            ))
+        })
+        .collect();
+
+    OverheadReport {
+        direct_report_path,
+        percentiles,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColdStartPenalty {
+    iterations: u64,
+    report_path: String,
+    /// Warm p50 of the direct instance the penalty is measured against.
+    direct_p50_ms: f64,
+    time_to_first_response_ms: BTreeMap<String, f64>,
+    penalty_ms: BTreeMap<String, f64>,
+}
+
+/// Cold-start penalty: time to first response after scaling from zero,
+/// minus the warm p50 of the direct instance.
+pub fn cold_start_penalty(
+    cold_start: &Value,
+    direct: &Value,
+    iterations: u64,
+    report_path: String,
+) -> Option<ColdStartPenalty> {
+    let direct_p50_ms = latency(direct, "p50")?;
+    let time_to_first_response_ms: BTreeMap<String, f64> = ["p50", "p95", "p99"]
+        .iter()
+        .filter_map(|&percentile| Some((percentile.to_string(), latency(cold_start, percentile)?)))
+        .collect();
+    let penalty_ms = time_to_first_response_ms
+        .iter()
+        .map(|(percentile, ms)| (percentile.clone(), ms - direct_p50_ms))
+        .collect();
+    Some(ColdStartPenalty {
+        iterations,
+        report_path,
+        direct_p50_ms,
+        time_to_first_response_ms,
+        penalty_ms,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct BaselineSummary {
+    pub image: String,
+    pub inner_port: u16,
+    pub direct_url: String,
+    pub direct_report_paths: BTreeMap<String, String>,
+    pub cold_start_penalty: Option<ColdStartPenalty>,
+}
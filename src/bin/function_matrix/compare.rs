@@ -0,0 +1,540 @@
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+/// Two-sided 95% critical value of the standard normal distribution.
+const Z_95: f64 = 1.96;
+
+const CHART_COLORS: &[&str] = &["#4c78a8", "#f58518", "#54a24b", "#e45756", "#72b7b2", "#b279a2"];
+
+#[derive(Debug, Args)]
+pub struct CompareArgs {
+    /// Matrix summary files; the first one is the baseline.
+    #[arg(required = true, num_args = 2..)]
+    summaries: Vec<PathBuf>,
+
+    /// Relative change that counts as a regression for throughput, latency and fairness.
+    #[arg(long, default_value_t = 5.0)]
+    threshold_percent: f64,
+
+    /// Error rate increase (percentage points) that counts as a regression.
+    #[arg(long, default_value_t = 0.5)]
+    error_rate_threshold: f64,
+
+    #[arg(long)]
+    markdown: Option<PathBuf>,
+
+    #[arg(long)]
+    csv: Option<PathBuf>,
+
+    #[arg(long)]
+    html: Option<PathBuf>,
+
+    /// Exit with a non-zero status when any regression is flagged.
+    #[arg(long, default_value_t = false)]
+    fail_on_regression: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct SummaryFile {
+    target_function: String,
+    runs: Vec<SummaryRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SummaryRun {
+    replicas: u16,
+    load_balancer: String,
+    bench_pattern: String,
+    benchmark_report: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RunKey {
+    replicas: u16,
+    load_balancer: String,
+    bench_pattern: String,
+}
+
+impl std::fmt::Display for RunKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.replicas, self.load_balancer, self.bench_pattern)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct RunMetrics {
+    throughput_rps: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    error_rate_percent: f64,
+    total_requests: u64,
+    failed_requests: u64,
+    jain_fairness_index: f64,
+}
+
+impl RunMetrics {
+    fn from_report(report: &Value) -> Self {
+        let number = |pointer: &str| report.pointer(pointer).and_then(Value::as_f64);
+        let count = |pointer: &str| report.pointer(pointer).and_then(Value::as_u64).unwrap_or(0);
+        Self {
+            throughput_rps: number("/throughput_rps").unwrap_or(0.0),
+            p95_ms: number("/latency_ms/p95").unwrap_or(0.0),
+            p99_ms: number("/latency_ms/p99").unwrap_or(0.0),
+            error_rate_percent: number("/error_rate_percent").unwrap_or(0.0),
+            total_requests: count("/total_requests"),
+            failed_requests: count("/failed_requests") + count("/timeout_requests"),
+            jain_fairness_index: number("/load_distribution/jain_fairness_index").unwrap_or(0.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Better {
+    Higher,
+    Lower,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Metric {
+    Throughput,
+    P95,
+    P99,
+    ErrorRate,
+    JainFairness,
+}
+
+impl Metric {
+    const ALL: [Metric; 5] = [
+        Metric::Throughput,
+        Metric::P95,
+        Metric::P99,
+        Metric::ErrorRate,
+        Metric::JainFairness,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Throughput => "throughput_rps",
+            Metric::P95 => "p95_ms",
+            Metric::P99 => "p99_ms",
+            Metric::ErrorRate => "error_rate_percent",
+            Metric::JainFairness => "jain_fairness_index",
+        }
+    }
+
+    fn better(self) -> Better {
+        match self {
+            Metric::Throughput | Metric::JainFairness => Better::Higher,
+            Metric::P95 | Metric::P99 | Metric::ErrorRate => Better::Lower,
+        }
+    }
+
+    fn value(self, metrics: &RunMetrics) -> f64 {
+        match self {
+            Metric::Throughput => metrics.throughput_rps,
+            Metric::P95 => metrics.p95_ms,
+            Metric::P99 => metrics.p99_ms,
+            Metric::ErrorRate => metrics.error_rate_percent,
+            Metric::JainFairness => metrics.jain_fairness_index,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MetricDelta {
+    metric: Metric,
+    baseline: f64,
+    candidate: f64,
+    delta_percent: Option<f64>,
+    /// `None` when the reports carry no data for a significance test.
+    significant: Option<bool>,
+    regression: bool,
+}
+
+#[derive(Debug)]
+struct RunComparison {
+    candidate: String,
+    key: RunKey,
+    deltas: Vec<MetricDelta>,
+}
+
+#[derive(Debug)]
+struct LoadedSummary {
+    label: String,
+    target_function: String,
+    runs: BTreeMap<RunKey, RunMetrics>,
+}
+
+#[derive(Debug)]
+struct Comparison {
+    summaries: Vec<LoadedSummary>,
+    runs: Vec<RunComparison>,
+    unmatched: Vec<(String, RunKey)>,
+}
+
+impl Comparison {
+    fn regressions(&self) -> usize {
+        self.runs
+            .iter()
+            .flat_map(|run| &run.deltas)
+            .filter(|delta| delta.regression)
+            .count()
+    }
+}
+
+async fn load_summary(path: &Path) -> Result<LoadedSummary> {
+    let text = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read summary {}", path.display()))?;
+    let summary: SummaryFile = serde_json::from_str(&text)
+        .with_context(|| format!("failed to parse summary {}", path.display()))?;
+    let runs = summary
+        .runs
+        .into_iter()
+        .map(|run| {
+            (
+                RunKey {
+                    replicas: run.replicas,
+                    load_balancer: run.load_balancer,
+                    bench_pattern: run.bench_pattern,
+                },
+                RunMetrics::from_report(&run.benchmark_report),
+            )
+        })
+        .collect();
+    let label = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string());
+    Ok(LoadedSummary {
+        label,
+        target_function: summary.target_function,
+        runs,
+    })
+}
+
+/// Two-proportion z-test on failed vs total requests.
+fn error_rate_significant(baseline: &RunMetrics, candidate: &RunMetrics) -> Option<bool> {
+    let (n1, n2) = (baseline.total_requests as f64, candidate.total_requests as f64);
+    if n1 == 0.0 || n2 == 0.0 {
+        return None;
+    }
+    let (p1, p2) = (baseline.failed_requests as f64 / n1, candidate.failed_requests as f64 / n2);
+    let pooled = (baseline.failed_requests + candidate.failed_requests) as f64 / (n1 + n2);
+    let standard_error = (pooled * (1.0 - pooled) * (1.0 / n1 + 1.0 / n2)).sqrt();
+    if standard_error <= f64::EPSILON {
+        return Some(false);
+    }
+    Some(((p2 - p1) / standard_error).abs() > Z_95)
+}
+
+fn compare_metric(
+    metric: Metric,
+    baseline: &RunMetrics,
+    candidate: &RunMetrics,
+    args: &CompareArgs,
+) -> MetricDelta {
+    let (before, after) = (metric.value(baseline), metric.value(candidate));
+    let delta_percent = (before.abs() > f64::EPSILON).then(|| (after - before) / before * 100.0);
+    let worse_by_percent = match (metric.better(), delta_percent) {
+        (Better::Higher, Some(delta)) => -delta,
+        (Better::Lower, Some(delta)) => delta,
+        (_, None) => 0.0,
+    };
+
+    // A shift of the mean says nothing about the tails, and the reports
+    // carry no samples to test percentiles on, so p95/p99 go by the threshold.
+    let (significant, beyond_threshold) = match metric {
+        Metric::ErrorRate => (
+            error_rate_significant(baseline, candidate),
+            after - before > args.error_rate_threshold,
+        ),
+        Metric::Throughput | Metric::P95 | Metric::P99 | Metric::JainFairness => {
+            (None, worse_by_percent > args.threshold_percent)
+        }
+    };
+
+    MetricDelta {
+        metric,
+        baseline: before,
+        candidate: after,
+        delta_percent,
+        significant,
+        regression: beyond_threshold && significant.unwrap_or(true),
+    }
+}
+
+fn compare(summaries: Vec<LoadedSummary>, args: &CompareArgs) -> Comparison {
+    let mut runs = Vec::new();
+    let mut unmatched = Vec::new();
+    let (baseline, candidates) = summaries.split_first().expect("at least two summaries");
+
+    for candidate in candidates {
+        for (key, candidate_metrics) in &candidate.runs {
+            let Some(baseline_metrics) = baseline.runs.get(key) else {
+                unmatched.push((candidate.label.clone(), key.clone()));
+                continue;
+            };
+            runs.push(RunComparison {
+                candidate: candidate.label.clone(),
+                key: key.clone(),
+                deltas: Metric::ALL
+                    .iter()
+                    .map(|&metric| compare_metric(metric, baseline_metrics, candidate_metrics, args))
+                    .collect(),
+            });
+        }
+        for key in baseline.runs.keys().filter(|key| !candidate.runs.contains_key(*key)) {
+            unmatched.push((baseline.label.clone(), key.clone()));
+        }
+    }
+
+    Comparison {
+        summaries,
+        runs,
+        unmatched,
+    }
+}
+
+fn format_delta(delta: &MetricDelta) -> String {
+    delta
+        .delta_percent
+        .map(|value| format!("{value:+.2}%"))
+        .unwrap_or_else(|| "n/a".to_string())
+}
+
+fn format_significance(delta: &MetricDelta) -> &'static str {
+    match delta.significant {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "-",
+    }
+}
+
+fn render_markdown(comparison: &Comparison) -> String {
+    let mut out = String::new();
+    let baseline = &comparison.summaries[0];
+    let _ = writeln!(out, "# Matrix comparison: {}\n", baseline.target_function);
+    let _ = writeln!(out, "Baseline: `{}`\n", baseline.label);
+
+    for candidate in &comparison.summaries[1..] {
+        let _ = writeln!(out, "## {} vs {}\n", candidate.label, baseline.label);
+        let _ = writeln!(
+            out,
+            "| replicas | balancer | pattern | metric | baseline | candidate | delta | significant | regression |"
+        );
+        let _ = writeln!(out, "|---|---|---|---|---|---|---|---|---|");
+        for run in comparison.runs.iter().filter(|run| run.candidate == candidate.label) {
+            for delta in &run.deltas {
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {:.3} | {:.3} | {} | {} | {} |",
+                    run.key.replicas,
+                    run.key.load_balancer,
+                    run.key.bench_pattern,
+                    delta.metric.name(),
+                    delta.baseline,
+                    delta.candidate,
+                    format_delta(delta),
+                    format_significance(delta),
+                    if delta.regression { "**REGRESSION**" } else { "" }
+                );
+            }
+        }
+        out.push('\n');
+    }
+
+    if !comparison.unmatched.is_empty() {
+        let _ = writeln!(out, "Runs without a counterpart:\n");
+        for (label, key) in &comparison.unmatched {
+            let _ = writeln!(out, "- `{label}`: {key}");
+        }
+        out.push('\n');
+    }
+
+    let _ = writeln!(out, "Regressions: {}", comparison.regressions());
+    out
+}
+
+fn render_csv(comparison: &Comparison) -> String {
+    let mut out = String::from(
+        "candidate,replicas,load_balancer,bench_pattern,metric,baseline,candidate_value,delta_percent,significant,regression\n",
+    );
+    for run in &comparison.runs {
+        for delta in &run.deltas {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{},{}",
+                run.candidate,
+                run.key.replicas,
+                run.key.load_balancer,
+                run.key.bench_pattern,
+                delta.metric.name(),
+                delta.baseline,
+                delta.candidate,
+                delta.delta_percent.map(|value| value.to_string()).unwrap_or_default(),
+                delta.significant.map(|value| value.to_string()).unwrap_or_default(),
+                delta.regression
+            );
+        }
+    }
+    out
+}
+
+fn html_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Horizontal grouped bar chart: one group per aligned run, one bar per summary.
+fn render_svg_chart(comparison: &Comparison, metric: Metric) -> String {
+    const LABEL_WIDTH: f64 = 260.0;
+    const CHART_WIDTH: f64 = 520.0;
+    const BAR_HEIGHT: f64 = 12.0;
+    const GROUP_GAP: f64 = 10.0;
+
+    let keys: BTreeSet<&RunKey> = comparison.runs.iter().map(|run| &run.key).collect();
+    let summaries = &comparison.summaries;
+    let max_value = keys
+        .iter()
+        .flat_map(|key| summaries.iter().filter_map(|summary| summary.runs.get(*key)))
+        .map(|metrics| metric.value(metrics))
+        .fold(0.0_f64, f64::max)
+        .max(f64::EPSILON);
+
+    let group_height = BAR_HEIGHT * summaries.len() as f64 + GROUP_GAP;
+    let height = group_height * keys.len() as f64 + 30.0;
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{height}" font-family="sans-serif" font-size="11">"#,
+        LABEL_WIDTH + CHART_WIDTH + 80.0
+    );
+
+    for (group, key) in keys.iter().enumerate() {
+        let top = group as f64 * group_height;
+        let _ = write!(
+            svg,
+            r#"<text x="0" y="{}">{}</text>"#,
+            top + BAR_HEIGHT,
+            html_escape(&key.to_string())
+        );
+        for (index, summary) in summaries.iter().enumerate() {
+            let Some(metrics) = summary.runs.get(*key) else {
+                continue;
+            };
+            let value = metric.value(metrics);
+            let y = top + index as f64 * BAR_HEIGHT;
+            let width = value / max_value * CHART_WIDTH;
+            let _ = write!(
+                svg,
+                r#"<rect x="{LABEL_WIDTH}" y="{y}" width="{width:.1}" height="{}" fill="{}"><title>{}: {value:.3}</title></rect><text x="{:.1}" y="{}">{value:.2}</text>"#,
+                BAR_HEIGHT - 2.0,
+                CHART_COLORS[index % CHART_COLORS.len()],
+                html_escape(&summary.label),
+                LABEL_WIDTH + width + 4.0,
+                y + BAR_HEIGHT - 3.0
+            );
+        }
+    }
+
+    for (index, summary) in summaries.iter().enumerate() {
+        let x = LABEL_WIDTH + index as f64 * 140.0;
+        let _ = write!(
+            svg,
+            r#"<rect x="{x}" y="{}" width="10" height="10" fill="{}"/><text x="{}" y="{}">{}</text>"#,
+            height - 18.0,
+            CHART_COLORS[index % CHART_COLORS.len()],
+            x + 14.0,
+            height - 9.0,
+            html_escape(&summary.label)
+        );
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+fn render_html(comparison: &Comparison) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Matrix comparison</title><style>\
+         body{font-family:sans-serif;margin:24px}table{border-collapse:collapse;font-size:12px}\
+         td,th{border:1px solid #ccc;padding:3px 6px;text-align:right}td:nth-child(-n+5){text-align:left}\
+         tr.regression{background:#fdd}</style></head><body>",
+    );
+    let baseline = &comparison.summaries[0];
+    let _ = write!(
+        out,
+        "<h1>Matrix comparison: {}</h1><p>Baseline: <code>{}</code>. Regressions: <b>{}</b></p>",
+        html_escape(&baseline.target_function),
+        html_escape(&baseline.label),
+        comparison.regressions()
+    );
+
+    for metric in Metric::ALL {
+        let _ = write!(out, "<h2>{}</h2>{}", metric.name(), render_svg_chart(comparison, metric));
+    }
+
+    out.push_str(
+        "<h2>Deltas</h2><table><tr><th>candidate</th><th>replicas</th><th>balancer</th><th>pattern</th>\
+         <th>metric</th><th>baseline</th><th>candidate</th><th>delta</th><th>significant</th></tr>",
+    );
+    for run in &comparison.runs {
+        for delta in &run.deltas {
+            let _ = write!(
+                out,
+                "<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td><td>{}</td><td>{}</td></tr>",
+                if delta.regression { " class=\"regression\"" } else { "" },
+                html_escape(&run.candidate),
+                run.key.replicas,
+                html_escape(&run.key.load_balancer),
+                html_escape(&run.key.bench_pattern),
+                delta.metric.name(),
+                delta.baseline,
+                delta.candidate,
+                format_delta(delta),
+                format_significance(delta)
+            );
+        }
+    }
+    out.push_str("</table></body></html>\n");
+    out
+}
+
+pub async fn run(args: CompareArgs) -> Result<()> {
+    let mut summaries = Vec::with_capacity(args.summaries.len());
+    for path in &args.summaries {
+        summaries.push(load_summary(path).await?);
+    }
+
+    let comparison = compare(summaries, &args);
+    let markdown = render_markdown(&comparison);
+    println!("{markdown}");
+
+    if let Some(path) = &args.markdown {
+        fs::write(path, &markdown).await?;
+        println!("Markdown report saved to {}", path.display());
+    }
+    if let Some(path) = &args.csv {
+        fs::write(path, render_csv(&comparison)).await?;
+        println!("CSV report saved to {}", path.display());
+    }
+    if let Some(path) = &args.html {
+        fs::write(path, render_html(&comparison)).await?;
+        println!("HTML report saved to {}", path.display());
+    }
+
+    let regressions = comparison.regressions();
+    if args.fail_on_regression && regressions > 0 {
+        bail!("{regressions} significant regression(s) detected");
+    }
+    Ok(())
+}
@@ -1,9 +1,12 @@
-use anyhow::{bail, Result};
-use clap::Parser;
+mod baseline;
+mod compare;
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{path::PathBuf, time::Duration};
+use std::{collections::BTreeMap, path::PathBuf, time::Duration};
 use tokio::{fs, process::Command, time::sleep};
 
 #[derive(Clone, Debug)]
@@ -28,12 +31,23 @@ struct CliConfig {
     bench_low_rps: f64,
     bench_high_rps: f64,
     bench_pulse_period_secs: u64,
+    baseline_direct: bool,
+    baseline_image: Option<String>,
+    baseline_inner_port: Option<u16>,
+    cold_start_iterations: u64,
+    api_key: Option<String>,
 }
 
+/// Passed to `invoke_bench` through the environment rather than its arguments.
+const API_KEY_ENV: &str = "SERVERLESS_API_KEY";
+
 #[derive(Debug, Parser)]
 #[command(name = "function_matrix")]
 #[command(about = "Run matrix benchmark scenarios against deployed serverless function")]
 struct CliArgs {
+    #[command(subcommand)]
+    command: Option<MatrixCommand>,
+
     #[arg(long, default_value = "http://localhost:5000")]
     base_url: String,
 
@@ -75,6 +89,33 @@ struct CliArgs {
 
     #[arg(long, default_value_t = false)]
     all_three_cases: bool,
+
+    /// Also benchmark a directly addressed instance of the function image
+    /// and report platform overhead per percentile.
+    #[arg(long, default_value_t = false)]
+    baseline_direct: bool,
+
+    /// Image for the direct instance, defaults to the deployed `name:version`.
+    #[arg(long)]
+    baseline_image: Option<String>,
+
+    /// Port the image listens on, defaults to the deployed `innerPort`.
+    #[arg(long)]
+    baseline_inner_port: Option<u16>,
+
+    /// Scale-from-zero iterations used for the cold-start penalty (0 disables).
+    #[arg(long, default_value_t = 3)]
+    cold_start_iterations: u64,
+
+    /// API key sent as `X-Api-Key`, defaults to `SERVERLESS_API_KEY`.
+    #[arg(long)]
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum MatrixCommand {
+    /// Compare matrix summaries and flag regressions against the first one
+    Compare(compare::CompareArgs),
 }
 
 #[derive(Debug, Deserialize)]
@@ -109,6 +150,8 @@ struct MatrixSummary {
     bench_high_rps: f64,
     bench_pulse_period_secs: u64,
     generated_at_unix_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    traditional_baseline: Option<baseline::BaselineSummary>,
     runs: Vec<MatrixRunResult>,
 }
 
@@ -122,6 +165,8 @@ struct MatrixRunResult {
     update_status: OperationStatusReport,
     benchmark_report_path: String,
     benchmark_report: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform_overhead: Option<baseline::OverheadReport>,
 }
 
 #[derive(Debug, Serialize)]
@@ -148,9 +193,7 @@ fn parse_replica_sets(input: &str) -> Vec<u16> {
     }
 }
 
-fn parse_args() -> CliConfig {
-    let args = CliArgs::parse();
-
+fn parse_args(args: CliArgs) -> CliConfig {
     let mut bench_pattern = args.bench_pattern.to_ascii_lowercase();
     let mut bench_all_patterns = args.all_bench_patterns;
     if bench_pattern == "all" {
@@ -185,6 +228,14 @@ fn parse_args() -> CliConfig {
         bench_low_rps,
         bench_high_rps,
         bench_pulse_period_secs,
+        baseline_direct: args.baseline_direct,
+        baseline_image: args.baseline_image,
+        baseline_inner_port: args.baseline_inner_port,
+        cold_start_iterations: args.cold_start_iterations,
+        api_key: args
+            .api_key
+            .or_else(|| std::env::var(API_KEY_ENV).ok())
+            .filter(|key| !key.trim().is_empty()),
     }
 }
 
@@ -305,9 +356,9 @@ async fn apply_config(
 
 async fn run_benchmark(
     cfg: &CliConfig,
-    matrix: MatrixConfig,
     bench_pattern: &str,
     output_path: &std::path::Path,
+    extra_args: &[String],
 ) -> Result<Value> {
     let output_path_string = output_path.to_string_lossy().to_string();
     let bench_duration_secs = cfg.bench_duration_secs.to_string();
@@ -349,26 +400,111 @@ async fn run_benchmark(
             "--output",
             &output_path_string,
         ])
+        .args(extra_args)
         .stdout(std::process::Stdio::inherit())
         .stderr(std::process::Stdio::inherit());
 
+    if let Some(api_key) = &cfg.api_key {
+        command.env(API_KEY_ENV, api_key);
+    }
+
     let status = command.status().await?;
     if !status.success() {
-        bail!("benchmark failed for replicas={} balancer={}", matrix.replicas, matrix.load_balancer);
+        bail!("benchmark failed for {}", output_path.display());
     }
 
     let report_text = fs::read_to_string(output_path).await?;
     Ok(serde_json::from_str::<Value>(&report_text)?)
 }
 
+/// Benchmarks every pattern against a direct instance of the function image,
+/// then measures the scale-from-zero cold-start penalty on the platform.
+async fn run_traditional_baseline(
+    client: &Client,
+    cfg: &CliConfig,
+    bench_patterns: &[String],
+    direct_reports: &mut BTreeMap<String, (String, Value)>,
+) -> Result<baseline::BaselineSummary> {
+    let instance = baseline::DirectInstance::start(
+        client,
+        &cfg.base_url,
+        &cfg.function_name,
+        cfg.baseline_image.clone(),
+        cfg.baseline_inner_port,
+    )
+    .await?;
+
+    let baseline_dir = cfg.output_dir.join(&cfg.function_name).join("direct");
+    let mut result = fs::create_dir_all(&baseline_dir).await.map_err(anyhow::Error::from);
+    for bench_pattern in bench_patterns {
+        if result.is_err() {
+            break;
+        }
+        let output_path = baseline_dir.join(format!("bench-{bench_pattern}.json"));
+        let extra_args = ["--direct-url".to_string(), instance.url()];
+        result = run_benchmark(cfg, bench_pattern, &output_path, &extra_args)
+            .await
+            .map(|report| {
+                direct_reports.insert(
+                    bench_pattern.clone(),
+                    (output_path.to_string_lossy().to_string(), report),
+                );
+            });
+    }
+    instance.stop().await;
+    result.context("direct baseline benchmark failed")?;
+
+    let cold_start_penalty = match direct_reports.values().next() {
+        Some((_, direct_report)) if cfg.cold_start_iterations > 0 => {
+            let output_path = baseline_dir.join("cold-start.json");
+            let iterations = cfg.cold_start_iterations.to_string();
+            let extra_args = [
+                "--iterations".to_string(),
+                iterations,
+                "--cold-start-method".to_string(),
+                "scale".to_string(),
+            ];
+            let report = run_benchmark(cfg, "cold-start", &output_path, &extra_args).await?;
+            baseline::cold_start_penalty(
+                &report,
+                direct_report,
+                cfg.cold_start_iterations,
+                output_path.to_string_lossy().to_string(),
+            )
+        }
+        _ => None,
+    };
+
+    Ok(baseline::BaselineSummary {
+        image: instance.image.clone(),
+        inner_port: instance.inner_port,
+        direct_url: instance.url(),
+        direct_report_paths: direct_reports
+            .iter()
+            .map(|(pattern, (path, _))| (pattern.clone(), path.clone()))
+            .collect(),
+        cold_start_penalty,
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cfg = parse_args();
+    let mut args = CliArgs::parse();
+    if let Some(MatrixCommand::Compare(compare_args)) = args.command.take() {
+        return compare::run(compare_args).await;
+    }
+
+    let cfg = parse_args(args);
     fs::create_dir_all(&cfg.output_dir).await?;
     let bench_patterns = selected_bench_patterns(&cfg);
 
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    if let Some(api_key) = &cfg.api_key {
+        default_headers.insert("x-api-key", reqwest::header::HeaderValue::from_str(api_key.trim())?);
+    }
     let client = Client::builder()
         .timeout(Duration::from_secs(20))
+        .default_headers(default_headers)
         .build()?;
 
     let initial_deploy = ensure_deployed(&client, &cfg).await?;
@@ -376,6 +512,13 @@ async fn main() -> Result<()> {
         bail!("initial deploy was not accepted");
     }
 
+    let mut direct_reports = BTreeMap::new();
+    let traditional_baseline = if cfg.baseline_direct {
+        Some(run_traditional_baseline(&client, &cfg, &bench_patterns, &mut direct_reports).await?)
+    } else {
+        None
+    };
+
     let mut runs = Vec::new();
 
     for &replicas in &cfg.replica_sets {
@@ -403,7 +546,14 @@ async fn main() -> Result<()> {
                     .join(format!("{}-{}", matrix.replicas, matrix.load_balancer));
                 fs::create_dir_all(&case_dir).await?;
                 let bench_output_path = case_dir.join(format!("bench-{}.json", bench_pattern));
-                let bench_report = run_benchmark(&cfg, matrix.clone(), bench_pattern, &bench_output_path).await?;
+                let bench_report = run_benchmark(&cfg, bench_pattern, &bench_output_path, &[])
+                    .await
+                    .with_context(|| {
+                        format!("replicas={} balancer={}", matrix.replicas, matrix.load_balancer)
+                    })?;
+                let platform_overhead = direct_reports.get(bench_pattern).map(|(path, report)| {
+                    baseline::overhead_report(&bench_report, report, path.clone())
+                });
 
                 runs.push(MatrixRunResult {
                     replicas: matrix.replicas,
@@ -421,6 +571,7 @@ async fn main() -> Result<()> {
                     },
                     benchmark_report_path: bench_output_path.to_string_lossy().to_string(),
                     benchmark_report: bench_report,
+                    platform_overhead,
                 });
             }
         }
@@ -439,6 +590,7 @@ async fn main() -> Result<()> {
         bench_high_rps: cfg.bench_high_rps,
         bench_pulse_period_secs: cfg.bench_pulse_period_secs,
         generated_at_unix_ms: current_unix_ms(),
+        traditional_baseline,
         runs,
     };
 
@@ -1,5 +1,7 @@
+use hdrhistogram::Histogram;
+use rand::{Rng, SeedableRng, rngs::StdRng};
 use reqwest::Client;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
@@ -12,6 +14,43 @@ enum LoadPattern {
     Stable,
     Pulse,
     Stress,
+    ColdStart,
+}
+
+/// How request send times are chosen. `Closed` keeps the fixed worker pool,
+/// the open-loop modes schedule arrivals regardless of response time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Arrival {
+    Closed,
+    Constant,
+    Poisson,
+}
+
+/// `Timed` replays records at their recorded offsets, `Payload` keeps the
+/// usual load pattern and only cycles through the recorded payloads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReplayMode {
+    Timed,
+    Payload,
+}
+
+/// One line of a `--replay` file, as written by the server recorder.
+#[derive(Debug, Clone, Deserialize)]
+struct ReplayRecord {
+    #[serde(rename = "offsetMs", default)]
+    offset_ms: f64,
+    #[serde(default)]
+    function: Option<String>,
+    #[serde(default)]
+    payload: Value,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ColdStartMethod {
+    Stop,
+    Scale,
 }
 
 #[derive(Debug)]
@@ -23,10 +62,19 @@ struct BenchConfig {
     request_timeout_ms: u64,
     output_path: String,
     load_pattern: LoadPattern,
+    arrival: Arrival,
     target_rps: f64,
     low_rps: f64,
     high_rps: f64,
     pulse_period_secs: u64,
+    iterations: u64,
+    cold_start_method: ColdStartMethod,
+    replay_path: Option<String>,
+    replay_speed: f64,
+    replay_mode: ReplayMode,
+    replay_records: Vec<ReplayRecord>,
+    direct_url: Option<String>,
+    api_key: Option<String>,
 }
 
 #[derive(Debug)]
@@ -35,19 +83,81 @@ struct BenchJob {
     seq: u64,
 }
 
-#[derive(Default, Debug)]
+/// Parsed `Server-Timing` response header of the platform.
+#[derive(Debug, Default)]
+struct ServerTiming {
+    durations: Vec<(String, f64)>,
+    /// Only present when the platform retried the call to the replica.
+    attempts: Option<u32>,
+}
+
+#[derive(Debug)]
+enum RequestOutcome {
+    Success {
+        container_id: String,
+        server_timing: ServerTiming,
+    },
+    Error,
+    Timeout,
+}
+
+#[derive(Debug)]
 struct WorkerStats {
-    latencies_ms: Vec<f64>,
+    latencies_us: Histogram<u64>,
     success_count: u64,
     error_count: u64,
     timeout_count: u64,
     container_hits: HashMap<String, u64>,
+    server_timing_us: HashMap<String, Histogram<u64>>,
+    retry_attempts: HashMap<u32, u64>,
+}
+
+impl Default for WorkerStats {
+    fn default() -> Self {
+        Self {
+            latencies_us: new_histogram(),
+            success_count: 0,
+            error_count: 0,
+            timeout_count: 0,
+            container_hits: HashMap::new(),
+            server_timing_us: HashMap::new(),
+            retry_attempts: HashMap::new(),
+        }
+    }
+}
+
+impl WorkerStats {
+    fn record(&mut self, outcome: RequestOutcome, elapsed_ms: f64) {
+        match outcome {
+            RequestOutcome::Success {
+                container_id,
+                server_timing,
+            } => {
+                self.success_count += 1;
+                record_ms(&mut self.latencies_us, elapsed_ms);
+                *self.container_hits.entry(container_id).or_insert(0) += 1;
+                for (name, ms) in server_timing.durations {
+                    record_ms(
+                        self.server_timing_us.entry(name).or_insert_with(new_histogram),
+                        ms,
+                    );
+                }
+                if let Some(attempts) = server_timing.attempts {
+                    *self.retry_attempts.entry(attempts).or_insert(0) += 1;
+                }
+            }
+            RequestOutcome::Error => self.error_count += 1,
+            RequestOutcome::Timeout => self.timeout_count += 1,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct BenchReport {
     target_function: String,
     base_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direct_url: Option<String>,
     load_pattern: LoadPatternReport,
     duration_secs: u64,
     concurrency: usize,
@@ -60,25 +170,99 @@ struct BenchReport {
     timeout_rate_percent: f64,
     throughput_rps: f64,
     latency_ms: LatencyMetrics,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    server_timing_ms: HashMap<String, LatencyMetrics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_breakdown: Option<ServerBreakdown>,
     load_distribution: LoadDistribution,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cold_start: Option<ColdStartReport>,
     generated_at_unix_ms: u128,
 }
 
+/// Where server time is spent, from the `Server-Timing` headers.
+#[derive(Debug, Serialize)]
+struct ServerBreakdown {
+    /// Mean of each component as a share of the mean server total; `other`
+    /// is routing and serialization not covered by any component.
+    share_of_total_percent: HashMap<String, f64>,
+    retried_requests: u64,
+    retried_percent: f64,
+    /// Number of requests per attempt count, for retried requests only.
+    attempts: HashMap<String, u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ColdStartReport {
+    method: String,
+    iterations: u64,
+    start_modes: HashMap<String, u64>,
+    image_ms: LatencyMetrics,
+    create_ms: LatencyMetrics,
+    start_ms: LatencyMetrics,
+    readiness_ms: LatencyMetrics,
+    first_request_ms: LatencyMetrics,
+    control_plane_ms: LatencyMetrics,
+}
+
+#[derive(Debug)]
+struct ColdStartSamples {
+    time_to_first_response_us: Histogram<u64>,
+    image_us: Histogram<u64>,
+    create_us: Histogram<u64>,
+    start_us: Histogram<u64>,
+    readiness_us: Histogram<u64>,
+    first_request_us: Histogram<u64>,
+    control_plane_us: Histogram<u64>,
+    start_modes: HashMap<String, u64>,
+    failures: u64,
+    timeouts: u64,
+    container_hits: HashMap<String, u64>,
+}
+
+impl Default for ColdStartSamples {
+    fn default() -> Self {
+        Self {
+            time_to_first_response_us: new_histogram(),
+            image_us: new_histogram(),
+            create_us: new_histogram(),
+            start_us: new_histogram(),
+            readiness_us: new_histogram(),
+            first_request_us: new_histogram(),
+            control_plane_us: new_histogram(),
+            start_modes: HashMap::new(),
+            failures: 0,
+            timeouts: 0,
+            container_hits: HashMap::new(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct LoadPatternReport {
     kind: String,
+    arrival: String,
+    coordinated_omission_corrected: bool,
     target_rps: Option<f64>,
     low_rps: Option<f64>,
     high_rps: Option<f64>,
     pulse_period_secs: Option<u64>,
+    replay_file: Option<String>,
+    replay_mode: Option<String>,
+    replay_speed: Option<f64>,
 }
 
 #[derive(Debug, Serialize)]
 struct LatencyMetrics {
     average: f64,
+    stddev: f64,
     p50: f64,
     p95: f64,
     p99: f64,
+    #[serde(rename = "p99_9")]
+    p99_9: f64,
+    #[serde(rename = "p99_99")]
+    p99_99: f64,
     max: f64,
     min: f64,
 }
@@ -92,7 +276,24 @@ struct LoadDistribution {
     jain_fairness_index: f64,
 }
 
-fn parse_args() -> BenchConfig {
+/// API key sent as `X-Api-Key` when `--api-key` is not given.
+const API_KEY_ENV: &str = "SERVERLESS_API_KEY";
+
+/// Looks up a flag value among its accepted spellings, so a typo fails the
+/// run instead of silently benchmarking the default.
+fn parse_choice<T: Copy>(flag: &str, value: &str, choices: &[(&str, T)]) -> anyhow::Result<T> {
+    let value = value.to_ascii_lowercase();
+    choices
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, choice)| *choice)
+        .ok_or_else(|| {
+            let accepted = choices.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ");
+            anyhow::anyhow!("unknown {flag} value '{value}', expected one of: {accepted}")
+        })
+}
+
+fn parse_args() -> anyhow::Result<BenchConfig> {
     let mut base_url = "http://localhost:5000".to_string();
     let mut function_name = "example-go".to_string();
     let mut duration_secs = 30_u64;
@@ -100,10 +301,18 @@ fn parse_args() -> BenchConfig {
     let mut request_timeout_ms = 2_500_u64;
     let mut output_path = "bench_report.json".to_string();
     let mut load_pattern = LoadPattern::Stress;
+    let mut arrival = Arrival::Closed;
     let mut target_rps = 100.0_f64;
     let mut low_rps = 50.0_f64;
     let mut high_rps = 200.0_f64;
     let mut pulse_period_secs = 5_u64;
+    let mut iterations = 10_u64;
+    let mut cold_start_method = ColdStartMethod::Stop;
+    let mut replay_path = None;
+    let mut replay_speed = 1.0_f64;
+    let mut replay_mode = ReplayMode::Timed;
+    let mut direct_url = None;
+    let mut api_key = std::env::var(API_KEY_ENV).ok();
 
     let args: Vec<String> = std::env::args().collect();
     let mut index = 1;
@@ -122,13 +331,31 @@ fn parse_args() -> BenchConfig {
             }
             "--output" => output_path = args[index + 1].clone(),
             "--pattern" => {
-                let value = args[index + 1].to_ascii_lowercase();
-                load_pattern = match value.as_str() {
-                    "stable" => LoadPattern::Stable,
-                    "pulse" | "pulsating" => LoadPattern::Pulse,
-                    "stress" => LoadPattern::Stress,
-                    _ => LoadPattern::Stress,
-                };
+                load_pattern = parse_choice(
+                    "--pattern",
+                    &args[index + 1],
+                    &[
+                        ("stable", LoadPattern::Stable),
+                        ("pulse", LoadPattern::Pulse),
+                        ("pulsating", LoadPattern::Pulse),
+                        ("stress", LoadPattern::Stress),
+                        ("cold-start", LoadPattern::ColdStart),
+                        ("cold_start", LoadPattern::ColdStart),
+                        ("coldstart", LoadPattern::ColdStart),
+                    ],
+                )?;
+            }
+            "--arrival" => {
+                arrival = parse_choice(
+                    "--arrival",
+                    &args[index + 1],
+                    &[
+                        ("closed", Arrival::Closed),
+                        ("constant", Arrival::Constant),
+                        ("uniform", Arrival::Constant),
+                        ("poisson", Arrival::Poisson),
+                    ],
+                )?;
             }
             "--target-rps" => {
                 target_rps = args[index + 1].parse().unwrap_or(target_rps);
@@ -142,12 +369,43 @@ fn parse_args() -> BenchConfig {
             "--pulse-period-secs" => {
                 pulse_period_secs = args[index + 1].parse().unwrap_or(pulse_period_secs);
             }
+            "--iterations" => {
+                iterations = args[index + 1].parse().unwrap_or(iterations);
+            }
+            "--cold-start-method" => {
+                cold_start_method = parse_choice(
+                    "--cold-start-method",
+                    &args[index + 1],
+                    &[
+                        ("stop", ColdStartMethod::Stop),
+                        ("scale", ColdStartMethod::Scale),
+                        ("scale-to-zero", ColdStartMethod::Scale),
+                    ],
+                )?;
+            }
+            "--replay" => replay_path = Some(args[index + 1].clone()),
+            "--direct-url" => direct_url = Some(args[index + 1].clone()),
+            "--api-key" => api_key = Some(args[index + 1].clone()),
+            "--replay-speed" => {
+                replay_speed = args[index + 1].parse().unwrap_or(replay_speed);
+            }
+            "--replay-mode" => {
+                replay_mode = parse_choice(
+                    "--replay-mode",
+                    &args[index + 1],
+                    &[
+                        ("timed", ReplayMode::Timed),
+                        ("payload", ReplayMode::Payload),
+                        ("payload-only", ReplayMode::Payload),
+                    ],
+                )?;
+            }
             _ => {}
         }
         index += 2;
     }
 
-    BenchConfig {
+    Ok(BenchConfig {
         base_url,
         function_name,
         duration_secs,
@@ -155,26 +413,163 @@ fn parse_args() -> BenchConfig {
         request_timeout_ms,
         output_path,
         load_pattern,
+        arrival,
         target_rps,
         low_rps,
         high_rps,
         pulse_period_secs,
+        iterations,
+        cold_start_method,
+        replay_path,
+        replay_speed,
+        replay_mode,
+        replay_records: Vec::new(),
+        direct_url,
+        api_key: api_key.filter(|key| !key.trim().is_empty()),
+    })
+}
+
+/// Where requests go: the platform's `/invoke` route, or a function instance
+/// addressed directly when benchmarking the traditional baseline.
+fn invoke_endpoint(cfg: &BenchConfig) -> String {
+    cfg.direct_url
+        .clone()
+        .unwrap_or_else(|| format!("{}/invoke/{}", cfg.base_url, cfg.function_name))
+}
+
+/// Server-Timing components that add up to the platform's own time.
+const SERVER_TIMING_COMPONENTS: &[&str] = &["queue", "balancer", "connect", "container", "retries"];
+
+/// Builds an invoke request that asks the platform for `Server-Timing`.
+fn invoke_request(client: &Client, endpoint: &str, payload: &Value) -> reqwest::RequestBuilder {
+    client
+        .post(endpoint)
+        .header("x-server-timing", "1")
+        .json(payload)
+}
+
+/// Parses `name;dur=12.5, attempts;desc="3"` entries.
+fn parse_server_timing(headers: &reqwest::header::HeaderMap) -> ServerTiming {
+    let mut timing = ServerTiming::default();
+    let entries = headers
+        .get_all("server-timing")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    for entry in entries {
+        let mut parts = entry.split(';').map(str::trim);
+        let Some(name) = parts.next().filter(|name| !name.is_empty()) else {
+            continue;
+        };
+        for param in parts {
+            if let Some(duration) = param.strip_prefix("dur=")
+                && let Ok(ms) = duration.parse()
+            {
+                timing.durations.push((name.to_string(), ms));
+            } else if name == "attempts"
+                && let Some(desc) = param.strip_prefix("desc=")
+            {
+                timing.attempts = desc.trim_matches('"').parse().ok();
+            }
+        }
+    }
+    timing
+}
+
+fn server_breakdown(stats: &WorkerStats) -> Option<ServerBreakdown> {
+    let total_mean = stats.server_timing_us.get("total")?.mean();
+    if total_mean <= f64::EPSILON {
+        return None;
+    }
+
+    let mut share_of_total_percent: HashMap<String, f64> = SERVER_TIMING_COMPONENTS
+        .iter()
+        .filter_map(|&name| {
+            let mean = stats.server_timing_us.get(name)?.mean();
+            Some((name.to_string(), mean / total_mean * 100.0))
+        })
+        .collect();
+    let covered: f64 = share_of_total_percent.values().sum();
+    share_of_total_percent.insert("other".to_string(), (100.0 - covered).max(0.0));
+
+    let retried_requests = stats.retry_attempts.values().sum();
+    Some(ServerBreakdown {
+        share_of_total_percent,
+        retried_requests,
+        retried_percent: if stats.success_count == 0 {
+            0.0
+        } else {
+            retried_requests as f64 / stats.success_count as f64 * 100.0
+        },
+        attempts: stats
+            .retry_attempts
+            .iter()
+            .map(|(attempts, count)| (attempts.to_string(), *count))
+            .collect(),
+    })
+}
+
+fn load_replay_records(path: &str) -> anyhow::Result<Vec<ReplayRecord>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut records = Vec::new();
+    for (line_no, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str::<ReplayRecord>(line)
+            .map_err(|e| anyhow::anyhow!("{path}:{}: {e}", line_no + 1))?;
+        records.push(record);
+    }
+    if records.is_empty() {
+        anyhow::bail!("replay file {path} has no records");
     }
+    records.sort_by(|a, b| a.offset_ms.total_cmp(&b.offset_ms));
+    Ok(records)
+}
+
+/// Latencies are kept in microseconds between 1us and one hour with three
+/// significant digits, which is enough resolution for p99.99.
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, 3_600_000_000, 3).expect("histogram bounds are valid")
 }
 
-fn percentile(sorted: &[f64], p: f64) -> f64 {
-    if sorted.is_empty() {
-        return 0.0;
+fn record_ms(histogram: &mut Histogram<u64>, elapsed_ms: f64) {
+    histogram.saturating_record((elapsed_ms * 1000.0).round().max(1.0) as u64);
+}
+
+fn latency_metrics(histogram: &Histogram<u64>) -> LatencyMetrics {
+    if histogram.is_empty() {
+        return LatencyMetrics {
+            average: 0.0,
+            stddev: 0.0,
+            p50: 0.0,
+            p95: 0.0,
+            p99: 0.0,
+            p99_9: 0.0,
+            p99_99: 0.0,
+            max: 0.0,
+            min: 0.0,
+        };
     }
 
-    let rank = ((p / 100.0) * (sorted.len() as f64 - 1.0)).round() as usize;
-    sorted[rank]
+    let ms = |us: u64| us as f64 / 1000.0;
+    LatencyMetrics {
+        average: histogram.mean() / 1000.0,
+        stddev: histogram.stdev() / 1000.0,
+        p50: ms(histogram.value_at_quantile(0.50)),
+        p95: ms(histogram.value_at_quantile(0.95)),
+        p99: ms(histogram.value_at_quantile(0.99)),
+        p99_9: ms(histogram.value_at_quantile(0.999)),
+        p99_99: ms(histogram.value_at_quantile(0.9999)),
+        min: ms(histogram.min()),
+        max: ms(histogram.max()),
+    }
 }
 
 fn default_payload(function_name: &str, worker_id: usize, seq: u64) -> Value {
     if function_name == "example-rust" {
         let base = 12_000.0 + (worker_id as f64 * 17.0);
-        let spike = if seq % 12 == 0 { 6_500.0 } else { 0.0 };
+        let spike = if seq.is_multiple_of(12) { 6_500.0 } else { 0.0 };
         return serde_json::json!({
             "jobId": format!("bench-job-{worker_id}-{seq}"),
             "facilityId": "factory-north-1",
@@ -204,8 +599,8 @@ fn default_payload(function_name: &str, worker_id: usize, seq: u64) -> Value {
         "orderId": format!("bench-order-{worker_id}-{seq}"),
         "customer": { "name": "Benchmark Client", "email": "bench@example.com" },
         "shippingCountry": "US",
-        "couponCode": if seq % 2 == 0 { serde_json::Value::String("WELCOME10".to_string()) } else { serde_json::Value::Null },
-        "expedited": seq % 5 == 0,
+        "couponCode": if seq.is_multiple_of(2) { serde_json::Value::String("WELCOME10".to_string()) } else { serde_json::Value::Null },
+        "expedited": seq.is_multiple_of(5),
         "items": [
             { "sku": "SKU-100", "name": "Widget", "quantity": 2, "price": 14.99 },
             { "sku": "SKU-200", "name": "Cable", "quantity": 1 + (seq % 3), "price": 7.50 }
@@ -213,54 +608,55 @@ fn default_payload(function_name: &str, worker_id: usize, seq: u64) -> Value {
     })
 }
 
+/// Payload for the `seq`-th request: recorded payloads in payload-only replay,
+/// the built-in example payloads otherwise.
+fn payload_for(cfg: &BenchConfig, worker_id: usize, seq: u64) -> Value {
+    if cfg.replay_mode == ReplayMode::Payload && !cfg.replay_records.is_empty() {
+        let index = (seq % cfg.replay_records.len() as u64) as usize;
+        return cfg.replay_records[index].payload.clone();
+    }
+    default_payload(&cfg.function_name, worker_id, seq)
+}
+
+async fn send_request(request: reqwest::RequestBuilder) -> RequestOutcome {
+    match request.send().await {
+        Ok(resp) => {
+            if !resp.status().is_success() {
+                return RequestOutcome::Error;
+            }
+
+            let server_timing = parse_server_timing(resp.headers());
+            match resp.json::<Value>().await {
+                Ok(value) => RequestOutcome::Success {
+                    container_id: value
+                        .get("containerId")
+                        .and_then(Value::as_str)
+                        .map(ToOwned::to_owned)
+                        .unwrap_or_else(|| "unknown".to_string()),
+                    server_timing,
+                },
+                Err(_) => RequestOutcome::Error,
+            }
+        }
+        Err(err) if err.is_timeout() => RequestOutcome::Timeout,
+        Err(_) => RequestOutcome::Error,
+    }
+}
+
 async fn run_worker(
     mut receiver: mpsc::Receiver<BenchJob>,
     client: Client,
     cfg: Arc<BenchConfig>,
 ) -> WorkerStats {
     let mut stats = WorkerStats::default();
-    let endpoint = format!("{}/invoke/{}", cfg.base_url, cfg.function_name);
+    let endpoint = invoke_endpoint(&cfg);
 
     while let Some(job) = receiver.recv().await {
-        let payload = default_payload(&cfg.function_name, job.worker_id, job.seq);
+        let payload = payload_for(&cfg, job.worker_id, job.seq);
 
         let started = Instant::now();
-        let response = client.post(&endpoint).json(&payload).send().await;
-        let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
-
-        match response {
-            Ok(resp) => {
-                if !resp.status().is_success() {
-                    stats.error_count += 1;
-                    continue;
-                }
-
-                let body = resp.json::<Value>().await;
-                match body {
-                    Ok(value) => {
-                        stats.success_count += 1;
-                        stats.latencies_ms.push(elapsed_ms);
-
-                        let container_id = value
-                            .get("containerId")
-                            .and_then(Value::as_str)
-                            .map(ToOwned::to_owned)
-                            .unwrap_or_else(|| "unknown".to_string());
-                        *stats.container_hits.entry(container_id).or_insert(0) += 1;
-                    }
-                    Err(_) => {
-                        stats.error_count += 1;
-                    }
-                }
-            }
-            Err(err) => {
-                if err.is_timeout() {
-                    stats.timeout_count += 1;
-                } else {
-                    stats.error_count += 1;
-                }
-            }
-        }
+        let outcome = send_request(invoke_request(&client, &endpoint, &payload)).await;
+        stats.record(outcome, started.elapsed().as_secs_f64() * 1000.0);
     }
 
     stats
@@ -276,6 +672,117 @@ fn current_pulse_rps(cfg: &BenchConfig, elapsed: Duration) -> f64 {
     }
 }
 
+fn open_loop_rps(cfg: &BenchConfig, elapsed: Duration) -> f64 {
+    match cfg.load_pattern {
+        LoadPattern::Pulse => current_pulse_rps(cfg, elapsed),
+        _ => cfg.target_rps.max(0.1),
+    }
+}
+
+/// Gap until the next arrival: fixed for `Constant`, exponentially
+/// distributed for `Poisson`.
+fn next_arrival_gap(arrival: Arrival, rate_rps: f64, rng: &mut impl Rng) -> Duration {
+    let mean_secs = 1.0 / rate_rps.max(0.1);
+    let secs = match arrival {
+        Arrival::Poisson => -(1.0 - rng.random::<f64>()).ln() * mean_secs,
+        Arrival::Closed | Arrival::Constant => mean_secs,
+    };
+    Duration::from_secs_f64(secs)
+}
+
+/// Open-loop driver: every request is spawned at its scheduled send time,
+/// independent of how many are still in flight, and its latency is measured
+/// from that scheduled time so server stalls are not hidden (coordinated
+/// omission).
+async fn run_open_loop(
+    cfg: Arc<BenchConfig>,
+    client: Client,
+    deadline: TokioInstant,
+) -> WorkerStats {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(RequestOutcome, f64)>();
+    let collector = tokio::spawn(async move {
+        let mut stats = WorkerStats::default();
+        while let Some((outcome, elapsed_ms)) = receiver.recv().await {
+            stats.record(outcome, elapsed_ms);
+        }
+        stats
+    });
+
+    let endpoint = invoke_endpoint(&cfg);
+    let mut rng = StdRng::from_os_rng();
+    let start = TokioInstant::now();
+    let mut intended = start;
+    let mut seq = 0_u64;
+
+    while intended < deadline {
+        sleep_until(intended).await;
+
+        let worker_id = (seq as usize) % cfg.concurrency.max(1);
+        let payload = payload_for(&cfg, worker_id, seq);
+        let request = invoke_request(&client, &endpoint, &payload);
+        let request_sender = sender.clone();
+        let scheduled_at = intended;
+        tokio::spawn(async move {
+            let outcome = send_request(request).await;
+            let elapsed_ms = scheduled_at.elapsed().as_secs_f64() * 1000.0;
+            let _ = request_sender.send((outcome, elapsed_ms));
+        });
+
+        seq += 1;
+        let rate_rps = open_loop_rps(&cfg, intended.duration_since(start));
+        intended += next_arrival_gap(cfg.arrival, rate_rps, &mut rng);
+    }
+
+    drop(sender);
+    collector.await.unwrap_or_default()
+}
+
+/// Replays recorded requests at their original offsets divided by
+/// `replay_speed`. Like the open-loop arrivals, latency is measured from the
+/// scheduled send time.
+async fn run_replay(cfg: Arc<BenchConfig>, client: Client) -> WorkerStats {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<(RequestOutcome, f64)>();
+    let collector = tokio::spawn(async move {
+        let mut stats = WorkerStats::default();
+        while let Some((outcome, elapsed_ms)) = receiver.recv().await {
+            stats.record(outcome, elapsed_ms);
+        }
+        stats
+    });
+
+    let speed = cfg.replay_speed.max(0.001);
+    let first_offset_ms = cfg.replay_records.first().map(|r| r.offset_ms).unwrap_or(0.0);
+    let start = TokioInstant::now();
+
+    for record in &cfg.replay_records {
+        let offset_ms = (record.offset_ms - first_offset_ms).max(0.0) / speed;
+        let scheduled_at = start + Duration::from_secs_f64(offset_ms / 1000.0);
+        sleep_until(scheduled_at).await;
+
+        let function_name = record.function.as_deref().unwrap_or(&cfg.function_name);
+        let endpoint = format!("{}/invoke/{function_name}", cfg.base_url);
+        let mut request = invoke_request(&client, &endpoint, &record.payload);
+        for (name, value) in &record.headers {
+            // Recordings made before the server dropped it may still carry
+            // the key, and replayed calls would all hit the stored result.
+            if name.eq_ignore_ascii_case("content-type") || name.eq_ignore_ascii_case("idempotency-key") {
+                continue;
+            }
+            request = request.header(name, value);
+        }
+
+        let request_sender = sender.clone();
+        tokio::spawn(async move {
+            let outcome = send_request(request).await;
+            let elapsed_ms = scheduled_at.elapsed().as_secs_f64() * 1000.0;
+            let _ = request_sender.send((outcome, elapsed_ms));
+        });
+    }
+
+    drop(sender);
+    collector.await.unwrap_or_default()
+}
+
 async fn run_producer(
     cfg: Arc<BenchConfig>,
     senders: Vec<mpsc::Sender<BenchJob>>,
@@ -293,7 +800,7 @@ async fn run_producer(
         }
 
         match cfg.load_pattern {
-            LoadPattern::Stress => {
+            LoadPattern::Stress | LoadPattern::ColdStart => {
                 let seq = sequence.fetch_add(1, Ordering::Relaxed);
                 let worker_id = (seq as usize) % cfg.concurrency;
                 let job = BenchJob { worker_id, seq };
@@ -309,7 +816,7 @@ async fn run_producer(
                 let rate_rps = match cfg.load_pattern {
                     LoadPattern::Stable => cfg.target_rps.max(0.1),
                     LoadPattern::Pulse => current_pulse_rps(&cfg, now.duration_since(start)),
-                    LoadPattern::Stress => unreachable!(),
+                    LoadPattern::Stress | LoadPattern::ColdStart => unreachable!(),
                 };
 
                 token_budget += rate_rps * elapsed_since_tick;
@@ -342,14 +849,66 @@ async fn run_producer(
     }
 }
 
+async fn run_closed_loop(
+    cfg: Arc<BenchConfig>,
+    client: Client,
+    deadline: TokioInstant,
+) -> WorkerStats {
+    let sequence = Arc::new(AtomicU64::new(0));
+
+    let mut handles = Vec::with_capacity(cfg.concurrency);
+    let mut senders = Vec::with_capacity(cfg.concurrency);
+
+    for worker_id in 0..cfg.concurrency {
+        let (sender, receiver) = mpsc::channel::<BenchJob>(1024);
+        senders.push(sender);
+
+        let worker_client = client.clone();
+        let worker_cfg = Arc::clone(&cfg);
+
+        handles.push(tokio::spawn(async move {
+            let _ = worker_id;
+            run_worker(receiver, worker_client, worker_cfg).await
+        }));
+    }
+
+    let producer_cfg = Arc::clone(&cfg);
+    let producer_sequence = Arc::clone(&sequence);
+    let producer_senders = senders;
+    let producer_handle = tokio::spawn(async move {
+        run_producer(producer_cfg, producer_senders, deadline, producer_sequence).await;
+    });
+
+    let _ = producer_handle.await;
+
+    let mut collected_stats = Vec::with_capacity(cfg.concurrency);
+    for handle in handles {
+        if let Ok(stats) = handle.await {
+            collected_stats.push(stats);
+        }
+    }
+
+    merge_worker_stats(collected_stats)
+}
+
 fn merge_worker_stats(all: Vec<WorkerStats>) -> WorkerStats {
     let mut merged = WorkerStats::default();
 
-    for mut s in all {
-        merged.latencies_ms.append(&mut s.latencies_ms);
+    for s in all {
+        let _ = merged.latencies_us.add(&s.latencies_us);
+        for (name, histogram) in s.server_timing_us {
+            let _ = merged
+                .server_timing_us
+                .entry(name)
+                .or_insert_with(new_histogram)
+                .add(&histogram);
+        }
         merged.success_count += s.success_count;
         merged.error_count += s.error_count;
         merged.timeout_count += s.timeout_count;
+        for (attempts, count) in s.retry_attempts {
+            *merged.retry_attempts.entry(attempts).or_insert(0) += count;
+        }
         for (container, hits) in s.container_hits {
             *merged.container_hits.entry(container).or_insert(0) += hits;
         }
@@ -411,57 +970,316 @@ fn build_load_distribution(container_hits: HashMap<String, u64>) -> LoadDistribu
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let cfg = Arc::new(parse_args());
+async fn wait_for_operation(
+    client: &Client,
+    cfg: &BenchConfig,
+    response: reqwest::Response,
+) -> anyhow::Result<()> {
+    let body = response.error_for_status()?.json::<Value>().await?;
+    let operation_id = body
+        .get("id")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("operation id missing in {body}"))?
+        .to_string();
+    let status_url = format!("{}/deploy/status/{operation_id}", cfg.base_url);
+    let deadline = Instant::now() + Duration::from_secs(300);
+
+    while Instant::now() < deadline {
+        if let Ok(response) = client.get(&status_url).send().await
+            && let Ok(status) = response.json::<Value>().await
+        {
+            match status.get("state").and_then(Value::as_str) {
+                Some("finished") => return Ok(()),
+                Some("failed") => anyhow::bail!("operation {operation_id} failed: {status}"),
+                _ => {}
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    anyhow::bail!("timed out waiting for operation {operation_id}")
+}
 
-    let client = Client::builder()
-        .timeout(Duration::from_millis(cfg.request_timeout_ms))
-        .build()?;
+/// Tears the function down and brings it back, returning the instant the
+/// start request was issued.
+async fn trigger_cold_start(client: &Client, cfg: &BenchConfig) -> anyhow::Result<Instant> {
+    let function_url = format!("{}/functions/{}", cfg.base_url, cfg.function_name);
+    match cfg.cold_start_method {
+        ColdStartMethod::Stop => {
+            let _ = client.post(format!("{function_url}/stop")).send().await;
+            let started = Instant::now();
+            let response = client
+                .post(format!("{}/deploy/{}", cfg.base_url, cfg.function_name))
+                .send()
+                .await?;
+            wait_for_operation(client, cfg, response).await?;
+            Ok(started)
+        }
+        ColdStartMethod::Scale => {
+            let response = client
+                .patch(&function_url)
+                .json(&serde_json::json!({ "replicas": 0 }))
+                .send()
+                .await?;
+            wait_for_operation(client, cfg, response).await?;
+            let started = Instant::now();
+            let response = client
+                .patch(&function_url)
+                .json(&serde_json::json!({ "replicas": 1 }))
+                .send()
+                .await?;
+            wait_for_operation(client, cfg, response).await?;
+            Ok(started)
+        }
+    }
+}
 
-    let deadline = TokioInstant::now() + Duration::from_secs(cfg.duration_secs);
-    let sequence = Arc::new(AtomicU64::new(0));
+async fn fetch_last_start(client: &Client, cfg: &BenchConfig) -> Option<Value> {
+    let metrics = client
+        .get(format!("{}/metrics", cfg.base_url))
+        .send()
+        .await
+        .ok()?
+        .json::<Value>()
+        .await
+        .ok()?;
+    metrics
+        .get("functions")?
+        .get(&cfg.function_name)?
+        .get("lastStart")
+        .filter(|value| !value.is_null())
+        .cloned()
+}
 
-    let mut handles = Vec::with_capacity(cfg.concurrency);
-    let mut senders = Vec::with_capacity(cfg.concurrency);
+async fn run_cold_start(client: &Client, cfg: &BenchConfig) -> ColdStartSamples {
+    let mut samples = ColdStartSamples::default();
+    let endpoint = format!("{}/invoke/{}", cfg.base_url, cfg.function_name);
 
-    for worker_id in 0..cfg.concurrency {
-        let (sender, receiver) = mpsc::channel::<BenchJob>(1024);
-        senders.push(sender);
+    for seq in 0..cfg.iterations {
+        let started = match trigger_cold_start(client, cfg).await {
+            Ok(started) => started,
+            Err(e) => {
+                eprintln!("cold start #{seq} failed to restart function: {e}");
+                samples.failures += 1;
+                continue;
+            }
+        };
 
-        let worker_client = client.clone();
-        let worker_cfg = Arc::clone(&cfg);
+        let deadline = started + Duration::from_millis(cfg.request_timeout_ms.max(1) * 20);
+        let payload = default_payload(&cfg.function_name, 0, seq);
+        let mut first_response: Option<Value> = None;
+        let mut timed_out = false;
+        while Instant::now() < deadline {
+            match client.post(&endpoint).json(&payload).send().await {
+                Ok(response) if response.status().is_success() => {
+                    first_response = response.json::<Value>().await.ok();
+                    if first_response.is_some() {
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => timed_out = err.is_timeout(),
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
 
-        handles.push(tokio::spawn(async move {
-            let _ = worker_id;
-            run_worker(receiver, worker_client, worker_cfg).await
-        }));
+        let Some(response) = first_response else {
+            if timed_out {
+                samples.timeouts += 1;
+            } else {
+                samples.failures += 1;
+            }
+            continue;
+        };
+        let total_ms = started.elapsed().as_secs_f64() * 1000.0;
+        record_ms(&mut samples.time_to_first_response_us, total_ms);
+
+        let container_id = response
+            .get("containerId")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown")
+            .to_string();
+        *samples.container_hits.entry(container_id).or_insert(0) += 1;
+
+        if let Some(phases) = fetch_last_start(client, cfg).await {
+            let phase = |name: &str| phases.get(name).and_then(Value::as_f64).unwrap_or(0.0);
+            let server_ms = phase("imageMs")
+                + phase("createMs")
+                + phase("startMs")
+                + phase("readinessMs")
+                + phase("firstRequestMs");
+            record_ms(&mut samples.image_us, phase("imageMs"));
+            record_ms(&mut samples.create_us, phase("createMs"));
+            record_ms(&mut samples.start_us, phase("startMs"));
+            record_ms(&mut samples.readiness_us, phase("readinessMs"));
+            record_ms(&mut samples.first_request_us, phase("firstRequestMs"));
+            record_ms(&mut samples.control_plane_us, (total_ms - server_ms).max(0.0));
+            let mode = phases
+                .get("mode")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+                .to_string();
+            *samples.start_modes.entry(mode).or_insert(0) += 1;
+        }
+
+        println!("cold start #{seq}: {total_ms:.2}ms to first response");
     }
 
-    let producer_cfg = Arc::clone(&cfg);
-    let producer_sequence = Arc::clone(&sequence);
-    let producer_senders = senders;
-    let producer_handle = tokio::spawn(async move {
-        run_producer(producer_cfg, producer_senders, deadline, producer_sequence).await;
-    });
+    samples
+}
 
-    let bench_started = Instant::now();
+fn is_timed_replay(cfg: &BenchConfig) -> bool {
+    cfg.replay_path.is_some() && cfg.replay_mode == ReplayMode::Timed
+}
 
-    let _ = producer_handle.await;
+fn load_pattern_report(cfg: &BenchConfig) -> LoadPatternReport {
+    let timed_replay = is_timed_replay(cfg);
+    LoadPatternReport {
+        kind: match cfg.load_pattern {
+            _ if timed_replay => "replay".to_string(),
+            LoadPattern::Stable => "stable".to_string(),
+            LoadPattern::Pulse => "pulse".to_string(),
+            LoadPattern::Stress => "stress".to_string(),
+            LoadPattern::ColdStart => "cold-start".to_string(),
+        },
+        arrival: match cfg.arrival {
+            Arrival::Closed => "closed".to_string(),
+            Arrival::Constant => "constant".to_string(),
+            Arrival::Poisson => "poisson".to_string(),
+        },
+        coordinated_omission_corrected: timed_replay || cfg.arrival != Arrival::Closed,
+        target_rps: (matches!(cfg.load_pattern, LoadPattern::Stable)
+            || (cfg.arrival != Arrival::Closed && !matches!(cfg.load_pattern, LoadPattern::Pulse)))
+            .then_some(cfg.target_rps),
+        low_rps: matches!(cfg.load_pattern, LoadPattern::Pulse).then_some(cfg.low_rps),
+        high_rps: matches!(cfg.load_pattern, LoadPattern::Pulse).then_some(cfg.high_rps),
+        pulse_period_secs: matches!(cfg.load_pattern, LoadPattern::Pulse)
+            .then_some(cfg.pulse_period_secs),
+        replay_file: cfg.replay_path.clone(),
+        replay_mode: cfg.replay_path.as_ref().map(|_| match cfg.replay_mode {
+            ReplayMode::Timed => "timed".to_string(),
+            ReplayMode::Payload => "payload".to_string(),
+        }),
+        replay_speed: timed_replay.then_some(cfg.replay_speed),
+    }
+}
 
-    let mut collected_stats = Vec::with_capacity(cfg.concurrency);
-    for handle in handles {
-        if let Ok(stats) = handle.await {
-            collected_stats.push(stats);
+fn current_unix_ms() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default()
+}
+
+async fn write_report(cfg: &BenchConfig, report: &BenchReport) -> anyhow::Result<()> {
+    let output_json = serde_json::to_string_pretty(report)?;
+    tokio::fs::write(&cfg.output_path, output_json.as_bytes()).await?;
+
+    println!("Benchmark report saved to {}", cfg.output_path);
+    println!(
+        "ok={} err={} timeout={} p95={:.2}ms p99={:.2}ms p99.9={:.2}ms throughput={:.2} rps",
+        report.successful_requests,
+        report.failed_requests,
+        report.timeout_requests,
+        report.latency_ms.p95,
+        report.latency_ms.p99,
+        report.latency_ms.p99_9,
+        report.throughput_rps
+    );
+    Ok(())
+}
+
+async fn run_cold_start_bench(client: &Client, cfg: &BenchConfig) -> anyhow::Result<()> {
+    let bench_started = Instant::now();
+    let mut samples = run_cold_start(client, cfg).await;
+    let elapsed_secs = bench_started.elapsed().as_secs_f64();
+
+    let successful_requests = samples.time_to_first_response_us.len();
+    let total_requests = successful_requests + samples.failures + samples.timeouts;
+    let rate = |count: u64| {
+        if total_requests == 0 {
+            0.0
+        } else {
+            (count as f64 / total_requests as f64) * 100.0
         }
+    };
+
+    let report = BenchReport {
+        target_function: cfg.function_name.clone(),
+        base_url: cfg.base_url.clone(),
+        direct_url: cfg.direct_url.clone(),
+        load_pattern: load_pattern_report(cfg),
+        duration_secs: elapsed_secs.round() as u64,
+        concurrency: 1,
+        request_timeout_ms: cfg.request_timeout_ms,
+        total_requests,
+        successful_requests,
+        failed_requests: samples.failures,
+        timeout_requests: samples.timeouts,
+        error_rate_percent: rate(samples.failures),
+        timeout_rate_percent: rate(samples.timeouts),
+        throughput_rps: if elapsed_secs <= f64::EPSILON {
+            0.0
+        } else {
+            successful_requests as f64 / elapsed_secs
+        },
+        latency_ms: latency_metrics(&samples.time_to_first_response_us),
+        server_timing_ms: HashMap::new(),
+        server_breakdown: None,
+        load_distribution: build_load_distribution(std::mem::take(&mut samples.container_hits)),
+        cold_start: Some(ColdStartReport {
+            method: match cfg.cold_start_method {
+                ColdStartMethod::Stop => "stop".to_string(),
+                ColdStartMethod::Scale => "scale".to_string(),
+            },
+            iterations: cfg.iterations,
+            start_modes: std::mem::take(&mut samples.start_modes),
+            image_ms: latency_metrics(&samples.image_us),
+            create_ms: latency_metrics(&samples.create_us),
+            start_ms: latency_metrics(&samples.start_us),
+            readiness_ms: latency_metrics(&samples.readiness_us),
+            first_request_ms: latency_metrics(&samples.first_request_us),
+            control_plane_ms: latency_metrics(&samples.control_plane_us),
+        }),
+        generated_at_unix_ms: current_unix_ms(),
+    };
+
+    write_report(cfg, &report).await
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut cfg = parse_args()?;
+    if let Some(path) = &cfg.replay_path {
+        cfg.replay_records = load_replay_records(path)?;
+        println!("Loaded {} replay records from {path}", cfg.replay_records.len());
     }
+    let cfg = Arc::new(cfg);
 
+    let mut default_headers = reqwest::header::HeaderMap::new();
+    if let Some(api_key) = &cfg.api_key {
+        default_headers.insert("x-api-key", reqwest::header::HeaderValue::from_str(api_key.trim())?);
+    }
+    let client = Client::builder()
+        .timeout(Duration::from_millis(cfg.request_timeout_ms))
+        .default_headers(default_headers)
+        .build()?;
+
+    if matches!(cfg.load_pattern, LoadPattern::ColdStart) {
+        return run_cold_start_bench(&client, &cfg).await;
+    }
+
+    let deadline = TokioInstant::now() + Duration::from_secs(cfg.duration_secs);
+    let bench_started = Instant::now();
+
+    let merged = if is_timed_replay(&cfg) {
+        run_replay(Arc::clone(&cfg), client).await
+    } else if cfg.arrival == Arrival::Closed {
+        run_closed_loop(Arc::clone(&cfg), client, deadline).await
+    } else {
+        run_open_loop(Arc::clone(&cfg), client, deadline).await
+    };
     let elapsed_secs = bench_started.elapsed().as_secs_f64();
-    let mut merged = merge_worker_stats(collected_stats);
 
-    merged
-        .latencies_ms
-        .sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
 
     let total_requests = merged.success_count + merged.error_count + merged.timeout_count;
     let throughput_rps = if elapsed_secs <= f64::EPSILON {
@@ -482,50 +1300,26 @@ async fn main() -> anyhow::Result<()> {
         (merged.timeout_count as f64 / total_requests as f64) * 100.0
     };
 
-    let latency = if merged.latencies_ms.is_empty() {
-        LatencyMetrics {
-            average: 0.0,
-            p50: 0.0,
-            p95: 0.0,
-            p99: 0.0,
-            max: 0.0,
-            min: 0.0,
-        }
-    } else {
-        let sum: f64 = merged.latencies_ms.iter().sum();
-        LatencyMetrics {
-            average: sum / merged.latencies_ms.len() as f64,
-            p50: percentile(&merged.latencies_ms, 50.0),
-            p95: percentile(&merged.latencies_ms, 95.0),
-            p99: percentile(&merged.latencies_ms, 99.0),
-            min: *merged.latencies_ms.first().unwrap_or(&0.0),
-            max: *merged.latencies_ms.last().unwrap_or(&0.0),
-        }
-    };
+    let latency = latency_metrics(&merged.latencies_us);
+    let server_timing_ms = merged
+        .server_timing_us
+        .iter()
+        .map(|(name, histogram)| (name.clone(), latency_metrics(histogram)))
+        .collect();
+    let server_breakdown = server_breakdown(&merged);
 
     let load_distribution = build_load_distribution(merged.container_hits);
 
-    let generated_at_unix_ms = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_millis())
-        .unwrap_or_default();
-
     let report = BenchReport {
         target_function: cfg.function_name.clone(),
         base_url: cfg.base_url.clone(),
-        load_pattern: LoadPatternReport {
-            kind: match cfg.load_pattern {
-                LoadPattern::Stable => "stable".to_string(),
-                LoadPattern::Pulse => "pulse".to_string(),
-                LoadPattern::Stress => "stress".to_string(),
-            },
-            target_rps: matches!(cfg.load_pattern, LoadPattern::Stable).then_some(cfg.target_rps),
-            low_rps: matches!(cfg.load_pattern, LoadPattern::Pulse).then_some(cfg.low_rps),
-            high_rps: matches!(cfg.load_pattern, LoadPattern::Pulse).then_some(cfg.high_rps),
-            pulse_period_secs: matches!(cfg.load_pattern, LoadPattern::Pulse)
-                .then_some(cfg.pulse_period_secs),
+        direct_url: cfg.direct_url.clone(),
+        load_pattern: load_pattern_report(&cfg),
+        duration_secs: if is_timed_replay(&cfg) {
+            elapsed_secs.round() as u64
+        } else {
+            cfg.duration_secs
         },
-        duration_secs: cfg.duration_secs,
         concurrency: cfg.concurrency,
         request_timeout_ms: cfg.request_timeout_ms,
         total_requests,
@@ -536,23 +1330,14 @@ async fn main() -> anyhow::Result<()> {
         timeout_rate_percent,
         throughput_rps,
         latency_ms: latency,
+        server_timing_ms,
+        server_breakdown,
         load_distribution,
-        generated_at_unix_ms,
+        cold_start: None,
+        generated_at_unix_ms: current_unix_ms(),
     };
 
-    let output_json = serde_json::to_string_pretty(&report)?;
-    tokio::fs::write(&cfg.output_path, output_json.as_bytes()).await?;
-
-    println!("Benchmark report saved to {}", cfg.output_path);
-    println!(
-        "ok={} err={} timeout={} p95={:.2}ms p99={:.2}ms throughput={:.2} rps",
-        report.successful_requests,
-        report.failed_requests,
-        report.timeout_requests,
-        report.latency_ms.p95,
-        report.latency_ms.p99,
-        report.throughput_rps
-    );
+    write_report(&cfg, &report).await?;
 
     Ok(())
 }
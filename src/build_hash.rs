@@ -0,0 +1,233 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use sha2::{Digest, Sha256};
+
+const DOCKERIGNORE: &str = ".dockerignore";
+
+/// One `.dockerignore` line: `/`-separated glob segments, where `*` and `?`
+/// stay within a segment and `**` spans any number of them.
+#[derive(Debug)]
+struct IgnoreRule {
+    negated: bool,
+    segments: Vec<String>,
+}
+
+/// The `.dockerignore` rules of a build context. The last matching rule
+/// wins, and a rule matching a directory covers everything inside it.
+#[derive(Debug, Default)]
+pub struct DockerIgnore {
+    rules: Vec<IgnoreRule>,
+}
+
+impl DockerIgnore {
+    pub fn parse(content: &str) -> Self {
+        let rules = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let (negated, pattern) = match line.strip_prefix('!') {
+                    Some(pattern) => (true, pattern.trim()),
+                    None => (false, line),
+                };
+                let segments = pattern
+                    .split('/')
+                    .filter(|segment| !segment.is_empty() && *segment != ".")
+                    .map(ToOwned::to_owned)
+                    .collect::<Vec<_>>();
+                (!segments.is_empty()).then_some(IgnoreRule { negated, segments })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    fn has_negations(&self) -> bool {
+        self.rules.iter().any(|rule| rule.negated)
+    }
+
+    /// `path` is relative to the context root and `/`-separated.
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let parts = path.split('/').collect::<Vec<_>>();
+        let mut ignored = false;
+        for rule in &self.rules {
+            let matches = (1..=parts.len()).any(|len| match_segments(&rule.segments, &parts[..len]));
+            if matches {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+fn match_segments(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skipped| match_segments(rest, &path[skipped..]))
+        }
+        Some((first, rest)) => {
+            !path.is_empty() && match_wildcard(first.as_bytes(), path[0].as_bytes()) && match_segments(rest, &path[1..])
+        }
+    }
+}
+
+fn match_wildcard(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skipped| match_wildcard(rest, &text[skipped..])),
+        Some((b'?', rest)) => !text.is_empty() && match_wildcard(rest, &text[1..]),
+        Some((byte, rest)) => text.first() == Some(byte) && match_wildcard(rest, &text[1..]),
+    }
+}
+
+/// SHA-256 over the Dockerfile setting and every file of the build context
+/// that `.dockerignore` keeps: relative path, executable bit and contents,
+/// in path order. Symlinks are sent to Docker as links, so their target
+/// is hashed rather than the file it points to. Equal hashes mean the build would produce the same image.
+pub async fn build_context_hash(context: &Path, dockerfile: &str) -> Result<String> {
+    let context = context.to_path_buf();
+    let dockerfile = dockerfile.to_string();
+    tokio::task::spawn_blocking(move || hash_context(&context, &dockerfile))
+        .await
+        .map_err(|e| anyhow!("Не удалось посчитать хеш контекста сборки: {e}"))?
+}
+
+fn hash_context(context: &Path, dockerfile: &str) -> Result<String> {
+    let ignore = match std::fs::read_to_string(context.join(DOCKERIGNORE)) {
+        Ok(content) => DockerIgnore::parse(&content),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => DockerIgnore::default(),
+        Err(e) => return Err(e).context("Не удалось прочитать .dockerignore"),
+    };
+    // Docker always sends the Dockerfile, even when it is ignored.
+    let dockerfile_path = match dockerfile.trim_start_matches("./") {
+        "" => "Dockerfile",
+        path => path,
+    };
+    let mut files = Vec::new();
+    collect_files(context, "", &ignore, dockerfile_path, &mut files)?;
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(dockerfile.as_bytes());
+    hasher.update([0]);
+    for (relative, path) in files {
+        let read_error = || format!("Не удалось прочитать '{}'", path.display());
+        let is_symlink = std::fs::symlink_metadata(&path)
+            .with_context(read_error)?
+            .file_type()
+            .is_symlink();
+        let (kind, contents) = if is_symlink {
+            let target = std::fs::read_link(&path).with_context(read_error)?;
+            (2, target.to_string_lossy().as_bytes().to_vec())
+        } else {
+            (is_executable(&path) as u8, std::fs::read(&path).with_context(read_error)?)
+        };
+        hasher.update(relative.as_bytes());
+        hasher.update([0, kind]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    ignore: &DockerIgnore,
+    dockerfile: &str,
+    files: &mut Vec<(String, PathBuf)>,
+) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Не удалось прочитать каталог '{}'", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative = if prefix.is_empty() {
+            name
+        } else {
+            format!("{prefix}/{name}")
+        };
+        let ignored = ignore.is_ignored(&relative);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            // A negation may re-include files of an ignored directory.
+            if !ignored || ignore.has_negations() {
+                collect_files(&entry.path(), &relative, ignore, dockerfile, files)?;
+            }
+        } else if !ignored || relative == dockerfile {
+            files.push((relative, entry.path()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DockerIgnore, build_context_hash};
+
+    #[test]
+    fn dockerignore_rules_follow_docker_semantics() {
+        let ignore = DockerIgnore::parse("# build output\ntarget\n*.log\n**/node_modules\ndocs/*.md\n!docs/README.md\n");
+        assert!(ignore.is_ignored("target"));
+        assert!(ignore.is_ignored("target/debug/app"));
+        assert!(ignore.is_ignored("debug.log"));
+        assert!(!ignore.is_ignored("logs/debug.log"));
+        assert!(ignore.is_ignored("web/node_modules/react/index.js"));
+        assert!(ignore.is_ignored("docs/guide.md"));
+        assert!(!ignore.is_ignored("docs/README.md"));
+        assert!(!ignore.is_ignored("src/main.rs"));
+    }
+
+    #[tokio::test]
+    async fn hash_changes_only_with_files_that_reach_the_build() {
+        let context = std::env::temp_dir().join(format!("build-hash-test-{}", uuid::Uuid::now_v7()));
+        tokio::fs::create_dir_all(context.join("src")).await.unwrap();
+        tokio::fs::write(context.join(".dockerignore"), "*.log\nfunction.json\n").await.unwrap();
+        tokio::fs::write(context.join("Dockerfile"), "FROM scratch\n").await.unwrap();
+        tokio::fs::write(context.join("src/main.go"), "package main\n").await.unwrap();
+        tokio::fs::write(context.join("function.json"), "{\"replicas\": 1}").await.unwrap();
+        let initial = build_context_hash(&context, "Dockerfile").await.unwrap();
+
+        tokio::fs::write(context.join("function.json"), "{\"replicas\": 3}").await.unwrap();
+        tokio::fs::write(context.join("debug.log"), "noise").await.unwrap();
+        assert_eq!(build_context_hash(&context, "Dockerfile").await.unwrap(), initial);
+        assert_ne!(build_context_hash(&context, "Dockerfile.dev").await.unwrap(), initial);
+
+        tokio::fs::write(context.join("src/main.go"), "package main\n\nfunc main() {}\n").await.unwrap();
+        assert_ne!(build_context_hash(&context, "Dockerfile").await.unwrap(), initial);
+        let _ = tokio::fs::remove_dir_all(&context).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_are_hashed_by_target_and_config_only_when_sent() {
+        let context = std::env::temp_dir().join(format!("build-hash-test-{}", uuid::Uuid::now_v7()));
+        tokio::fs::create_dir_all(&context).await.unwrap();
+        tokio::fs::write(context.join("Dockerfile"), "FROM scratch\n").await.unwrap();
+        tokio::fs::symlink("missing/target", context.join("dangling")).await.unwrap();
+        tokio::fs::write(context.join("function.json"), "{\"replicas\": 1}").await.unwrap();
+        let initial = build_context_hash(&context, "Dockerfile").await.unwrap();
+
+        tokio::fs::remove_file(context.join("dangling")).await.unwrap();
+        tokio::fs::symlink("other/target", context.join("dangling")).await.unwrap();
+        let relinked = build_context_hash(&context, "Dockerfile").await.unwrap();
+        assert_ne!(relinked, initial);
+
+        // Not ignored, so Docker sends it and an image may copy it.
+        tokio::fs::write(context.join("function.json"), "{\"replicas\": 3}").await.unwrap();
+        assert_ne!(build_context_hash(&context, "Dockerfile").await.unwrap(), relinked);
+        let _ = tokio::fs::remove_dir_all(&context).await;
+    }
+}
@@ -1,15 +1,28 @@
 #![allow(dead_code)]
 
 use std::result::Result::Ok;
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Result, anyhow, bail};
-use bollard::query_parameters::{ListNetworksOptions, ListVolumesOptions};
-use bollard::secret::{Mount, NetworkCreateRequest, VolumeCreateOptions};
+use bollard::errors::Error as DockerError;
+use bollard::query_parameters::{
+    DataUsageOptionsBuilder, DownloadFromContainerOptionsBuilder, InspectNetworkOptions,
+    ListImagesOptionsBuilder, ListNetworksOptions, ListVolumesOptions, PruneBuildOptionsBuilder,
+    RemoveImageOptionsBuilder, RemoveVolumeOptionsBuilder, UploadToContainerOptionsBuilder,
+};
+use bollard::secret::{
+    EndpointSettings, ImageSummary, Mount, NetworkCreateRequest, NetworkingConfig, Volume,
+    VolumeCreateOptions,
+};
 use bollard::{
     Docker, body_full,
+    exec::{CreateExecOptions, StartExecResults},
     query_parameters::{
-        self, BuildImageOptionsBuilder, CreateContainerOptionsBuilder,
+        self, BuildImageOptionsBuilder, CreateContainerOptionsBuilder, LogsOptionsBuilder,
         RemoveContainerOptionsBuilder,
     },
     secret::{ContainerCreateBody, HostConfig, PortBinding},
@@ -17,14 +30,22 @@ use bollard::{
 use futures_util::StreamExt;
 use log::{error, info, warn};
 use serde_json::{Value, json};
-use tokio::io::AsyncReadExt;
-use tokio::time::{Duration, sleep};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{Duration, Instant, sleep};
 
 use crate::errors::deploy_error::DeployError;
 use crate::function_manager::FunctionConfig;
+use crate::http_timing::{ConnectTimingLayer, with_connect_timer};
+use crate::invoke_chain::PLATFORM_HOST;
+use crate::network::{NetworkConfig, NetworkMode};
+use crate::volume::{FUNCTION_LABEL, RETENTION_LABEL, SHARED_DATA_PATH, VolumeRetention};
 
 const MB_TO_BYTES: i64 = 1024 * 1024;
 pub const MANAGED_CONTAINER_LABEL: &str = "serverless.managed=true";
+pub const VERSION_LABEL: &str = "serverless.version";
+/// Hash of the build context the image was built from.
+pub const BUILD_HASH_LABEL: &str = "serverless.build-hash";
+const BUILD_CONTEXT_PREFIX: &str = "build-context-";
 
 fn managed_container_labels() -> HashMap<String, String> {
     HashMap::from([(
@@ -33,7 +54,58 @@ fn managed_container_labels() -> HashMap<String, String> {
     )])
 }
 
+/// Labels of the volumes and networks a function owns, so the ones no
+/// deployment uses anymore can be found and removed.
+fn function_resource_labels(function_name: &str) -> HashMap<String, String> {
+    let mut labels = managed_container_labels();
+    labels.insert(FUNCTION_LABEL.to_string(), function_name.to_string());
+    labels
+}
+
+fn function_label_filter(function_name: &str) -> HashMap<String, Vec<String>> {
+    HashMap::from([(
+        "label".to_string(),
+        vec![format!("{FUNCTION_LABEL}={function_name}")],
+    )])
+}
+
+fn is_not_found(error: &DockerError) -> bool {
+    matches!(
+        error,
+        DockerError::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
+fn function_label_key_filter() -> HashMap<String, Vec<String>> {
+    HashMap::from([("label".to_string(), vec![FUNCTION_LABEL.to_string()])])
+}
+
+fn build_context_dir() -> PathBuf {
+    if cfg!(target_os = "windows") {
+        PathBuf::from(std::env::var("TEMP").unwrap_or_else(|_| "C:\\Windows\\Temp".to_string()))
+    } else {
+        PathBuf::from("/tmp")
+    }
+}
+
 type ContainerId = String;
+
+/// Response of a replica plus where the time went, for `Server-Timing`.
+#[derive(Debug)]
+pub struct HttpInvocation {
+    pub body: Value,
+    pub attempts: u32,
+    /// Connection setup of the successful attempt.
+    pub connect_ms: f64,
+    /// Successful attempt minus its connection setup.
+    pub container_ms: f64,
+    /// Failed attempts and the backoff between them.
+    pub retries_ms: f64,
+}
+
 #[derive(Debug)]
 pub struct ContainerManager {
     docker: Docker,
@@ -45,6 +117,7 @@ impl ContainerManager {
         let http_client = reqwest::Client::builder()
             .pool_idle_timeout(Duration::from_secs(90))
             .pool_max_idle_per_host(64)
+            .connector_layer(ConnectTimingLayer)
             .build()?;
         Ok(Self {
             docker,
@@ -52,19 +125,39 @@ impl ContainerManager {
         })
     }
 
-    pub async fn try_invoke_http(&self, host_port: u16, payload: &Value) -> Result<Value> {
-        let url = format!("http://127.0.0.1:{host_port}/");
+    pub async fn try_invoke_http(
+        &self,
+        addr: SocketAddr,
+        payload: &Value,
+        headers: &[(String, String)],
+    ) -> Result<HttpInvocation> {
+        let url = format!("http://{addr}/");
         let mut last_error: Option<anyhow::Error> = None;
+        let started = Instant::now();
 
         for attempt in 0..8 {
-            match self.http_client.post(&url).json(payload).send().await {
+            let attempt_started = Instant::now();
+            let mut request = self.http_client.post(&url).json(payload);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let (response, connect) = with_connect_timer(request.send()).await;
+            match response {
                 Ok(response) => {
                     let status = response.status();
                     let body = response.text().await?;
                     if !status.is_success() {
                         bail!("invoke failed with status {status}: {body}");
                     }
-                    return Self::parse_invoke_body(&body);
+                    let connect_ms = connect.as_secs_f64() * 1000.0;
+                    let attempt_ms = attempt_started.elapsed().as_secs_f64() * 1000.0;
+                    return Ok(HttpInvocation {
+                        body: Self::parse_invoke_body(&body)?,
+                        attempts: attempt + 1,
+                        connect_ms,
+                        container_ms: (attempt_ms - connect_ms).max(0.0),
+                        retries_ms: attempt_started.duration_since(started).as_secs_f64() * 1000.0,
+                    });
                 }
                 Err(error) => {
                     last_error = Some(anyhow!(error));
@@ -90,13 +183,12 @@ impl ContainerManager {
         let mut found_host_port: Option<String> = None;
         if let Some(ports) = details.network_settings.and_then(|settings| settings.ports) {
             for key in [format!("{inner_port}/tcp"), inner_port.to_string()] {
-                if let Some(Some(bindings)) = ports.get(&key) {
-                    if let Some(binding) = bindings.first() {
-                        if let Some(host_port) = binding.host_port.clone() {
-                            found_host_port = Some(host_port);
-                            break;
-                        }
-                    }
+                if let Some(Some(bindings)) = ports.get(&key)
+                    && let Some(binding) = bindings.first()
+                    && let Some(host_port) = binding.host_port.clone()
+                {
+                    found_host_port = Some(host_port);
+                    break;
                 }
             }
         }
@@ -150,6 +242,38 @@ impl ContainerManager {
             .await;
     }
 
+    /// Runs `cmd` in a running container as its user and fails unless it
+    /// exits with 0.
+    pub async fn exec(&self, container_id: &str, cmd: &[&str]) -> Result<()> {
+        let exec = self
+            .docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    cmd: Some(cmd.to_vec()),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let mut output = String::new();
+        if let StartExecResults::Attached { output: mut stream, .. } = self.docker.start_exec(&exec.id, None).await? {
+            while let Some(chunk) = stream.next().await {
+                output.push_str(&chunk?.to_string());
+            }
+        }
+        let exit_code = self.docker.inspect_exec(&exec.id).await?.exit_code;
+        if exit_code != Some(0) {
+            bail!(
+                "Команда '{}' в контейнере {container_id} завершилась с кодом {exit_code:?}: {}",
+                cmd.join(" "),
+                output.trim()
+            );
+        }
+        Ok(())
+    }
+
     pub async fn is_created(&self, container_id: &str) -> bool {
         self.docker
             .inspect_container(
@@ -162,6 +286,136 @@ impl ContainerManager {
             .is_some()
     }
 
+    pub async fn is_running(&self, container_id: &str) -> bool {
+        self.docker
+            .inspect_container(
+                container_id,
+                None::<query_parameters::InspectContainerOptions>,
+            )
+            .await
+            .ok()
+            .and_then(|details| details.state)
+            .and_then(|state| state.running)
+            .unwrap_or(false)
+    }
+
+    /// Exit code and the tail of the output of a stopped container, `None`
+    /// while it is still running.
+    pub async fn exit_report(&self, container_id: &str) -> Option<(i64, String)> {
+        let state = self
+            .docker
+            .inspect_container(
+                container_id,
+                None::<query_parameters::InspectContainerOptions>,
+            )
+            .await
+            .ok()?
+            .state?;
+        if state.running.unwrap_or(false) {
+            return None;
+        }
+        let options = LogsOptionsBuilder::new()
+            .stdout(true)
+            .stderr(true)
+            .tail("20")
+            .build();
+        let mut output = String::new();
+        let mut logs = self.docker.logs(container_id, Some(options));
+        while let Some(Ok(chunk)) = logs.next().await {
+            output.push_str(&chunk.to_string());
+        }
+        Some((state.exit_code.unwrap_or_default(), output.trim().to_string()))
+    }
+
+    /// Checks that the daemon runs in experimental mode and that CRIU is
+    /// installed, both of which `docker checkpoint` requires.
+    pub async fn probe_checkpoint_support(&self) -> bool {
+        let experimental = match self.docker.info().await {
+            Ok(info) => info.experimental_build.unwrap_or(false),
+            Err(e) => {
+                warn!("Checkpoint probe: failed to query docker info: {e}");
+                return false;
+            }
+        };
+        if !experimental {
+            info!("Checkpoint probe: docker daemon is not running in experimental mode");
+            return false;
+        }
+
+        let criu = tokio::process::Command::new("criu")
+            .arg("--version")
+            .output()
+            .await;
+        match criu {
+            Ok(output) if output.status.success() => true,
+            _ => {
+                info!("Checkpoint probe: criu is not available");
+                false
+            }
+        }
+    }
+
+    pub async fn checkpoint_container(
+        &self,
+        container_id: &str,
+        checkpoint_dir: &Path,
+        checkpoint_name: &str,
+    ) -> Result<()> {
+        tokio::fs::create_dir_all(checkpoint_dir).await?;
+        let output = tokio::process::Command::new("docker")
+            .arg("checkpoint")
+            .arg("create")
+            .arg("--leave-running")
+            .arg(format!("--checkpoint-dir={}", checkpoint_dir.display()))
+            .arg(container_id)
+            .arg(checkpoint_name)
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!(
+                "docker checkpoint create failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn start_container_from_checkpoint(
+        &self,
+        container_id: &str,
+        checkpoint_dir: &Path,
+        checkpoint_name: &str,
+    ) -> Result<()> {
+        let output = tokio::process::Command::new("docker")
+            .arg("start")
+            .arg(format!("--checkpoint-dir={}", checkpoint_dir.display()))
+            .arg(format!("--checkpoint={checkpoint_name}"))
+            .arg(container_id)
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!(
+                "docker start from checkpoint failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
+    /// Polls until the replica accepts TCP connections.
+    pub async fn wait_until_ready(&self, addr: SocketAddr, timeout: Duration) -> Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            if tokio::net::TcpStream::connect(addr).await.is_ok() {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                bail!("{addr} did not become ready within {timeout:?}");
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+
     pub async fn start_container(&self, container_id: &str) -> Result<()> {
         self.docker
             .start_container(
@@ -180,7 +434,7 @@ impl ContainerManager {
         )
     }
 
-    fn resource_name_from_image_name(image_name: &str) -> String {
+    pub fn resource_name_from_image_name(image_name: &str) -> String {
         image_name.replace(':', "-")
     }
 
@@ -205,7 +459,7 @@ impl ContainerManager {
                     bindings.insert(
                         function_config.inner_port.to_string(),
                         Some(vec![PortBinding {
-                            host_ip: Some("0.0.0.0".to_string()),
+                            host_ip: Some(Ipv4Addr::LOCALHOST.to_string()),
                             host_port: Some(port.to_string()),
                         }]),
                     );
@@ -234,7 +488,13 @@ impl ContainerManager {
         Ok(response.id)
     }
 
-    async fn create_network_if_not_exists(&self, name: &str) -> Result<()> {
+    async fn create_network_if_not_exists(
+        &self,
+        name: &str,
+        internal: bool,
+        options: HashMap<String, String>,
+        labels: HashMap<String, String>,
+    ) -> Result<()> {
         let networks = self
             .docker
             .list_networks(None::<ListNetworksOptions>)
@@ -248,27 +508,35 @@ impl ContainerManager {
         }
         let config = NetworkCreateRequest {
             name: name.to_string(),
+            internal: Some(internal),
+            options: Some(options),
+            labels: Some(labels),
             ..Default::default()
         };
-        info!("Creating docker network: '{}'", name);
+        info!("Creating docker network: '{}' (internal: {internal})", name);
         self.docker.create_network(config).await?;
         Ok(())
     }
 
-    async fn create_shared_volume_if_not_exists(&self, volume_name: &str) -> Result<()> {
-        let list_volumes_options = ListVolumesOptions {
-            filters: Some(HashMap::from([(
-                "name".to_string(),
-                vec![volume_name.to_string()],
-            )])),
-        };
-        let volumes = self.docker.list_volumes(Some(list_volumes_options)).await?;
-        if volumes.volumes.is_some() {
-            info!("Shared volume '{volume_name}' already exists. Skipping creation...");
-            return Ok(());
+    async fn create_shared_volume_if_not_exists(
+        &self,
+        volume_name: &str,
+        function_name: &str,
+        retention: VolumeRetention,
+    ) -> Result<()> {
+        match self.docker.inspect_volume(volume_name).await {
+            Ok(_) => {
+                info!("Shared volume '{volume_name}' already exists. Skipping creation...");
+                return Ok(());
+            }
+            Err(e) if is_not_found(&e) => {}
+            Err(e) => return Err(e.into()),
         }
+        let mut labels = function_resource_labels(function_name);
+        labels.insert(RETENTION_LABEL.to_string(), retention.as_str().to_string());
         let config = VolumeCreateOptions {
             name: Some(volume_name.to_string()),
+            labels: Some(labels),
             ..Default::default()
         };
         info!("Creating volume: '{volume_name}'");
@@ -276,34 +544,360 @@ impl ContainerManager {
         Ok(())
     }
 
+    /// Disk usage of a volume as reported by `docker system df`, `None` when
+    /// the driver does not report it.
+    pub async fn volume_size(&self, volume_name: &str) -> Result<Option<u64>> {
+        let options = DataUsageOptionsBuilder::new()
+            ._type(vec!["volume".to_string()])
+            .build();
+        let usage = self.docker.df(Some(options)).await?;
+        Ok(usage
+            .volumes
+            .into_iter()
+            .flatten()
+            .find(|volume| volume.name == volume_name)
+            .and_then(|volume| volume.usage_data)
+            .and_then(|usage| u64::try_from(usage.size).ok()))
+    }
+
+    /// Writes the contents of the replica's shared volume to a tarball, with
+    /// paths relative to the volume root.
+    pub async fn export_volume(&self, container_id: &str, tar_path: &Path) -> Result<()> {
+        // A trailing `/.` archives the contents without the directory itself.
+        let options = DownloadFromContainerOptionsBuilder::new()
+            .path(&format!("{SHARED_DATA_PATH}/."))
+            .build();
+        let partial_path = tar_path.with_extension("partial");
+        let mut file = tokio::fs::File::create(&partial_path).await?;
+        let mut archive = self.docker.download_from_container(container_id, Some(options));
+        while let Some(chunk) = archive.next().await {
+            match chunk {
+                Ok(chunk) => file.write_all(&chunk).await?,
+                Err(e) => {
+                    drop(file);
+                    let _ = tokio::fs::remove_file(&partial_path).await;
+                    return Err(anyhow!("Не удалось выгрузить том контейнера {container_id}: {e}"));
+                }
+            }
+        }
+        file.flush().await?;
+        tokio::fs::rename(&partial_path, tar_path).await?;
+        Ok(())
+    }
+
+    /// Replaces the contents of the replica's shared volume with a tarball
+    /// made by `export_volume`. The volume is emptied first, so files
+    /// created after the snapshot do not survive the restore.
+    pub async fn import_volume(&self, container_id: &str, tar_path: &Path) -> Result<()> {
+        let bytes = tokio::fs::read(tar_path).await?;
+        self.exec(container_id, &["find", SHARED_DATA_PATH, "-mindepth", "1", "-delete"])
+            .await
+            .map_err(|e| anyhow!("Не удалось очистить том контейнера {container_id}: {e}"))?;
+        let options = UploadToContainerOptionsBuilder::new()
+            .path(SHARED_DATA_PATH)
+            .build();
+        self.docker
+            .upload_to_container(container_id, Some(options), body_full(bytes.into()))
+            .await
+            .map_err(|e| anyhow!("Не удалось загрузить снимок в том контейнера {container_id}: {e}"))
+    }
+
+    /// Removes volumes and networks of a function that the current deployment
+    /// does not use. Only volumes with `latest` or `none` retention are
+    /// removed here, the others outlive versions. Resources still in use are
+    /// skipped by Docker.
+    pub async fn remove_orphan_resources(
+        &self,
+        function_name: &str,
+        keep_volume: Option<&str>,
+        keep_networks: &[String],
+    ) -> Result<()> {
+        let volumes = self
+            .docker
+            .list_volumes(Some(ListVolumesOptions {
+                filters: Some(function_label_filter(function_name)),
+            }))
+            .await?;
+        for volume in volumes.volumes.into_iter().flatten() {
+            let retention = volume.labels.get(RETENTION_LABEL).map(String::as_str);
+            if !VolumeRetention::removed_with_old_versions(retention) || keep_volume == Some(volume.name.as_str()) {
+                continue;
+            }
+            let options = RemoveVolumeOptionsBuilder::new().force(false).build();
+            match self.docker.remove_volume(&volume.name, Some(options)).await {
+                Ok(()) => info!("Removed orphan volume '{}' of '{function_name}'", volume.name),
+                Err(e) => warn!("Failed to remove volume '{}' of '{function_name}': {e}", volume.name),
+            }
+        }
+
+        let networks = self
+            .docker
+            .list_networks(Some(ListNetworksOptions {
+                filters: Some(function_label_filter(function_name)),
+            }))
+            .await?;
+        for name in networks.into_iter().filter_map(|network| network.name) {
+            if !keep_networks.contains(&name) {
+                self.remove_network(&name).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a network unless containers are still attached to it.
+    pub async fn remove_network(&self, name: &str) {
+        match self.try_remove_network(name).await {
+            Ok(()) => info!("Removed docker network '{name}'"),
+            Err(e) => info!("Keeping docker network '{name}': {e}"),
+        }
+    }
+
+    pub async fn try_remove_network(&self, name: &str) -> Result<()> {
+        self.docker.remove_network(name).await?;
+        Ok(())
+    }
+
+    pub async fn remove_volume(&self, name: &str) -> Result<()> {
+        let options = RemoveVolumeOptionsBuilder::new().force(false).build();
+        self.docker.remove_volume(name, Some(options)).await?;
+        Ok(())
+    }
+
+    /// Names of networks owned by some function.
+    pub async fn list_function_networks(&self) -> Result<Vec<String>> {
+        let networks = self
+            .docker
+            .list_networks(Some(ListNetworksOptions {
+                filters: Some(function_label_key_filter()),
+            }))
+            .await?;
+        Ok(networks.into_iter().filter_map(|network| network.name).collect())
+    }
+
+    /// Volumes owned by some function.
+    pub async fn list_function_volumes(&self) -> Result<Vec<Volume>> {
+        let volumes = self
+            .docker
+            .list_volumes(Some(ListVolumesOptions {
+                filters: Some(function_label_key_filter()),
+            }))
+            .await?;
+        Ok(volumes.volumes.unwrap_or_default())
+    }
+
+    /// Images built for functions, dangling ones included.
+    pub async fn list_function_images(&self) -> Result<Vec<ImageSummary>> {
+        let options = ListImagesOptionsBuilder::new()
+            .filters(&function_label_key_filter())
+            .build();
+        Ok(self.docker.list_images(Some(options)).await?)
+    }
+
+    /// Untags the image, deleting it once no tag refers to it. Images used
+    /// by containers are refused by Docker.
+    pub async fn remove_image(&self, name: &str) -> Result<()> {
+        let options = RemoveImageOptionsBuilder::new().force(false).build();
+        self.docker.remove_image(name, Some(options), None).await?;
+        Ok(())
+    }
+
+    /// Size of the build cache not used by a running build.
+    pub async fn build_cache_size(&self) -> Result<u64> {
+        let options = DataUsageOptionsBuilder::new()
+            ._type(vec!["build-cache".to_string()])
+            .build();
+        let usage = self.docker.df(Some(options)).await?;
+        Ok(usage
+            .build_cache
+            .into_iter()
+            .flatten()
+            .filter(|cache| !cache.in_use.unwrap_or(false))
+            .filter_map(|cache| u64::try_from(cache.size.unwrap_or_default()).ok())
+            .sum())
+    }
+
+    /// Returns the number of bytes reclaimed.
+    pub async fn prune_build_cache(&self) -> Result<u64> {
+        let response = self
+            .docker
+            .prune_build(Some(PruneBuildOptionsBuilder::new().build()))
+            .await?;
+        Ok(u64::try_from(response.space_reclaimed.unwrap_or_default()).unwrap_or_default())
+    }
+
+    /// Build context tarballs older than `min_age`, left behind by builds
+    /// the platform did not finish.
+    pub async fn stale_build_contexts(&self, min_age: Duration) -> Result<Vec<(PathBuf, u64)>> {
+        let mut entries = tokio::fs::read_dir(build_context_dir()).await?;
+        let mut stale = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if !entry.file_name().to_string_lossy().starts_with(BUILD_CONTEXT_PREFIX) {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata().await else {
+                continue;
+            };
+            let old_enough = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= min_age);
+            if metadata.is_file() && old_enough {
+                stale.push((entry.path(), metadata.len()));
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Creates the function's own network and the shared networks it joins.
+    /// Returns the gateway of the function's network: the host address its
+    /// replicas reach the platform at, and for isolated functions the only
+    /// one.
+    pub async fn prepare_function_network(
+        &self,
+        image_name: &str,
+        function_name: &str,
+        network: &NetworkConfig,
+    ) -> Result<IpAddr> {
+        let resource_name = Self::resource_name_from_image_name(image_name);
+        let network_name = network.function_network_name(&resource_name);
+        let mut options = HashMap::new();
+        if network.mode == NetworkMode::None {
+            options.insert(
+                "com.docker.network.bridge.enable_icc".to_string(),
+                "false".to_string(),
+            );
+        }
+        self.create_network_if_not_exists(
+            &network_name,
+            network.isolated(),
+            options,
+            function_resource_labels(function_name),
+        )
+        .await?;
+        // Shared networks are internal so they never become an egress path.
+        for shared_network in network.shared_network_names() {
+            self.create_network_if_not_exists(
+                &shared_network,
+                true,
+                HashMap::new(),
+                managed_container_labels(),
+            )
+            .await?;
+        }
+
+        let details = self
+            .docker
+            .inspect_network(&network_name, None::<InspectNetworkOptions>)
+            .await?;
+        let gateway = details
+            .ipam
+            .and_then(|ipam| ipam.config)
+            .into_iter()
+            .flatten()
+            .find_map(|config| config.gateway?.parse::<IpAddr>().ok())
+            .ok_or_else(|| anyhow!("У сети '{network_name}' нет адреса шлюза"))?;
+        Ok(gateway)
+    }
+
+    /// Address the platform reaches a replica at: the published loopback
+    /// port, or the container address for isolated functions.
+    pub async fn replica_addr(
+        &self,
+        container_id: &str,
+        template: &ContainerCreateBody,
+        inner_port: u16,
+    ) -> Result<SocketAddr> {
+        let host_config = template.host_config.as_ref();
+        let published = host_config
+            .and_then(|config| config.port_bindings.as_ref())
+            .is_some_and(|bindings| !bindings.is_empty());
+        if published {
+            let host_port = self.get_published_host_port(container_id, inner_port).await?;
+            return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, host_port)));
+        }
+
+        let network_name = host_config
+            .and_then(|config| config.network_mode.clone())
+            .ok_or_else(|| anyhow!("У шаблона контейнера {container_id} не задана сеть"))?;
+        let ip_address = self
+            .docker
+            .inspect_container(
+                container_id,
+                None::<query_parameters::InspectContainerOptions>,
+            )
+            .await?
+            .network_settings
+            .and_then(|settings| settings.networks)
+            .and_then(|mut networks| networks.remove(&network_name))
+            .and_then(|endpoint| endpoint.ip_address)
+            .filter(|ip_address| !ip_address.is_empty())
+            .ok_or_else(|| anyhow!("Контейнер {container_id} не получил адрес в сети '{network_name}'"))?;
+        let ip_address = ip_address
+            .parse::<IpAddr>()
+            .map_err(|e| anyhow!("Invalid address '{ip_address}' for container {container_id}: {e}"))?;
+        Ok(SocketAddr::new(ip_address, inner_port))
+    }
+
     pub async fn setup_function_template(
         &self,
         image_name: &str,
         function_config: &FunctionConfig,
+        env: Vec<String>,
+        gateway: IpAddr,
     ) -> Result<ContainerCreateBody> {
         let resource_name = Self::resource_name_from_image_name(image_name);
-        self.create_network_if_not_exists(&resource_name).await?;
-        self.create_shared_volume_if_not_exists(&resource_name)
+        let network = &function_config.network;
+        let network_name = network.function_network_name(&resource_name);
+        let volume = &function_config.volume;
+        let volume_name = volume.volume_name(&function_config.name, &function_config.version);
+        self.create_shared_volume_if_not_exists(&volume_name, &function_config.name, volume.retention)
             .await?;
+        let image_user = self.image_user(image_name).await?;
+        let user = function_config.security.effective_user(image_user.as_deref());
+        // Images without `chown` keep the volume as it is.
+        if let Err(e) = self.chown_shared_volume(image_name, &volume_name, &user).await {
+            warn!("Failed to hand volume '{volume_name}' to user '{user}': {e}");
+        }
         let mounts = vec![Mount {
-            target: Some("/shared_data".to_string()),
-            source: Some(resource_name.clone()),
+            target: Some(SHARED_DATA_PATH.to_string()),
+            source: Some(volume_name),
             typ: Some(bollard::secret::MountTypeEnum::VOLUME),
             ..Default::default()
         }];
-        let host_config = HostConfig {
-            mounts: Some(mounts),
-            network_mode: Some(resource_name),
-            port_bindings: Some(HashMap::from([(
+        // Docker does not publish ports of containers on internal networks.
+        let port_bindings = (!network.isolated()).then(|| {
+            HashMap::from([(
                 format!("{}/tcp", function_config.inner_port),
                 Some(vec![PortBinding {
-                    host_ip: Some("0.0.0.0".to_string()),
+                    host_ip: Some(Ipv4Addr::LOCALHOST.to_string()),
                     host_port: Some("0".to_string()),
                 }]),
-            )])),
+            )])
+        });
+        let mut endpoints = HashMap::from([(network_name.clone(), EndpointSettings::default())]);
+        for shared_network in network.shared_network_names() {
+            endpoints.insert(
+                shared_network,
+                EndpointSettings {
+                    aliases: Some(vec![function_config.name.clone()]),
+                    ..Default::default()
+                },
+            );
+        }
+        let mut host_config = HostConfig {
+            mounts: Some(mounts),
+            network_mode: Some(network_name),
+            port_bindings,
+            extra_hosts: Some(vec![format!("{PLATFORM_HOST}:{gateway}")]),
             memory: Some(function_config.memory * MB_TO_BYTES),
             ..Default::default()
         };
+        function_config.resources.apply_to(&mut host_config);
+        function_config
+            .security
+            .apply_to(&function_config.name, &mut host_config)
+            .await?;
         info!("Creating container template for '{image_name}'");
         let exposed_ports = HashMap::from([(format!("{}/tcp", function_config.inner_port), HashMap::new())]);
         Ok(ContainerCreateBody {
@@ -311,15 +905,88 @@ impl ContainerManager {
             labels: Some(managed_container_labels()),
             host_config: Some(host_config),
             exposed_ports: Some(exposed_ports),
+            env: Some(env),
+            user: Some(user),
+            networking_config: Some(NetworkingConfig {
+                endpoints_config: Some(endpoints),
+            }),
             ..Default::default()
         })
     }
 
+    /// `USER` of a local image, `None` when the image does not set one.
+    async fn image_user(&self, image_name: &str) -> Result<Option<String>> {
+        let image = self.docker.inspect_image(image_name).await?;
+        Ok(image
+            .config
+            .and_then(|config| config.user)
+            .filter(|user| !user.trim().is_empty()))
+    }
+
+    /// Hands the shared volume to the user replicas run as. Docker creates
+    /// volumes owned by root, and kept volumes may hold files of an earlier
+    /// user, so `chown -R` runs as root in a one-shot container of the
+    /// function image, without network access.
+    async fn chown_shared_volume(&self, image_name: &str, volume_name: &str, user: &str) -> Result<()> {
+        let config = ContainerCreateBody {
+            image: Some(image_name.to_string()),
+            labels: Some(managed_container_labels()),
+            user: Some("0:0".to_string()),
+            entrypoint: Some(vec![
+                "chown".to_string(),
+                "-R".to_string(),
+                user.to_string(),
+                SHARED_DATA_PATH.to_string(),
+            ]),
+            host_config: Some(HostConfig {
+                mounts: Some(vec![Mount {
+                    target: Some(SHARED_DATA_PATH.to_string()),
+                    source: Some(volume_name.to_string()),
+                    typ: Some(bollard::secret::MountTypeEnum::VOLUME),
+                    ..Default::default()
+                }]),
+                network_mode: Some("none".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let container_id = self.create_container_from_template(&config, image_name).await?;
+        let finished = async {
+            self.start_container(&container_id).await?;
+            let mut wait = self
+                .docker
+                .wait_container(&container_id, None::<query_parameters::WaitContainerOptions>);
+            while let Some(status) = wait.next().await {
+                status.map_err(|e| anyhow!("chown тома '{volume_name}' для '{user}' не удался: {e}"))?;
+            }
+            Ok(())
+        }
+        .await;
+        self.remove_container(&container_id).await;
+        finished
+    }
+
+    /// Build hash label of a local image, `None` when the image is missing or
+    /// was built without one.
+    pub async fn image_build_hash(&self, image_name: &str) -> Result<Option<String>> {
+        match self.docker.inspect_image(image_name).await {
+            Ok(image) => Ok(image
+                .config
+                .and_then(|config| config.labels)
+                .and_then(|mut labels| labels.remove(BUILD_HASH_LABEL))),
+            Err(e) if is_not_found(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     pub async fn build_image(
         &self,
         context_path: &str,
         image_name: &str,
         dockerfile_path: &str,
+        function_name: &str,
+        version: &str,
+        build_hash: &str,
     ) -> Result<()> {
         info!(
             "Building image '{image_name}' with dockerfile '{dockerfile_path}' from '{context_path}'"
@@ -328,13 +995,19 @@ impl ContainerManager {
             .create_build_context(context_path)
             .await
             .map_err(|e| anyhow!("Failed to create_build_context: {e}"))?;
+        let mut labels = function_resource_labels(function_name);
+        labels.insert(VERSION_LABEL.to_string(), version.to_string());
+        labels.insert(BUILD_HASH_LABEL.to_string(), build_hash.to_string());
         let build_image_options = BuildImageOptionsBuilder::new()
             .dockerfile(dockerfile_path)
             .t(image_name)
+            .labels(&labels)
             .rm(true)
             .forcerm(true)
             .build();
-        let bytes = Self::tar_to_bytes(&tar_path).await?;
+        let bytes = Self::tar_to_bytes(&tar_path).await;
+        let _ = tokio::fs::remove_file(&tar_path).await;
+        let bytes = bytes?;
         let mut image =
             self.docker
                 .build_image(build_image_options, None, Some(body_full(bytes.into())));
@@ -396,12 +1069,7 @@ impl ContainerManager {
     }
 
     async fn create_build_context(&self, path: &str) -> Result<String> {
-        let temp_dir = if cfg!(target_os = "windows") {
-            PathBuf::from(std::env::var("TEMP").unwrap_or_else(|_| "C:\\Windows\\Temp".to_string()))
-        } else {
-            PathBuf::from("/tmp")
-        };
-        let tar_path = temp_dir.join(format!("build-context-{}", uuid::Uuid::now_v7()));
+        let tar_path = build_context_dir().join(format!("{BUILD_CONTEXT_PREFIX}{}", uuid::Uuid::now_v7()));
         let output = tokio::process::Command::new("tar")
             .arg("-cf")
             .arg(&tar_path)
@@ -0,0 +1,418 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{info, warn};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, copy_bidirectional},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+use crate::network::EgressRule;
+
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type SharedRules = Arc<RwLock<Vec<EgressRule>>>;
+
+/// A forward proxy listening on the gateway of one network of an `egress`
+/// function, the only address replicas on that network can reach.
+#[derive(Debug)]
+struct RunningProxy {
+    addr: SocketAddr,
+    rules: SharedRules,
+    task: JoinHandle<()>,
+}
+
+impl Drop for RunningProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Proxies of one function. Every version gets its own network, so during
+/// a deploy the replicas of the old version keep using the proxy on the old
+/// gateway until the new ones are up; `current` is the one new replicas get.
+#[derive(Debug, Default)]
+struct FunctionProxies {
+    listeners: HashMap<IpAddr, RunningProxy>,
+    current: Option<IpAddr>,
+}
+
+/// Proxy state of a function before a deploy, to put back if it fails.
+#[derive(Debug)]
+pub struct EgressSnapshot {
+    function_name: String,
+    current: Option<IpAddr>,
+    rules: HashMap<IpAddr, Vec<EgressRule>>,
+}
+
+/// Egress proxies of all running `egress` functions.
+#[derive(Debug, Default)]
+pub struct EgressProxies(Mutex<HashMap<String, FunctionProxies>>);
+
+impl EgressProxies {
+    fn proxies(&self) -> MutexGuard<'_, HashMap<String, FunctionProxies>> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Makes the proxy on `bind_ip` the one new replicas of the function
+    /// use. A running proxy on that address only gets the new rules, so its
+    /// connections and port survive; proxies on other gateways are kept.
+    pub async fn start(&self, function_name: &str, bind_ip: IpAddr, rules: Vec<EgressRule>) -> Result<SocketAddr> {
+        if let Some(function) = self.proxies().get_mut(function_name)
+            && let Some(proxy) = function.listeners.get(&bind_ip)
+        {
+            *write_rules(&proxy.rules) = rules;
+            function.current = Some(bind_ip);
+            info!("Egress proxy for '{function_name}' on {} got new rules", proxy.addr);
+            return Ok(proxy.addr);
+        }
+
+        let listener = TcpListener::bind((bind_ip, 0)).await.with_context(|| {
+            format!("Не удалось запустить egress-прокси функции '{function_name}' на {bind_ip}")
+        })?;
+        let addr = listener.local_addr()?;
+        let rules = Arc::new(RwLock::new(rules));
+        let task = tokio::spawn(serve(listener, function_name.to_string(), Arc::clone(&rules)));
+        info!("Egress proxy for '{function_name}' listening on {addr}");
+        let mut proxies = self.proxies();
+        let function = proxies.entry(function_name.to_string()).or_default();
+        function
+            .listeners
+            .insert(bind_ip, RunningProxy { addr, rules, task });
+        function.current = Some(bind_ip);
+        Ok(addr)
+    }
+
+    /// Address of the proxy new replicas of the function use.
+    pub fn addr(&self, function_name: &str) -> Option<SocketAddr> {
+        let proxies = self.proxies();
+        let function = proxies.get(function_name)?;
+        function
+            .current
+            .and_then(|bind_ip| function.listeners.get(&bind_ip))
+            .map(|proxy| proxy.addr)
+    }
+
+    pub fn snapshot(&self, function_name: &str) -> EgressSnapshot {
+        let proxies = self.proxies();
+        let function = proxies.get(function_name);
+        EgressSnapshot {
+            function_name: function_name.to_string(),
+            current: function.and_then(|function| function.current),
+            rules: function
+                .map(|function| {
+                    function
+                        .listeners
+                        .iter()
+                        .map(|(bind_ip, proxy)| (*bind_ip, read_rules(&proxy.rules).clone()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Undoes `start` calls made after `snapshot`: proxies started since are
+    /// stopped and the others get their old rules back.
+    pub fn restore(&self, snapshot: EgressSnapshot) {
+        let mut proxies = self.proxies();
+        let Some(function) = proxies.get_mut(&snapshot.function_name) else {
+            return;
+        };
+        function
+            .listeners
+            .retain(|bind_ip, _| snapshot.rules.contains_key(bind_ip));
+        for (bind_ip, rules) in snapshot.rules {
+            if let Some(proxy) = function.listeners.get(&bind_ip) {
+                *write_rules(&proxy.rules) = rules;
+            }
+        }
+        function.current = snapshot.current;
+        if function.listeners.is_empty() {
+            proxies.remove(&snapshot.function_name);
+        }
+    }
+
+    /// Stops the proxies of the function's previous networks once no
+    /// replica uses them.
+    pub fn retain_current(&self, function_name: &str) {
+        if let Some(function) = self.proxies().get_mut(function_name) {
+            let current = function.current;
+            function
+                .listeners
+                .retain(|bind_ip, _| Some(*bind_ip) == current);
+        }
+    }
+
+    pub fn stop(&self, function_name: &str) {
+        self.proxies().remove(function_name);
+    }
+}
+
+fn read_rules(rules: &SharedRules) -> RwLockReadGuard<'_, Vec<EgressRule>> {
+    rules.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write_rules(rules: &SharedRules) -> RwLockWriteGuard<'_, Vec<EgressRule>> {
+    rules.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+async fn serve(listener: TcpListener, function_name: String, rules: SharedRules) {
+    loop {
+        let client = match listener.accept().await {
+            Ok((client, _)) => client,
+            Err(e) => {
+                warn!("Egress proxy of '{function_name}' failed to accept: {e}");
+                continue;
+            }
+        };
+        let function_name = function_name.clone();
+        // Each connection is checked against the rules current when it arrives.
+        let rules = read_rules(&rules).clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(client, &function_name, &rules).await {
+                warn!("Egress proxy of '{function_name}': {e}");
+            }
+        });
+    }
+}
+
+/// Where a proxied request goes and what to send upstream first.
+#[derive(Debug, PartialEq)]
+struct ProxyRequest {
+    host: String,
+    port: u16,
+    /// `CONNECT` tunnels; otherwise a plain HTTP request rewritten to
+    /// origin form.
+    tunnel: bool,
+    upstream_head: Vec<u8>,
+}
+
+async fn handle(mut client: TcpStream, function_name: &str, rules: &[EgressRule]) -> Result<()> {
+    let (head, rest) = read_head(&mut client).await?;
+    let request = match parse_request(&head) {
+        Ok(request) => request,
+        Err(e) => {
+            respond(&mut client, "400 Bad Request").await;
+            return Err(e);
+        }
+    };
+    if !rules.iter().any(|rule| rule.allows(&request.host, request.port)) {
+        respond(&mut client, "403 Forbidden").await;
+        bail!("blocked egress to {}:{}", request.host, request.port);
+    }
+
+    let connect = TcpStream::connect((request.host.as_str(), request.port));
+    let mut upstream = match tokio::time::timeout(UPSTREAM_CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(upstream)) => upstream,
+        Ok(Err(e)) => {
+            respond(&mut client, "502 Bad Gateway").await;
+            return Err(anyhow!("{}:{} is unreachable: {e}", request.host, request.port));
+        }
+        Err(_) => {
+            respond(&mut client, "504 Gateway Timeout").await;
+            bail!("{}:{} did not accept the connection in time", request.host, request.port);
+        }
+    };
+    if request.tunnel {
+        client
+            .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+            .await?;
+    } else {
+        upstream.write_all(&request.upstream_head).await?;
+    }
+    upstream.write_all(&rest).await?;
+    info!(
+        "Egress of '{function_name}' to {}:{} allowed",
+        request.host, request.port
+    );
+    copy_bidirectional(&mut client, &mut upstream).await?;
+    Ok(())
+}
+
+/// Reads up to the end of the request head; returns the head and whatever
+/// the client already sent after it.
+async fn read_head(client: &mut TcpStream) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut buffer = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = client.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("client closed the connection before sending a request");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            let rest = buffer.split_off(end + 4);
+            return Ok((buffer, rest));
+        }
+        if buffer.len() > MAX_REQUEST_HEAD {
+            bail!("request head is too large");
+        }
+    }
+}
+
+fn parse_request(head: &[u8]) -> Result<ProxyRequest> {
+    let head = std::str::from_utf8(head).context("request head is not utf-8")?;
+    let (request_line, headers) = head.split_once("\r\n").unwrap_or((head, ""));
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next()) else {
+        bail!("malformed request line '{request_line}'");
+    };
+
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_host_port(target, None)?;
+        return Ok(ProxyRequest {
+            host,
+            port,
+            tunnel: true,
+            upstream_head: Vec::new(),
+        });
+    }
+
+    let Some(without_scheme) = target.strip_prefix("http://") else {
+        bail!("only CONNECT and absolute http:// requests are proxied, got '{target}'");
+    };
+    let (authority, path) = match without_scheme.find('/') {
+        Some(index) => without_scheme.split_at(index),
+        None => (without_scheme, "/"),
+    };
+    let (host, port) = split_host_port(authority, Some(80))?;
+    Ok(ProxyRequest {
+        host,
+        port,
+        tunnel: false,
+        upstream_head: format!("{method} {path} {version}\r\n{headers}").into_bytes(),
+    })
+}
+
+fn split_host_port(authority: &str, default_port: Option<u16>) -> Result<(String, u16)> {
+    let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+        let (host, rest) = bracketed
+            .split_once(']')
+            .ok_or_else(|| anyhow!("malformed authority '{authority}'"))?;
+        (host, rest.strip_prefix(':'))
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    let port = match port {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| anyhow!("malformed port in '{authority}'"))?,
+        None => default_port.ok_or_else(|| anyhow!("missing port in '{authority}'"))?,
+    };
+    if host.is_empty() {
+        bail!("missing host in '{authority}'");
+    }
+    Ok((host.to_string(), port))
+}
+
+async fn respond(client: &mut TcpStream, status: &str) {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    let _ = client.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::network::EgressRule;
+
+    use super::{EgressProxies, parse_request};
+
+    #[test]
+    fn plain_requests_are_rewritten_to_origin_form() {
+        let request =
+            parse_request(b"GET http://api.example.com:8080/v1?q=1 HTTP/1.1\r\nHost: api.example.com\r\n\r\n")
+                .unwrap();
+        assert_eq!((request.host.as_str(), request.port, request.tunnel), ("api.example.com", 8080, false));
+        assert_eq!(
+            request.upstream_head,
+            b"GET /v1?q=1 HTTP/1.1\r\nHost: api.example.com\r\n\r\n"
+        );
+
+        let request = parse_request(b"CONNECT [::1]:443 HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!((request.host.as_str(), request.port, request.tunnel), ("::1", 443, true));
+        assert!(parse_request(b"GET https://a.io/ HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn tunnels_only_to_allowed_destinations() {
+        let upstream = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let upstream_port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = upstream.accept().await {
+                let _ = stream.write_all(b"pong").await;
+            }
+        });
+
+        let proxies = EgressProxies::default();
+        let rule = EgressRule::parse(&format!("localhost:{upstream_port}")).unwrap();
+        let proxy = proxies
+            .start("example", IpAddr::V4(Ipv4Addr::LOCALHOST), vec![rule])
+            .await
+            .unwrap();
+        assert_eq!(proxies.addr("example"), Some(proxy));
+
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        let request = format!("CONNECT localhost:{upstream_port} HTTP/1.1\r\n\r\n");
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("pong"), "{response}");
+
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        let request = format!("CONNECT 127.0.0.1:{upstream_port} HTTP/1.1\r\n\r\n");
+        client.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+
+        proxies.stop("example");
+        assert_eq!(proxies.addr("example"), None);
+    }
+
+    #[tokio::test]
+    async fn redeploys_keep_running_proxies_until_they_succeed() {
+        let proxies = EgressProxies::default();
+        let old_gateway = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let new_gateway = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        let rule = |host: &str| vec![EgressRule::parse(host).unwrap()];
+
+        let first = proxies.start("example", old_gateway, rule("a.example.com")).await.unwrap();
+        let same = proxies.start("example", old_gateway, rule("b.example.com")).await.unwrap();
+        assert_eq!(first, same, "new rules must not move the proxy");
+
+        let before = proxies.snapshot("example");
+        let next = proxies.start("example", new_gateway, rule("c.example.com")).await.unwrap();
+        assert_eq!(proxies.addr("example"), Some(next));
+        assert!(TcpStream::connect(first).await.is_ok(), "old replicas keep their proxy");
+        proxies.restore(before);
+        assert_eq!(proxies.addr("example"), Some(first));
+        assert_eq!(proxies.snapshot("example").rules[&old_gateway], rule("b.example.com"));
+        assert!(!proxies.snapshot("example").rules.contains_key(&new_gateway));
+
+        proxies.start("example", new_gateway, rule("c.example.com")).await.unwrap();
+        proxies.retain_current("example");
+        assert_eq!(proxies.snapshot("example").rules.keys().collect::<Vec<_>>(), [&new_gateway]);
+
+        let before = proxies.snapshot("first-deploy");
+        proxies.start("first-deploy", old_gateway, rule("a.example.com")).await.unwrap();
+        proxies.restore(before);
+        assert_eq!(proxies.addr("first-deploy"), None);
+    }
+}
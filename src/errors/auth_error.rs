@@ -0,0 +1,19 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Требуется API-ключ")]
+    Unauthorized,
+    #[error("Недействительный API-ключ")]
+    InvalidKey,
+    #[error("Требуется bearer-токен или API-ключ")]
+    MissingCredentials,
+    #[error("Недействительный токен: {0}")]
+    InvalidToken(String),
+    #[error("Недостаточно прав: требуется scope '{0}'")]
+    Forbidden(String),
+    #[error("Некорректный scope '{0}'")]
+    InvalidScope(String),
+    #[error("API-ключ '{0}' не найден")]
+    KeyNotFound(String),
+}
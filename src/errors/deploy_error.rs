@@ -4,4 +4,12 @@ use thiserror::Error;
 pub enum DeployError {
     #[error("General Docker error: {0:?}")]
     DockerGeneral(Vec<bollard::errors::Error>),
+    #[error(
+        "Контейнер функции '{function}' завершился сразу после запуска (код {exit_code}). Вероятно, образ не может работать с профилем 'security' из function.json: проверьте readOnlyRootfs, tmpfs, user и capAdd. Вывод контейнера:\n{logs}"
+    )]
+    ContainerExited {
+        function: String,
+        exit_code: i64,
+        logs: String,
+    },
 }
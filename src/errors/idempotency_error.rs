@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum IdempotencyError {
+    #[error("Idempotency-Key должен содержать от 1 до 255 символов")]
+    InvalidKey,
+    #[error("Запрос с Idempotency-Key '{0}' еще выполняется")]
+    InProgress(String),
+    #[error("Idempotency-Key '{0}' уже использован с другим телом запроса")]
+    KeyReused(String),
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum InvokeChainError {
+    #[error("Не передан токен вызова (заголовок X-Serverless-Invoke-Token)")]
+    MissingToken,
+    #[error("Некорректный токен вызова")]
+    InvalidToken,
+    #[error("Срок действия токена вызова истек")]
+    Expired,
+    #[error("Цепочка вызовов '{chain_id}' превысила максимальную глубину {max_depth}")]
+    DepthExceeded { chain_id: String, max_depth: u32 },
+}
@@ -0,0 +1,24 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LimitError {
+    #[error("Превышен лимит запросов для '{scope}'")]
+    RateLimited { scope: String, retry_after_secs: u64 },
+    #[error("Превышен лимит одновременных вызовов для '{scope}'")]
+    ConcurrencyLimited { scope: String, retry_after_secs: u64 },
+    #[error("Очередь вызовов функции '{function}' переполнена")]
+    QueueFull { function: String, retry_after_secs: u64 },
+    #[error("Истекло время ожидания свободной реплики функции '{function}'")]
+    QueueTimeout { function: String, retry_after_secs: u64 },
+}
+
+impl LimitError {
+    pub fn retry_after_secs(&self) -> u64 {
+        match self {
+            Self::RateLimited { retry_after_secs, .. }
+            | Self::ConcurrencyLimited { retry_after_secs, .. }
+            | Self::QueueFull { retry_after_secs, .. }
+            | Self::QueueTimeout { retry_after_secs, .. } => *retry_after_secs,
+        }
+    }
+}
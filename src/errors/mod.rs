@@ -1,14 +1,26 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
 
-use crate::errors::function_error::FunctionError;
+use crate::errors::{
+    auth_error::AuthError, function_error::FunctionError, idempotency_error::IdempotencyError,
+    invoke_chain_error::InvokeChainError, limit_error::LimitError, secret_error::SecretError,
+    state_error::StateError, volume_error::VolumeError, workflow_error::WorkflowError,
+};
 
+pub mod auth_error;
 pub mod deploy_error;
 pub mod function_error;
+pub mod idempotency_error;
+pub mod invoke_chain_error;
+pub mod limit_error;
+pub mod secret_error;
+pub mod state_error;
+pub mod volume_error;
+pub mod workflow_error;
 
 #[derive(Debug, Serialize)]
 pub struct ApiError {
@@ -21,6 +33,9 @@ pub struct ApiError {
 pub struct ApiErrorResponse {
     #[serde(skip_serializing)]
     pub status: StatusCode,
+    /// Sent as `Retry-After` when the caller should back off.
+    #[serde(skip_serializing)]
+    pub retry_after_secs: Option<u64>,
     pub error: ApiError,
 }
 
@@ -33,6 +48,7 @@ impl ApiErrorResponse {
     ) -> Self {
         Self {
             status,
+            retry_after_secs: None,
             error: ApiError {
                 code: code.into(),
                 message: message.into(),
@@ -44,18 +60,27 @@ impl ApiErrorResponse {
 
 impl IntoResponse for ApiErrorResponse {
     fn into_response(self) -> Response {
-        (self.status, Json(self)).into_response()
+        let retry_after = self.retry_after_secs;
+        let mut response = (self.status, Json(self)).into_response();
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(RETRY_AFTER, secs.into());
+        }
+        response
     }
 }
 
 pub fn serialize_err(e: anyhow::Error) -> ApiErrorResponse {
     let (status, code) = error_meta(&e);
-    ApiErrorResponse::new(
+    let mut response = ApiErrorResponse::new(
         status,
         code,
         e.to_string(),
         e.chain().skip(1).map(|cause| cause.to_string()).collect(),
-    )
+    );
+    response.retry_after_secs = e
+        .downcast_ref::<LimitError>()
+        .map(LimitError::retry_after_secs);
+    response
 }
 
 fn error_meta(error: &anyhow::Error) -> (StatusCode, &'static str) {
@@ -68,6 +93,86 @@ fn error_meta(error: &anyhow::Error) -> (StatusCode, &'static str) {
         };
     }
 
+    if let Some(auth_error) = error.downcast_ref::<AuthError>() {
+        return match auth_error {
+            AuthError::Unauthorized
+            | AuthError::InvalidKey
+            | AuthError::MissingCredentials
+            | AuthError::InvalidToken(_) => {
+                (StatusCode::UNAUTHORIZED, "UNAUTHORIZED")
+            }
+            AuthError::Forbidden(_) => (StatusCode::FORBIDDEN, "FORBIDDEN"),
+            AuthError::InvalidScope(_) => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+            AuthError::KeyNotFound(_) => (StatusCode::NOT_FOUND, "NOT_FOUND"),
+        };
+    }
+
+    if let Some(limit_error) = error.downcast_ref::<LimitError>() {
+        return match limit_error {
+            LimitError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, "RATE_LIMITED"),
+            LimitError::ConcurrencyLimited { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, "CONCURRENCY_LIMITED")
+            }
+            LimitError::QueueFull { .. } => (StatusCode::SERVICE_UNAVAILABLE, "QUEUE_FULL"),
+            LimitError::QueueTimeout { .. } => (StatusCode::SERVICE_UNAVAILABLE, "QUEUE_TIMEOUT"),
+        };
+    }
+
+    if let Some(idempotency_error) = error.downcast_ref::<IdempotencyError>() {
+        return match idempotency_error {
+            IdempotencyError::InvalidKey => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+            IdempotencyError::InProgress(_) => (StatusCode::CONFLICT, "IDEMPOTENCY_IN_PROGRESS"),
+            IdempotencyError::KeyReused(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "IDEMPOTENCY_KEY_REUSED")
+            }
+        };
+    }
+
+    if let Some(secret_error) = error.downcast_ref::<SecretError>() {
+        return match secret_error {
+            SecretError::Disabled => (StatusCode::SERVICE_UNAVAILABLE, "SECRETS_DISABLED"),
+            SecretError::NotFound(_) => (StatusCode::NOT_FOUND, "NOT_FOUND"),
+            SecretError::InvalidName(_) => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+            SecretError::InUse(..) => (StatusCode::CONFLICT, "SECRET_IN_USE"),
+            SecretError::Corrupted(_) => (StatusCode::INTERNAL_SERVER_ERROR, "SECRET_CORRUPTED"),
+        };
+    }
+
+    if let Some(chain_error) = error.downcast_ref::<InvokeChainError>() {
+        return match chain_error {
+            InvokeChainError::MissingToken
+            | InvokeChainError::InvalidToken
+            | InvokeChainError::Expired => (StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+            InvokeChainError::DepthExceeded { .. } => (StatusCode::LOOP_DETECTED, "CALL_DEPTH_EXCEEDED"),
+        };
+    }
+
+    if let Some(state_error) = error.downcast_ref::<StateError>() {
+        return match state_error {
+            StateError::InvalidKey(_) => (StatusCode::BAD_REQUEST, "BAD_REQUEST"),
+            StateError::NotFound(_) => (StatusCode::NOT_FOUND, "NOT_FOUND"),
+            StateError::ValueTooLarge { .. } => (StatusCode::PAYLOAD_TOO_LARGE, "STATE_VALUE_TOO_LARGE"),
+            StateError::QuotaExceeded { .. } => (StatusCode::INSUFFICIENT_STORAGE, "STATE_QUOTA_EXCEEDED"),
+            StateError::NotInteger(_) => (StatusCode::CONFLICT, "STATE_NOT_INTEGER"),
+        };
+    }
+
+    if let Some(volume_error) = error.downcast_ref::<VolumeError>() {
+        return match volume_error {
+            VolumeError::NoVolume(_) => (StatusCode::CONFLICT, "VOLUME_UNAVAILABLE"),
+            VolumeError::SnapshotNotFound(_) | VolumeError::UnknownFunction(_) => (StatusCode::NOT_FOUND, "NOT_FOUND"),
+        };
+    }
+
+    if let Some(workflow_error) = error.downcast_ref::<WorkflowError>() {
+        return match workflow_error {
+            WorkflowError::NotFound(_) | WorkflowError::ExecutionNotFound(_) => {
+                (StatusCode::NOT_FOUND, "NOT_FOUND")
+            }
+            WorkflowError::InvalidDefinition(..) => (StatusCode::BAD_REQUEST, "INVALID_WORKFLOW"),
+        };
+    }
+
     if error.downcast_ref::<redis::RedisError>().is_some() {
         return (StatusCode::INTERNAL_SERVER_ERROR, "REDIS_ERROR");
     }
@@ -91,3 +196,27 @@ fn error_meta(error: &anyhow::Error) -> (StatusCode, &'static str) {
 
     (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL_ERROR")
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{StatusCode, header::RETRY_AFTER},
+        response::IntoResponse,
+    };
+
+    use super::{limit_error::LimitError, serialize_err};
+
+    #[test]
+    fn limit_errors_are_429_with_retry_after() {
+        let error = LimitError::RateLimited {
+            scope: "function:example".to_string(),
+            retry_after_secs: 3,
+        };
+        let response = serialize_err(error.into());
+        assert_eq!(response.error.code, "RATE_LIMITED");
+
+        let response = response.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "3");
+    }
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("Хранилище секретов отключено: не задан SERVERLESS_SECRETS_KEY")]
+    Disabled,
+    #[error("Секрет '{0}' не найден")]
+    NotFound(String),
+    #[error("Некорректное имя секрета '{0}': допустимы латинские буквы, цифры, '-', '_' и '.'")]
+    InvalidName(String),
+    #[error("Секрет '{0}' используется функциями: {1}")]
+    InUse(String, String),
+    #[error("Не удалось расшифровать секрет '{0}'")]
+    Corrupted(String),
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Некорректный ключ состояния '{0}'")]
+    InvalidKey(String),
+    #[error("Ключ состояния '{0}' не найден")]
+    NotFound(String),
+    #[error("Значение занимает {size} байт, функции '{function}' разрешено не больше {max}")]
+    ValueTooLarge { function: String, size: usize, max: usize },
+    #[error("Функция '{function}' достигла лимита в {max_keys} ключей состояния")]
+    QuotaExceeded { function: String, max_keys: u32 },
+    #[error("Значение ключа состояния '{0}' не является целым числом")]
+    NotInteger(String),
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VolumeError {
+    #[error("У функции '{0}' нет тома: тома есть только у функций с runtime 'docker'")]
+    NoVolume(String),
+    #[error("Снимок тома '{0}' не найден")]
+    SnapshotNotFound(String),
+    #[error("Функция '{0}' не найдена")]
+    UnknownFunction(String),
+}
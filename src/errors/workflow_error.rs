@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum WorkflowError {
+    #[error("Workflow '{0}' не найден")]
+    NotFound(String),
+    #[error("Некорректное описание workflow '{0}': {1}")]
+    InvalidDefinition(String, String),
+    #[error("Выполнение workflow '{0}' не найдено")]
+    ExecutionNotFound(String),
+}
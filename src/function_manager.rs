@@ -1,17 +1,40 @@
 use crate::{
+    admission::{Admission, AdmissionLimits},
     balancers::{LoadBalancingKind, LoadBalancingStrategy, create_balancer},
+    build_hash::build_context_hash,
     container_manager::ContainerManager,
     deployed_functions::DeployedFunctions,
-    errors::function_error::FunctionError,
+    egress_proxy::EgressProxies,
+    gc::{GcFailure, GcItem, GcItemKind, GcPolicy, GcReport, GcSettings, classify_images, plan_image_removals},
+    errors::{deploy_error::DeployError, function_error::FunctionError, volume_error::VolumeError},
+    invoke_chain::{CallChain, INTERNAL_URL_ENV, InvokeTokens, PLATFORM_HOST, internal_url},
+    invoke_cache::{InvokeCacheSettings, ResponseCacheConfig},
+    jwt::JwtConfig,
+    limits::InvokeLimits,
+    metrics::{FunctionMetricsReport, Metrics, StartMode, StartPhases},
+    network::{NetworkConfig, NetworkMode},
+    process_manager::ProcessManager,
     redis_manager::RedisManager,
+    resources::ResourceLimits,
+    secrets::{SecretRef, SecretStore},
+    security::SecurityConfig,
+    state::StateConfig,
+    volume::{FUNCTION_LABEL, RETENTION_LABEL, SnapshotInfo, SnapshotStore, VolumeConfig, VolumeRetention},
+    wasm_runtime::WasmRuntime,
 };
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use bollard::secret::ContainerCreateBody;
-use log::info;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::RwLock;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{Mutex, OnceCell, RwLock};
 
 fn default_replicas() -> u16 {
     1
@@ -21,26 +44,115 @@ fn default_load_balancer() -> String {
     "round_robin".to_string()
 }
 
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_max_queue_length() -> u32 {
+    100
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_idempotency_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+const CHECKPOINT_DIR: &str = "serverless-checkpoints";
+/// Build contexts younger than this may belong to a build in progress.
+const STALE_BUILD_CONTEXT_AGE: Duration = Duration::from_secs(60 * 60);
+
 fn function_config_path(function_name: &str) -> String {
     format!("functions/{function_name}/function.json")
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FunctionRuntime {
+    #[default]
+    Docker,
+    Process,
+    Wasm,
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FunctionConfig {
     pub name: String,
+    #[serde(default)]
+    pub runtime: FunctionRuntime,
     #[serde(rename(serialize = "innerPort", deserialize = "innerPort"))]
     pub inner_port: u16,
     pub memory: i64,
+    #[serde(flatten)]
+    pub resources: ResourceLimits,
     pub timeout: u32,
     pub version: String,
+    #[serde(default)]
     pub dockerfile: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(default, rename = "workingDir", skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Environment variables resolved from the secret store when replicas
+    /// are created, e.g. `{"DB_PASSWORD": {"secretRef": "db-password"}}`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub secrets: HashMap<String, SecretRef>,
+    #[serde(default = "default_max_restarts", rename = "maxRestarts")]
+    pub max_restarts: u32,
     #[serde(default = "default_replicas")]
     pub replicas: u16,
     #[serde(default = "default_load_balancer", rename = "loadBalancer")]
     pub load_balancer: String,
     #[serde(default, rename = "replicaWeights")]
     pub replica_weights: Vec<usize>,
+    #[serde(default, rename = "warmPool")]
+    pub warm_pool: u16,
+    /// Experimental: restore new replicas from a CRIU checkpoint.
+    #[serde(default, rename = "checkpointRestore")]
+    pub checkpoint_restore: bool,
+    /// Lets `/invoke` through without an API key when auth is enabled.
+    #[serde(default, rename = "anonymousInvoke")]
+    pub anonymous_invoke: bool,
+    /// Bearer token verification for `/invoke`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<JwtConfig>,
+    /// Rate and concurrency quotas for `/invoke`, shared by all callers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<InvokeLimits>,
+    /// Enables the admission queue: invocations beyond
+    /// `replicas * maxConcurrencyPerReplica` wait for a free slot.
+    #[serde(default, rename = "maxConcurrencyPerReplica", skip_serializing_if = "Option::is_none")]
+    pub max_concurrency_per_replica: Option<u32>,
+    #[serde(default = "default_max_queue_length", rename = "maxQueueLength")]
+    pub max_queue_length: u32,
+    #[serde(default = "default_queue_timeout_ms", rename = "queueTimeoutMs")]
+    pub queue_timeout_ms: u64,
+    /// How long a response is kept for repeats with the same `Idempotency-Key`.
+    #[serde(default = "default_idempotency_ttl_secs", rename = "idempotencyTtlSecs")]
+    pub idempotency_ttl_secs: u64,
+    #[serde(default, rename = "responseCache", skip_serializing_if = "Option::is_none")]
+    pub response_cache: Option<ResponseCacheConfig>,
+    /// Hardening of container replicas; secure unless relaxed here.
+    #[serde(default)]
+    pub security: SecurityConfig,
+    /// Network isolation and egress policy of container replicas.
+    #[serde(default, skip_serializing_if = "NetworkConfig::is_default")]
+    pub network: NetworkConfig,
+    /// Quotas of the key/value state under `/internal/state`.
+    #[serde(default)]
+    pub state: StateConfig,
+    /// Retention of the `/shared_data` volume of container replicas.
+    #[serde(default, skip_serializing_if = "VolumeConfig::is_default")]
+    pub volume: VolumeConfig,
     #[serde(skip_deserializing, skip_serializing)]
     pub build_context_path: std::path::PathBuf,
 }
@@ -50,6 +162,8 @@ pub struct FunctionConfigUpdate {
     #[serde(rename = "innerPort")]
     pub inner_port: Option<u16>,
     pub memory: Option<i64>,
+    #[serde(flatten)]
+    pub resources: ResourceLimits,
     pub timeout: Option<u32>,
     pub version: Option<String>,
     pub dockerfile: Option<String>,
@@ -58,6 +172,37 @@ pub struct FunctionConfigUpdate {
     pub load_balancer: Option<String>,
     #[serde(rename = "replicaWeights")]
     pub replica_weights: Option<Vec<usize>>,
+    #[serde(rename = "warmPool")]
+    pub warm_pool: Option<u16>,
+    #[serde(rename = "checkpointRestore")]
+    pub checkpoint_restore: Option<bool>,
+    pub runtime: Option<FunctionRuntime>,
+    pub command: Option<Vec<String>>,
+    pub module: Option<String>,
+    #[serde(rename = "workingDir")]
+    pub working_dir: Option<String>,
+    pub env: Option<HashMap<String, String>>,
+    pub secrets: Option<HashMap<String, SecretRef>>,
+    #[serde(rename = "maxRestarts")]
+    pub max_restarts: Option<u32>,
+    #[serde(rename = "anonymousInvoke")]
+    pub anonymous_invoke: Option<bool>,
+    pub jwt: Option<JwtConfig>,
+    pub limits: Option<InvokeLimits>,
+    #[serde(rename = "maxConcurrencyPerReplica")]
+    pub max_concurrency_per_replica: Option<u32>,
+    #[serde(rename = "maxQueueLength")]
+    pub max_queue_length: Option<u32>,
+    #[serde(rename = "queueTimeoutMs")]
+    pub queue_timeout_ms: Option<u64>,
+    #[serde(rename = "idempotencyTtlSecs")]
+    pub idempotency_ttl_secs: Option<u64>,
+    #[serde(rename = "responseCache")]
+    pub response_cache: Option<ResponseCacheConfig>,
+    pub security: Option<SecurityConfig>,
+    pub network: Option<NetworkConfig>,
+    pub state: Option<StateConfig>,
+    pub volume: Option<VolumeConfig>,
 }
 
 impl FunctionConfig {
@@ -67,9 +212,105 @@ impl FunctionConfig {
         config.build_context_path = path.as_ref().parent().unwrap().to_path_buf();
         Ok(config)
     }
+
+    /// Checks settings that would otherwise only fail once replicas start.
+    pub fn validate(&self) -> Result<()> {
+        self.resources.validate(&self.name, self.memory)?;
+        self.state.validate(&self.name)?;
+        if self.runtime == FunctionRuntime::Docker {
+            self.security.validate(&self.name, self.inner_port)?;
+            self.network.validate(&self.name)?;
+        } else if !self.secrets.is_empty() {
+            bail!(
+                "Секреты поддерживаются только для функций с runtime 'docker', а не для '{}'",
+                self.name
+            );
+        } else if !self.network.is_default() {
+            bail!(
+                "Сетевая политика поддерживается только для функций с runtime 'docker', а не для '{}'",
+                self.name
+            );
+        } else if !self.volume.is_default() {
+            bail!(
+                "Настройки тома поддерживаются только для функций с runtime 'docker', а не для '{}'",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Command line for the `process` runtime: explicit `command` wins,
+    /// otherwise `entrypoint` is split on whitespace.
+    pub fn process_command(&self) -> Result<Vec<String>> {
+        if !self.command.is_empty() {
+            return Ok(self.command.clone());
+        }
+        let command = self
+            .entrypoint
+            .as_deref()
+            .map(|entrypoint| {
+                entrypoint
+                    .split_whitespace()
+                    .map(ToOwned::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if command.is_empty() {
+            bail!(
+                "Функция '{}' с runtime 'process' должна задавать 'command' или 'entrypoint'",
+                self.name
+            );
+        }
+        Ok(command)
+    }
+
+    /// Module path for the `wasm` runtime, relative to the function directory.
+    pub fn wasm_module(&self) -> Result<&str> {
+        self.module
+            .as_deref()
+            .or(self.entrypoint.as_deref())
+            .filter(|module| !module.trim().is_empty())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Функция '{}' с runtime 'wasm' должна задавать 'module'",
+                    self.name
+                )
+            })
+    }
 }
 
 impl FunctionConfigUpdate {
+    /// True when the update only resizes the function, so running replicas
+    /// can be kept and the warm pool used instead of a full redeploy.
+    pub fn is_scale_only(&self) -> bool {
+        self.inner_port.is_none()
+            && self.memory.is_none()
+            && self.resources.is_empty()
+            && self.timeout.is_none()
+            && self.version.is_none()
+            && self.dockerfile.is_none()
+            && self.load_balancer.is_none()
+            && self.replica_weights.is_none()
+            && self.checkpoint_restore.is_none()
+            && self.runtime.is_none()
+            && self.command.is_none()
+            && self.module.is_none()
+            && self.working_dir.is_none()
+            && self.env.is_none()
+            && self.secrets.is_none()
+            && self.security.is_none()
+            && self.network.is_none()
+            && self.volume.is_none()
+            && self.max_restarts.is_none()
+    }
+
+    /// True when the update only changes who may call the function, how often
+    /// and how calls are queued, which is applied to the running function
+    /// without touching replicas.
+    pub fn is_policy_only(&self) -> bool {
+        self.is_scale_only() && self.replicas.is_none() && self.warm_pool.is_none()
+    }
+
     pub fn apply_to(self, config: &mut FunctionConfig) {
         if let Some(value) = self.inner_port {
             config.inner_port = value;
@@ -77,6 +318,7 @@ impl FunctionConfigUpdate {
         if let Some(value) = self.memory {
             config.memory = value;
         }
+        config.resources.merge(self.resources);
         if let Some(value) = self.timeout {
             config.timeout = value;
         }
@@ -95,25 +337,168 @@ impl FunctionConfigUpdate {
         if let Some(value) = self.replica_weights {
             config.replica_weights = value;
         }
+        if let Some(value) = self.warm_pool {
+            config.warm_pool = value;
+        }
+        if let Some(value) = self.checkpoint_restore {
+            config.checkpoint_restore = value;
+        }
+        if let Some(value) = self.runtime {
+            config.runtime = value;
+        }
+        if let Some(value) = self.command {
+            config.command = value;
+        }
+        if let Some(value) = self.module {
+            config.module = Some(value);
+        }
+        if let Some(value) = self.working_dir {
+            config.working_dir = Some(value);
+        }
+        if let Some(value) = self.env {
+            config.env = value;
+        }
+        if let Some(value) = self.secrets {
+            config.secrets = value;
+        }
+        if let Some(value) = self.network {
+            config.network = value;
+        }
+        if let Some(value) = self.state {
+            config.state = value;
+        }
+        if let Some(value) = self.volume {
+            config.volume = value;
+        }
+        if let Some(value) = self.security {
+            config.security = value;
+        }
+        if let Some(value) = self.max_restarts {
+            config.max_restarts = value;
+        }
+        if let Some(value) = self.anonymous_invoke {
+            config.anonymous_invoke = value;
+        }
+        if let Some(value) = self.jwt {
+            config.jwt = Some(value);
+        }
+        if let Some(value) = self.limits {
+            config.limits = Some(value);
+        }
+        if let Some(value) = self.max_concurrency_per_replica {
+            config.max_concurrency_per_replica = (value > 0).then_some(value);
+        }
+        if let Some(value) = self.max_queue_length {
+            config.max_queue_length = value;
+        }
+        if let Some(value) = self.queue_timeout_ms {
+            config.queue_timeout_ms = value;
+        }
+        if let Some(value) = self.idempotency_ttl_secs {
+            config.idempotency_ttl_secs = value;
+        }
+        if let Some(value) = self.response_cache {
+            config.response_cache = (value.ttl_secs > 0).then_some(value);
+        }
     }
 }
 
 #[derive(Debug, Serialize)]
 pub struct RunningFunction {
     pub config: FunctionConfig,
-    pub container_config: ContainerCreateBody,
+    /// Holds resolved secret values in `env`, so it is never serialized.
+    #[serde(skip_serializing)]
+    pub container_config: Option<ContainerCreateBody>,
     pub container_ids: Vec<String>,
-    pub host_ports_by_container: HashMap<String, u16>,
+    /// Started containers kept out of the balancer for instant promotion.
+    pub warm_container_ids: Vec<String>,
+    pub replica_addrs: HashMap<String, SocketAddr>,
+    pub checkpoint: Option<CheckpointRef>,
+}
+
+impl RunningFunction {
+    fn admission_limits(&self) -> Option<AdmissionLimits> {
+        let per_replica = self.config.max_concurrency_per_replica? as usize;
+        Some(AdmissionLimits {
+            per_replica,
+            capacity: per_replica * self.container_ids.len(),
+            max_queue_length: self.config.max_queue_length as usize,
+            queue_timeout: Duration::from_millis(self.config.queue_timeout_ms),
+        })
+    }
+}
+
+/// A CRIU checkpoint stored outside the container so other containers of the
+/// same image can be started from it.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckpointRef {
+    pub dir: PathBuf,
+    pub name: String,
+}
+
+struct DeployedReplicas {
+    deployed_name: String,
+    container_config: Option<ContainerCreateBody>,
+    container_ids: Vec<String>,
+    warm_container_ids: Vec<String>,
+    replica_addrs: HashMap<String, SocketAddr>,
+    checkpoint: Option<CheckpointRef>,
+}
+
+struct ContainerTemplate {
+    container_config: ContainerCreateBody,
+    image_name: String,
+    inner_port: u16,
+    readiness_timeout: Duration,
+    checkpoint: Option<CheckpointRef>,
 }
 
 pub struct InvokeOutcome {
     pub container_id: String,
     pub result: Value,
+    /// Per-invocation instantiation time, reported by in-process runtimes.
+    pub instantiate_ms: Option<f64>,
+    pub timings: InvokeTimings,
+}
+
+/// Where the time of one invocation went, reported through `Server-Timing`.
+#[derive(Debug, Default, Clone)]
+pub struct InvokeTimings {
+    /// Waiting for the function registry before balancing starts.
+    pub queueing_ms: f64,
+    /// Looking up the balancer and selecting a replica.
+    pub balancer_ms: f64,
+    /// Opening a new connection to the replica (zero for pooled ones).
+    pub connection_ms: f64,
+    /// The replica handling the request.
+    pub container_ms: f64,
+    /// Failed attempts and backoff before the successful one.
+    pub retries_ms: f64,
+    /// Whole round trip to the replica.
+    pub upstream_ms: f64,
+    pub attempts: u32,
 }
 
 pub struct FunctionManager {
     container_manager: ContainerManager,
+    process_manager: ProcessManager,
+    wasm_runtime: WasmRuntime,
     pub deployed_functions: DeployedFunctions,
+    pub metrics: Metrics,
+    admission: Admission,
+    pub secrets: SecretStore,
+    egress: EgressProxies,
+    pub invoke_tokens: InvokeTokens,
+    snapshots: SnapshotStore,
+    pub gc: GcSettings,
+    /// Held shared by container deployments and exclusively by garbage
+    /// collection, so it never sees a half-deployed function's resources.
+    maintenance: RwLock<()>,
+    /// Per-function locks held while replicas are scaled, restarted or
+    /// stopped, so the reconciler never mistakes a replica being removed for
+    /// a crash.
+    replica_changes: StdMutex<HashMap<String, Arc<Mutex<()>>>>,
+    checkpoint_support: OnceCell<bool>,
     load_balancers: Arc<RwLock<HashMap<String, Arc<dyn LoadBalancingStrategy>>>>,
 }
 
@@ -122,32 +507,76 @@ impl FunctionManager {
         let container_manager = ContainerManager::new()?;
         Ok(Self {
             container_manager,
+            process_manager: ProcessManager::new(),
+            wasm_runtime: WasmRuntime::new()?,
             deployed_functions: DeployedFunctions::new(),
+            metrics: Metrics::new(),
+            admission: Admission::default(),
+            secrets: SecretStore::from_env()?,
+            egress: EgressProxies::default(),
+            invoke_tokens: InvokeTokens::from_env()?,
+            snapshots: SnapshotStore::default(),
+            gc: GcSettings::from_env()?,
+            maintenance: RwLock::new(()),
+            replica_changes: StdMutex::new(HashMap::new()),
+            checkpoint_support: OnceCell::new(),
             load_balancers: Arc::new(RwLock::new(HashMap::new())),
         })
     }
 
+    #[allow(dead_code)]
     pub async fn try_invoke(&self, function_name: &str, payload: Value) -> Result<Value> {
-        let outcome = self.try_invoke_with_meta(function_name, payload).await?;
+        let outcome = self
+            .try_invoke_with_meta(function_name, payload, &[], &CallChain::root())
+            .await?;
         Ok(outcome.result)
     }
 
+    /// `headers` are forwarded to HTTP replicas together with the call chain
+    /// and a token for nested calls; wasm replicas only see the payload.
     pub async fn try_invoke_with_meta(
         &self,
         function_name: &str,
         payload: Value,
+        headers: &[(String, String)],
+        chain: &CallChain,
     ) -> Result<InvokeOutcome> {
-        let (container_ids, host_ports_by_container) = {
+        let received = Instant::now();
+        let admission = {
+            let guard = self.deployed_functions.read().await;
+            guard
+                .get(function_name)
+                .ok_or(FunctionError::FunctionNotDeployed)?
+                .admission_limits()
+        };
+        let _permit = match admission {
+            Some(limits) => Some(
+                self.admission
+                    .queue(function_name)
+                    .acquire(function_name, limits)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let (container_ids, replica_addrs, timeout) = {
             let guard = self.deployed_functions.read().await;
-            let config = guard
+            let running = guard
                 .get(function_name)
                 .ok_or(FunctionError::FunctionNotDeployed)?;
             (
-                config.container_ids.clone(),
-                config.host_ports_by_container.clone(),
+                running.container_ids.clone(),
+                running.replica_addrs.clone(),
+                Duration::from_secs(running.config.timeout as u64),
             )
         };
 
+        let balancing = Instant::now();
+        let mut timings = InvokeTimings {
+            queueing_ms: balancing.duration_since(received).as_secs_f64() * 1000.0,
+            attempts: 1,
+            ..Default::default()
+        };
         let load_balancer = {
             let guard = self.load_balancers.read().await;
             guard
@@ -158,35 +587,77 @@ impl FunctionManager {
 
         let container_id =
             load_balancer.select_container(function_name, &container_ids, Some(&payload))?;
+        timings.balancer_ms = elapsed_ms(balancing);
 
-        let host_port = host_ports_by_container
-            .get(&container_id)
-            .copied()
-            .ok_or_else(|| anyhow!("Host port not found for container {container_id}"))?;
-
-        let result = self.container_manager.try_invoke_http(host_port, &payload).await;
+        let invoked = Instant::now();
+        let mut instantiate_ms = None;
+        let result = if WasmRuntime::is_wasm_replica(&container_id) {
+            self.wasm_runtime
+                .invoke(&container_id, &payload)
+                .await
+                .map(|invocation| {
+                    instantiate_ms = Some(invocation.instantiate_ms);
+                    invocation.result
+                })
+        } else {
+            let addr = replica_addrs
+                .get(&container_id)
+                .copied()
+                .ok_or_else(|| anyhow!("Address not found for replica {container_id}"))?;
+            let mut headers = headers.to_vec();
+            headers.extend(self.invoke_tokens.headers(function_name, chain, timeout));
+            self.container_manager
+                .try_invoke_http(addr, &payload, &headers)
+                .await
+                .map(|invocation| {
+                    timings.attempts = invocation.attempts;
+                    timings.connection_ms = invocation.connect_ms;
+                    timings.container_ms = invocation.container_ms;
+                    timings.retries_ms = invocation.retries_ms;
+                    invocation.body
+                })
+        };
 
+        timings.upstream_ms = elapsed_ms(invoked);
+        if WasmRuntime::is_wasm_replica(&container_id) {
+            timings.container_ms = timings.upstream_ms;
+        }
         load_balancer.on_invocation_finished(function_name, &container_id, result.is_ok());
+        if result.is_ok() {
+            self.metrics
+                .record_invocation(function_name, &container_id, timings.upstream_ms);
+        }
 
         let result = result?;
         Ok(InvokeOutcome {
             container_id,
             result,
+            instantiate_ms,
+            timings,
         })
     }
 
     pub async fn cleanup_containers(&self, redis_manager: &RedisManager) {
-        let function_container_pairs: Vec<(String, Vec<String>)> = {
+        let function_container_pairs: Vec<(String, Vec<String>, Vec<String>)> = {
             let values = self.deployed_functions.read().await;
             values
                 .iter()
-                .map(|(function, config)| (function.clone(), config.container_ids.clone()))
+                .map(|(function, config)| {
+                    (
+                        function.clone(),
+                        config.container_ids.clone(),
+                        config.warm_container_ids.clone(),
+                    )
+                })
                 .collect()
         };
-        for (function, config) in function_container_pairs {
-            for id in config {
+        for (function, container_ids, warm_container_ids) in function_container_pairs {
+            for id in container_ids {
                 self.remove_container(&function, &id, redis_manager).await;
             }
+            for id in warm_container_ids {
+                self.remove_replica(&id).await;
+            }
         }
     }
 
@@ -196,11 +667,11 @@ impl FunctionManager {
         container_id: &str,
         redis_manager: &RedisManager,
     ) {
-        self.container_manager.remove_container(container_id).await;
+        self.remove_replica(container_id).await;
         let mut should_remove_balancer = false;
         if let Some(function) = self.deployed_functions.write().await.get_mut(function_name) {
             function.container_ids.retain(|id| id != container_id);
-            function.host_ports_by_container.remove(container_id);
+            function.replica_addrs.remove(container_id);
             should_remove_balancer = function.container_ids.is_empty();
         }
         if should_remove_balancer {
@@ -209,6 +680,16 @@ impl FunctionManager {
         let _ = redis_manager.remove_function_replica(function_name, container_id);
     }
 
+    async fn remove_replica(&self, replica_id: &str) {
+        if ProcessManager::is_process_replica(replica_id) {
+            self.process_manager.remove_process(replica_id).await;
+        } else if WasmRuntime::is_wasm_replica(replica_id) {
+            self.wasm_runtime.remove_replica(replica_id);
+        } else {
+            self.container_manager.remove_container(replica_id).await;
+        }
+    }
+
     pub async fn read_function_config(path: &str) -> Result<FunctionConfig> {
         let function_dir = format!("functions/{path}");
         let config_path = function_config_path(path);
@@ -243,53 +724,387 @@ impl FunctionManager {
         redis_manager: &RedisManager,
     ) -> Result<FunctionConfig> {
         let mut config = Self::read_function_config(function_name).await?;
+        let scale_only = update.is_scale_only();
+        let policy_only = update.is_policy_only();
         update.apply_to(&mut config);
+        config.validate()?;
+        let (replicas, warm_pool) = (config.replicas, config.warm_pool);
 
         let config_path = function_config_path(function_name);
         let serialized = serde_json::to_string_pretty(&config)?;
         tokio::fs::write(&config_path, serialized).await?;
 
-        let should_redeploy = {
+        let deployed_runtime = {
             let deployed = self.deployed_functions.read().await;
-            deployed.contains_key(function_name)
+            deployed.get(function_name).map(|running| running.config.runtime)
         };
+        let should_redeploy = deployed_runtime.is_some();
+        self.apply_invoke_policy(function_name, &config).await;
 
-        if should_redeploy {
-            self.redeploy_function_by_name(function_name, redis_manager)
+        if policy_only {
+            return Ok(config);
+        }
+        if scale_only && deployed_runtime == Some(FunctionRuntime::Docker) {
+            self.scale_function(function_name, replicas, warm_pool, redis_manager)
+                .await?;
+        } else if should_redeploy {
+            self.redeploy_function_by_name(function_name, redis_manager, false)
                 .await?;
         }
 
         Self::read_function_config(function_name).await
     }
 
+    async fn apply_invoke_policy(&self, function_name: &str, config: &FunctionConfig) {
+        if let Some(running) = self.deployed_functions.write().await.get_mut(function_name) {
+            running.config.anonymous_invoke = config.anonymous_invoke;
+            running.config.jwt = config.jwt.clone();
+            running.config.limits = config.limits.clone();
+            running.config.max_concurrency_per_replica = config.max_concurrency_per_replica;
+            running.config.max_queue_length = config.max_queue_length;
+            running.config.queue_timeout_ms = config.queue_timeout_ms;
+            running.config.idempotency_ttl_secs = config.idempotency_ttl_secs;
+            running.config.response_cache = config.response_cache.clone();
+            running.config.state = config.state.clone();
+        }
+    }
+
+    pub async fn invoke_cache_settings(&self, function_name: &str) -> Option<InvokeCacheSettings> {
+        let deployed = self.deployed_functions.read().await;
+        let config = &deployed.get(function_name)?.config;
+        Some(InvokeCacheSettings {
+            version: config.version.clone(),
+            idempotency_ttl_secs: config.idempotency_ttl_secs,
+            in_progress_timeout: Duration::from_secs(config.timeout.max(1) as u64)
+                + Duration::from_millis(config.queue_timeout_ms),
+            response_cache: config.response_cache.clone(),
+        })
+    }
+
+    pub async fn state_config(&self, function_name: &str) -> Result<StateConfig> {
+        self.deployed_functions
+            .read()
+            .await
+            .get(function_name)
+            .map(|running| running.config.state.clone())
+            .ok_or_else(|| FunctionError::FunctionNotDeployed.into())
+    }
+
+    pub async fn invoke_limits(&self, function_name: &str) -> Option<InvokeLimits> {
+        self.deployed_functions
+            .read()
+            .await
+            .get(function_name)
+            .and_then(|running| running.config.limits.clone())
+    }
+
+    pub async fn jwt_config(&self, function_name: &str) -> Option<JwtConfig> {
+        self.deployed_functions
+            .read()
+            .await
+            .get(function_name)
+            .and_then(|running| running.config.jwt.clone())
+    }
+
+    pub async fn allows_anonymous_invoke(&self, function_name: &str) -> bool {
+        self.deployed_functions
+            .read()
+            .await
+            .get(function_name)
+            .is_some_and(|running| running.config.anonymous_invoke)
+    }
+
     pub async fn stop_function(
         &self,
         function_name: &str,
         redis_manager: &RedisManager,
     ) -> Result<usize> {
-        let container_ids = {
+        let lock = self.replica_change_lock(function_name);
+        let _changing = lock.lock().await;
+        let (container_ids, warm_container_ids, checkpoint) = {
             let mut deployed = self.deployed_functions.write().await;
             let running = deployed
                 .remove(function_name)
                 .ok_or(FunctionError::FunctionNotDeployed)?;
-            running.container_ids
+            (
+                running.container_ids,
+                running.warm_container_ids,
+                running.checkpoint,
+            )
         };
 
         self.load_balancers.write().await.remove(function_name);
+        self.egress.stop(function_name);
 
         let removed = container_ids.len();
         for container_id in container_ids {
-            self.container_manager.remove_container(&container_id).await;
+            self.remove_replica(&container_id).await;
             let _ = redis_manager.remove_function_replica(function_name, &container_id);
         }
+        for container_id in warm_container_ids {
+            self.remove_replica(&container_id).await;
+        }
+        if let Some(checkpoint) = checkpoint {
+            let _ = tokio::fs::remove_dir_all(&checkpoint.dir).await;
+        }
 
         Ok(removed)
     }
 
+    /// Stops the function for good: besides the replicas, its networks go
+    /// away and so does its volume when the retention is `none`.
+    pub async fn stop_and_release_function(
+        &self,
+        function_name: &str,
+        redis_manager: &RedisManager,
+    ) -> Result<usize> {
+        let config = {
+            let deployed = self.deployed_functions.read().await;
+            let running = deployed
+                .get(function_name)
+                .ok_or(FunctionError::FunctionNotDeployed)?;
+            (running.config.runtime == FunctionRuntime::Docker).then(|| {
+                (
+                    running.config.volume.clone(),
+                    running.config.version.clone(),
+                    running.config.network.shared_network_names(),
+                )
+            })
+        };
+        let removed = self.stop_function(function_name, redis_manager).await?;
+
+        if let Some((volume, version, shared_networks)) = config {
+            let volume_name = volume.volume_name(function_name, &version);
+            let keep_volume = (volume.retention != VolumeRetention::None).then_some(volume_name.as_str());
+            if let Err(e) = self
+                .container_manager
+                .remove_orphan_resources(function_name, keep_volume, &[])
+                .await
+            {
+                warn!("Failed to release resources of '{function_name}': {e}");
+            }
+            for shared_network in shared_networks {
+                self.container_manager.remove_network(&shared_network).await;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Volume name and retention of a container function, from the running
+    /// deployment or else from `function.json`.
+    async fn function_volume(&self, function_name: &str) -> Result<(String, VolumeRetention)> {
+        let deployed = {
+            let deployed = self.deployed_functions.read().await;
+            deployed.get(function_name).map(|running| {
+                (
+                    running.config.runtime,
+                    running.config.volume.clone(),
+                    running.config.version.clone(),
+                )
+            })
+        };
+        let (runtime, volume, version) = match deployed {
+            Some(deployed) => deployed,
+            None => {
+                let config = Self::read_function_config(function_name).await?;
+                (config.runtime, config.volume, config.version)
+            }
+        };
+        if runtime != FunctionRuntime::Docker {
+            return Err(VolumeError::NoVolume(function_name.to_string()).into());
+        }
+        Ok((volume.volume_name(function_name, &version), volume.retention))
+    }
+
+    /// A running replica, through which the volume is read and written.
+    async fn volume_replica(&self, function_name: &str) -> Result<String> {
+        let deployed = self.deployed_functions.read().await;
+        let running = deployed
+            .get(function_name)
+            .ok_or(FunctionError::FunctionNotDeployed)?;
+        if running.container_config.is_none() {
+            return Err(VolumeError::NoVolume(function_name.to_string()).into());
+        }
+        running
+            .container_ids
+            .first()
+            .or(running.warm_container_ids.first())
+            .cloned()
+            .ok_or_else(|| FunctionError::NoRunningContainers.into())
+    }
+
+    pub async fn volume_report(&self, function_name: &str) -> Result<Value> {
+        let (volume_name, retention) = self.function_volume(function_name).await?;
+        let size_bytes = self.container_manager.volume_size(&volume_name).await?;
+        let snapshots = self.snapshots.list(function_name).await?;
+        Ok(serde_json::json!({
+            "function": function_name,
+            "volume": volume_name,
+            "retention": retention,
+            "sizeBytes": size_bytes,
+            "snapshots": snapshots
+        }))
+    }
+
+    pub async fn snapshot_volume(&self, function_name: &str) -> Result<SnapshotInfo> {
+        let container_id = self.volume_replica(function_name).await?;
+        let (id, path) = self.snapshots.create_path(function_name).await?;
+        self.container_manager
+            .export_volume(&container_id, &path)
+            .await?;
+        info!("Created snapshot '{id}' of the volume of '{function_name}'");
+        self.snapshots.info(&id, &path).await
+    }
+
+    pub async fn restore_volume(&self, function_name: &str, snapshot_id: &str) -> Result<()> {
+        let path = self.snapshots.path(function_name, snapshot_id).await?;
+        let container_id = self.volume_replica(function_name).await?;
+        self.container_manager
+            .import_volume(&container_id, &path)
+            .await?;
+        info!("Restored snapshot '{snapshot_id}' into the volume of '{function_name}'");
+        Ok(())
+    }
+
+    pub async fn delete_volume_snapshot(&self, function_name: &str, snapshot_id: &str) -> Result<()> {
+        self.snapshots.remove(function_name, snapshot_id).await
+    }
+
+    /// Removes images, build cache, build contexts, networks and volumes no
+    /// deployment needs under `policy`; a dry run only lists them.
+    pub async fn collect_garbage(&self, policy: &GcPolicy, dry_run: bool) -> Result<GcReport> {
+        let _maintenance = self.maintenance.write().await;
+        // Images, networks and volumes of the running container functions.
+        let in_use = {
+            let deployed = self.deployed_functions.read().await;
+            deployed
+                .values()
+                .filter(|running| running.container_config.is_some())
+                .map(|running| {
+                    let config = &running.config;
+                    let image_name = format!("{}:{}", config.name, config.version);
+                    let network_name = config
+                        .network
+                        .function_network_name(&ContainerManager::resource_name_from_image_name(&image_name));
+                    let volume_name = config.volume.volume_name(&config.name, &config.version);
+                    (config.name.clone(), (image_name, network_name, volume_name))
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        let images = self.container_manager.list_function_images().await?;
+        let (images, mut items) = classify_images(images, |tag| {
+            in_use.values().any(|(image_name, _, _)| image_name == tag)
+        });
+        let build_cache_bytes = self.container_manager.build_cache_size().await?;
+        let (image_removals, prune_build_cache) = plan_image_removals(&images, build_cache_bytes, policy);
+        items.extend(image_removals);
+        if prune_build_cache {
+            items.push(GcItem {
+                kind: GcItemKind::BuildCache,
+                name: "build-cache".to_string(),
+                size_bytes: Some(build_cache_bytes),
+                reason: "превышен лимит диска".to_string(),
+            });
+        }
+        for (path, size) in self
+            .container_manager
+            .stale_build_contexts(STALE_BUILD_CONTEXT_AGE)
+            .await?
+        {
+            items.push(GcItem {
+                kind: GcItemKind::BuildContext,
+                name: path.to_string_lossy().to_string(),
+                size_bytes: Some(size),
+                reason: "контекст незавершенной сборки".to_string(),
+            });
+        }
+        for network in self.container_manager.list_function_networks().await? {
+            if !in_use.values().any(|(_, network_name, _)| *network_name == network) {
+                items.push(GcItem {
+                    kind: GcItemKind::Network,
+                    name: network,
+                    size_bytes: None,
+                    reason: "не используется развернутыми функциями".to_string(),
+                });
+            }
+        }
+        for volume in self.container_manager.list_function_volumes().await? {
+            let retention = volume.labels.get(RETENTION_LABEL).map(String::as_str);
+            let function = volume.labels.get(FUNCTION_LABEL);
+            let orphan = match function.and_then(|function| in_use.get(function)) {
+                Some((_, _, volume_name)) => {
+                    *volume_name != volume.name && VolumeRetention::removed_with_old_versions(retention)
+                }
+                None => retention == Some(VolumeRetention::None.as_str()),
+            };
+            if orphan {
+                items.push(GcItem {
+                    kind: GcItemKind::Volume,
+                    name: volume.name,
+                    size_bytes: None,
+                    reason: "том прежней версии или остановленной функции".to_string(),
+                });
+            }
+        }
+
+        let mut report = GcReport {
+            dry_run,
+            removed: Vec::new(),
+            failed: Vec::new(),
+            reclaimed_bytes: 0,
+        };
+        if dry_run {
+            report.reclaimed_bytes = items.iter().filter_map(|item| item.size_bytes).sum();
+            report.removed = items;
+            return Ok(report);
+        }
+        for item in items {
+            let result = match item.kind {
+                GcItemKind::Image | GcItemKind::DanglingImage => self
+                    .container_manager
+                    .remove_image(&item.name)
+                    .await
+                    .map(|()| item.size_bytes.unwrap_or_default()),
+                GcItemKind::BuildCache => self.container_manager.prune_build_cache().await,
+                GcItemKind::BuildContext => tokio::fs::remove_file(&item.name)
+                    .await
+                    .map(|()| item.size_bytes.unwrap_or_default())
+                    .map_err(Into::into),
+                GcItemKind::Network => self
+                    .container_manager
+                    .try_remove_network(&item.name)
+                    .await
+                    .map(|()| 0),
+                GcItemKind::Volume => self
+                    .container_manager
+                    .remove_volume(&item.name)
+                    .await
+                    .map(|()| 0),
+            };
+            match result {
+                Ok(reclaimed) => {
+                    info!("GC removed {:?} '{}': {}", item.kind, item.name, item.reason);
+                    report.reclaimed_bytes += reclaimed;
+                    report.removed.push(item);
+                }
+                Err(e) => {
+                    warn!("GC failed to remove {:?} '{}': {e}", item.kind, item.name);
+                    report.failed.push(GcFailure {
+                        name: item.name,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+
     pub async fn redeploy_function_by_name(
         &self,
         function_name: &str,
         redis_manager: &RedisManager,
+        force_rebuild: bool,
     ) -> Result<String> {
         let was_deployed = {
             let deployed = self.deployed_functions.read().await;
@@ -301,45 +1116,52 @@ impl FunctionManager {
         }
 
         let config = Self::read_function_config(function_name).await?;
-        self.deploy_function(config, redis_manager).await
+        self.deploy_function(config, redis_manager, force_rebuild).await
     }
 
+    /// Container functions reuse the existing image when the build context
+    /// has not changed since it was built, unless `force_rebuild` is set.
     pub async fn deploy_function(
         &self,
         config: FunctionConfig,
         redis_manager: &RedisManager,
+        force_rebuild: bool,
     ) -> Result<String> {
-        let image_name = format!("{}:{}", config.name, config.version);
-        info!("Building image: {}", image_name);
-        self.container_manager
-            .build_image(
-                &config.build_context_path.to_string_lossy(),
-                &image_name,
-                &config.dockerfile,
-            )
-            .await?;
-        let container_config = self
-            .container_manager
-            .setup_function_template(&image_name, &config)
-            .await?;
-
-        let mut container_ids = Vec::with_capacity(config.replicas as usize);
-        let mut host_ports_by_container = HashMap::with_capacity(config.replicas as usize);
-        for _ in 0..config.replicas {
-            let container_id = self
-                .container_manager
-                .create_container_from_template(&container_config, &image_name)
-                .await?;
-            self.container_manager
-                .start_container(&container_id)
-                .await?;
-            let host_port = self
-                .container_manager
-                .get_published_host_port(&container_id, config.inner_port)
-                .await?;
-            host_ports_by_container.insert(container_id.clone(), host_port);
-            container_ids.push(container_id);
-        }
+        config.validate()?;
+        let replicas = match config.runtime {
+            FunctionRuntime::Docker => {
+                self.deploy_containers(&config, redis_manager, force_rebuild)
+                    .await?
+            }
+            FunctionRuntime::Process => {
+                let (container_ids, replica_addrs) =
+                    self.deploy_processes(&config).await?;
+                DeployedReplicas {
+                    deployed_name: format!("{}:{}", config.name, config.version),
+                    container_config: None,
+                    container_ids,
+                    warm_container_ids: Vec::new(),
+                    replica_addrs,
+                    checkpoint: None,
+                }
+            }
+            FunctionRuntime::Wasm => DeployedReplicas {
+                deployed_name: format!("{}:{}", config.name, config.version),
+                container_config: None,
+                container_ids: self.wasm_runtime.deploy(&config).await?,
+                warm_container_ids: Vec::new(),
+                replica_addrs: HashMap::new(),
+                checkpoint: None,
+            },
+        };
+        let DeployedReplicas {
+            deployed_name,
+            container_config,
+            container_ids,
+            warm_container_ids,
+            replica_addrs,
+            checkpoint,
+        } = replicas;
 
         let kind = parse_load_balancer_kind(&config.load_balancer)
             .unwrap_or(LoadBalancingKind::RoundRobin);
@@ -355,12 +1177,807 @@ impl FunctionManager {
             config,
             container_config,
             container_ids: container_ids.clone(),
-            host_ports_by_container,
+            warm_container_ids,
+            replica_addrs,
+            checkpoint,
         };
         redis_manager.replace_function_replicas(&function.config.name, &container_ids)?;
         running_containers.insert(function.config.name.clone(), function);
-        Ok(image_name)
+        Ok(deployed_name)
+    }
+
+    async fn deploy_containers(
+        &self,
+        config: &FunctionConfig,
+        redis_manager: &RedisManager,
+        force_rebuild: bool,
+    ) -> Result<DeployedReplicas> {
+        let _maintenance = self.maintenance.read().await;
+        let image_name = format!("{}:{}", config.name, config.version);
+        let gateway = self
+            .container_manager
+            .prepare_function_network(&image_name, &config.name, &config.network)
+            .await?;
+        // Replicas of a running version keep their proxy until the new ones
+        // are up; a failed deploy leaves it as it was.
+        let egress_before = self.egress.snapshot(&config.name);
+        let replicas = match self
+            .start_containers(config, redis_manager, force_rebuild, image_name, gateway)
+            .await
+        {
+            Ok(replicas) => replicas,
+            Err(e) => {
+                self.egress.restore(egress_before);
+                return Err(e);
+            }
+        };
+        if config.network.mode == NetworkMode::Egress {
+            self.egress.retain_current(&config.name);
+        } else {
+            self.egress.stop(&config.name);
+        }
+
+        // Volumes and networks of previous versions are unused by now.
+        let network_name = config
+            .network
+            .function_network_name(&ContainerManager::resource_name_from_image_name(&replicas.deployed_name));
+        let volume_name = config.volume.volume_name(&config.name, &config.version);
+        if let Err(e) = self
+            .container_manager
+            .remove_orphan_resources(&config.name, Some(&volume_name), &[network_name])
+            .await
+        {
+            warn!("Failed to clean up old resources of '{}': {e}", config.name);
+        }
+        Ok(replicas)
     }
+
+    /// Starts the egress proxy, builds the image unless it is up to date and
+    /// starts the replicas and warm containers. Replicas started before a
+    /// failure are removed again.
+    async fn start_containers(
+        &self,
+        config: &FunctionConfig,
+        redis_manager: &RedisManager,
+        force_rebuild: bool,
+        image_name: String,
+        gateway: IpAddr,
+    ) -> Result<DeployedReplicas> {
+        if config.network.mode == NetworkMode::Egress {
+            self.egress
+                .start(&config.name, gateway, config.network.egress_rules()?)
+                .await?;
+        }
+        let env = self.container_env(config, redis_manager)?;
+        let image_started = Instant::now();
+        let build_hash = build_context_hash(&config.build_context_path, &config.dockerfile).await?;
+        let up_to_date = !force_rebuild
+            && self.container_manager.image_build_hash(&image_name).await?.as_deref() == Some(build_hash.as_str());
+        if up_to_date {
+            info!("Image '{image_name}' is up to date with its build context, skipping build");
+        } else {
+            info!("Building image: {}", image_name);
+            self.container_manager
+                .build_image(
+                    &config.build_context_path.to_string_lossy(),
+                    &image_name,
+                    &config.dockerfile,
+                    &config.name,
+                    &config.version,
+                    &build_hash,
+                )
+                .await?;
+        }
+        let image_ms = elapsed_ms(image_started);
+        let container_config = self
+            .container_manager
+            .setup_function_template(&image_name, config, env, gateway)
+            .await?;
+        let mut template = ContainerTemplate {
+            container_config,
+            image_name,
+            inner_port: config.inner_port,
+            readiness_timeout: readiness_timeout(config),
+            checkpoint: None,
+        };
+
+        let mut container_ids = Vec::with_capacity(config.replicas as usize);
+        let mut warm_container_ids = Vec::with_capacity(config.warm_pool as usize);
+        let mut replica_addrs = HashMap::with_capacity(config.replicas as usize);
+        let started: Result<()> = async {
+            for _ in 0..config.replicas {
+                let (container_id, addr, mut phases) = self
+                    .start_container_replica(&config.name, &template)
+                    .await?;
+                self.metrics.record_cold_start(&config.name);
+                if container_ids.is_empty() {
+                    phases.image_ms = image_ms;
+                }
+                self.metrics.record_replica_start(&config.name, phases);
+                if config.checkpoint_restore && template.checkpoint.is_none() {
+                    template.checkpoint = self
+                        .create_checkpoint(config, &container_id, addr)
+                        .await;
+                }
+                replica_addrs.insert(container_id.clone(), addr);
+                container_ids.push(container_id);
+            }
+
+            for _ in 0..config.warm_pool {
+                let (container_id, addr, _) = self
+                    .start_container_replica(&config.name, &template)
+                    .await?;
+                replica_addrs.insert(container_id.clone(), addr);
+                warm_container_ids.push(container_id);
+            }
+            Ok(())
+        }
+        .await;
+        // A failed start must not leave the replicas started before it running.
+        if let Err(e) = started {
+            for container_id in container_ids.iter().chain(&warm_container_ids) {
+                self.container_manager.remove_container(container_id).await;
+            }
+            return Err(e);
+        }
+
+        Ok(DeployedReplicas {
+            deployed_name: template.image_name,
+            container_config: Some(template.container_config),
+            container_ids,
+            warm_container_ids,
+            replica_addrs,
+            checkpoint: template.checkpoint,
+        })
+    }
+
+    /// Checkpoints a freshly started replica once it accepts connections.
+    /// Returns `None` (and the function keeps booting normally) when the
+    /// daemon lacks checkpoint support or the checkpoint fails.
+    async fn create_checkpoint(
+        &self,
+        config: &FunctionConfig,
+        container_id: &str,
+        addr: SocketAddr,
+    ) -> Option<CheckpointRef> {
+        let supported = *self
+            .checkpoint_support
+            .get_or_init(|| self.container_manager.probe_checkpoint_support())
+            .await;
+        if !supported {
+            warn!(
+                "checkpointRestore requested for '{}' but docker checkpoints are unavailable; falling back to regular starts",
+                config.name
+            );
+            return None;
+        }
+
+        if let Err(e) = self
+            .container_manager
+            .wait_until_ready(addr, Duration::from_secs(config.timeout.max(1) as u64))
+            .await
+        {
+            warn!("Skipping checkpoint for '{}': {e}", config.name);
+            return None;
+        }
+
+        let checkpoint = CheckpointRef {
+            dir: std::env::temp_dir()
+                .join(CHECKPOINT_DIR)
+                .join(format!("{}-{}", config.name, config.version)),
+            name: format!("ready-{}", uuid::Uuid::now_v7().simple()),
+        };
+        match self
+            .container_manager
+            .checkpoint_container(container_id, &checkpoint.dir, &checkpoint.name)
+            .await
+        {
+            Ok(()) => {
+                info!(
+                    "Created checkpoint '{}' for '{}' in '{}'",
+                    checkpoint.name,
+                    config.name,
+                    checkpoint.dir.display()
+                );
+                Some(checkpoint)
+            }
+            Err(e) => {
+                warn!("Failed to checkpoint '{}': {e}", config.name);
+                None
+            }
+        }
+    }
+
+    async fn start_container_replica(
+        &self,
+        function_name: &str,
+        template: &ContainerTemplate,
+    ) -> Result<(String, SocketAddr, StartPhases)> {
+        let created = Instant::now();
+        let container_id = self
+            .container_manager
+            .create_container_from_template(&template.container_config, &template.image_name)
+            .await?;
+        let create_ms = elapsed_ms(created);
+        let started = Instant::now();
+
+        let restored = match &template.checkpoint {
+            Some(checkpoint) => match self
+                .container_manager
+                .start_container_from_checkpoint(&container_id, &checkpoint.dir, &checkpoint.name)
+                .await
+            {
+                Ok(()) => {
+                    self.metrics.record_checkpoint_restore(function_name);
+                    true
+                }
+                Err(e) => {
+                    warn!("Checkpoint restore failed for '{function_name}', booting instead: {e}");
+                    self.metrics.record_checkpoint_restore_failure(function_name);
+                    false
+                }
+            },
+            None => false,
+        };
+        if !restored {
+            self.container_manager
+                .start_container(&container_id)
+                .await?;
+        }
+
+        let addr = match self
+            .container_manager
+            .replica_addr(&container_id, &template.container_config, template.inner_port)
+            .await
+        {
+            Ok(addr) => addr,
+            Err(e) => {
+                self.fail_if_exited(function_name, &container_id).await?;
+                return Err(e);
+            }
+        };
+        let start_ms = elapsed_ms(started);
+
+        let ready = Instant::now();
+        if let Err(e) = self
+            .container_manager
+            .wait_until_ready(addr, template.readiness_timeout)
+            .await
+        {
+            self.fail_if_exited(function_name, &container_id).await?;
+            warn!("Replica '{container_id}' of '{function_name}' is not ready yet: {e}");
+        }
+        let phases = StartPhases {
+            mode: if restored {
+                StartMode::Checkpoint
+            } else {
+                StartMode::Boot
+            },
+            container_id: container_id.clone(),
+            image_ms: 0.0,
+            create_ms,
+            start_ms,
+            readiness_ms: elapsed_ms(ready),
+            first_request_ms: None,
+        };
+        Ok((container_id, addr, phases))
+    }
+
+    /// A replica that exits right after start usually cannot run under its
+    /// security profile (read-only rootfs, non-root user, dropped
+    /// capabilities); it is removed and the start fails with its output.
+    async fn fail_if_exited(&self, function_name: &str, container_id: &str) -> Result<()> {
+        let Some((exit_code, logs)) = self.container_manager.exit_report(container_id).await else {
+            return Ok(());
+        };
+        self.container_manager.remove_container(container_id).await;
+        Err(DeployError::ContainerExited {
+            function: function_name.to_string(),
+            exit_code,
+            logs,
+        }
+        .into())
+    }
+
+    /// Adds one balancer replica, promoting a warm container when available.
+    async fn add_container_replica(
+        &self,
+        function_name: &str,
+        redis_manager: &RedisManager,
+    ) -> Result<String> {
+        let (promoted, runtime) = {
+            let mut deployed = self.deployed_functions.write().await;
+            let running = deployed
+                .get_mut(function_name)
+                .ok_or(FunctionError::FunctionNotDeployed)?;
+            let promoted = if running.warm_container_ids.is_empty() {
+                None
+            } else {
+                let container_id = running.warm_container_ids.remove(0);
+                running.container_ids.push(container_id.clone());
+                Some(container_id)
+            };
+            (promoted, running.config.runtime)
+        };
+
+        let container_id = match promoted {
+            Some(container_id) => {
+                info!("Promoted warm container '{container_id}' for '{function_name}'");
+                self.metrics.record_warm_hit(function_name);
+                self.metrics.record_replica_start(
+                    function_name,
+                    StartPhases {
+                        mode: StartMode::Warm,
+                        container_id: container_id.clone(),
+                        ..Default::default()
+                    },
+                );
+                container_id
+            }
+            // Wasm replicas share the compiled code and have no address.
+            None if runtime == FunctionRuntime::Wasm => {
+                let container_id = self.wasm_runtime.add_replica(function_name)?;
+                let mut deployed = self.deployed_functions.write().await;
+                let running = deployed
+                    .get_mut(function_name)
+                    .ok_or(FunctionError::FunctionNotDeployed)?;
+                running.container_ids.push(container_id.clone());
+                container_id
+            }
+            None => {
+                let (container_id, addr) = match self.process_config(function_name).await {
+                    Some(config) => self.start_process_replica(&config).await?,
+                    None => {
+                        let template = self.container_template(function_name).await?;
+                        let (container_id, addr, phases) = self
+                            .start_container_replica(function_name, &template)
+                            .await?;
+                        self.metrics.record_cold_start(function_name);
+                        self.metrics.record_replica_start(function_name, phases);
+                        (container_id, addr)
+                    }
+                };
+                let mut deployed = self.deployed_functions.write().await;
+                let running = deployed
+                    .get_mut(function_name)
+                    .ok_or(FunctionError::FunctionNotDeployed)?;
+                running.container_ids.push(container_id.clone());
+                running
+                    .replica_addrs
+                    .insert(container_id.clone(), addr);
+                container_id
+            }
+        };
+
+        self.reconfigure_balancer(function_name).await;
+        redis_manager.add_function_replica(function_name, &container_id)?;
+        Ok(container_id)
+    }
+
+    /// Config of a deployed `process` function, `None` for other runtimes.
+    async fn process_config(&self, function_name: &str) -> Option<FunctionConfig> {
+        let deployed = self.deployed_functions.read().await;
+        deployed
+            .get(function_name)
+            .filter(|running| running.config.runtime == FunctionRuntime::Process)
+            .map(|running| running.config.clone())
+    }
+
+    async fn container_template(&self, function_name: &str) -> Result<ContainerTemplate> {
+        let deployed = self.deployed_functions.read().await;
+        let running = deployed
+            .get(function_name)
+            .ok_or(FunctionError::FunctionNotDeployed)?;
+        let container_config = running
+            .container_config
+            .clone()
+            .ok_or_else(|| anyhow!("Функция '{function_name}' не использует контейнеры"))?;
+        Ok(ContainerTemplate {
+            container_config,
+            image_name: format!("{}:{}", running.config.name, running.config.version),
+            inner_port: running.config.inner_port,
+            readiness_timeout: readiness_timeout(&running.config),
+            checkpoint: running.checkpoint.clone(),
+        })
+    }
+
+    /// `KEY=value` pairs for a container: plain `env`, then secrets, which
+    /// win on conflicting names. Egress functions also get the proxy
+    /// variables unless `env` sets them; the platform URL for nested calls is
+    /// always set.
+    fn container_env(&self, config: &FunctionConfig, redis_manager: &RedisManager) -> Result<Vec<String>> {
+        let mut env = config.env.clone();
+        if config.network.mode == NetworkMode::Egress
+            && let Some(proxy) = self.egress.addr(&config.name)
+        {
+            let proxy_url = format!("http://{proxy}");
+            for variable in ["HTTP_PROXY", "HTTPS_PROXY", "http_proxy", "https_proxy"] {
+                env.entry(variable.to_string())
+                    .or_insert_with(|| proxy_url.clone());
+            }
+            for variable in ["NO_PROXY", "no_proxy"] {
+                env.entry(variable.to_string())
+                    .or_insert_with(|| format!("localhost,127.0.0.1,{PLATFORM_HOST}"));
+            }
+        }
+        env.insert("FUNCTION_NAME".to_string(), config.name.clone());
+        env.insert("FUNCTION_VERSION".to_string(), config.version.clone());
+        env.insert(INTERNAL_URL_ENV.to_string(), internal_url());
+        for (variable, secret) in &config.secrets {
+            let value = self
+                .secrets
+                .get(redis_manager, &secret.secret_ref)
+                .with_context(|| format!("Не удалось получить секрет для '{variable}'"))?;
+            env.insert(variable.clone(), value);
+        }
+
+        let mut pairs = Vec::with_capacity(env.len());
+        for (variable, value) in env {
+            if variable.is_empty() || variable.contains('=') {
+                bail!("Некорректное имя переменной окружения '{variable}' у функции '{}'", config.name);
+            }
+            pairs.push(format!("{variable}={value}"));
+        }
+        pairs.sort();
+        Ok(pairs)
+    }
+
+    /// Running container functions whose config references the secret.
+    pub async fn functions_using_secret(&self, secret_name: &str) -> Vec<String> {
+        let deployed = self.deployed_functions.read().await;
+        let mut names = deployed
+            .iter()
+            .filter(|(_, running)| {
+                running.container_config.is_some()
+                    && running
+                        .config
+                        .secrets
+                        .values()
+                        .any(|secret| secret.secret_ref == secret_name)
+            })
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Re-resolves the environment and replaces replicas one at a time, so
+    /// the function keeps serving while picking up rotated secrets. Warm
+    /// containers and checkpoints carry the old environment and are dropped.
+    pub async fn rolling_restart(&self, function_name: &str, redis_manager: &RedisManager) -> Result<()> {
+        let lock = self.replica_change_lock(function_name);
+        let _changing = lock.lock().await;
+        let (old_container_ids, warm_container_ids, checkpoint) = {
+            let mut deployed = self.deployed_functions.write().await;
+            let running = deployed
+                .get_mut(function_name)
+                .ok_or(FunctionError::FunctionNotDeployed)?;
+            let env = self.container_env(&running.config, redis_manager)?;
+            let container_config = running
+                .container_config
+                .as_mut()
+                .ok_or_else(|| anyhow!("Функция '{function_name}' не использует контейнеры"))?;
+            container_config.env = Some(env);
+            let warm_container_ids = std::mem::take(&mut running.warm_container_ids);
+            for container_id in &warm_container_ids {
+                running.replica_addrs.remove(container_id);
+            }
+            (
+                running.container_ids.clone(),
+                warm_container_ids,
+                running.checkpoint.take(),
+            )
+        };
+
+        for container_id in warm_container_ids {
+            self.remove_replica(&container_id).await;
+        }
+        if let Some(checkpoint) = checkpoint {
+            let _ = tokio::fs::remove_dir_all(&checkpoint.dir).await;
+        }
+
+        for container_id in old_container_ids {
+            let replacement = self
+                .add_container_replica(function_name, redis_manager)
+                .await?;
+            info!("Replaced replica '{container_id}' of '{function_name}' with '{replacement}'");
+            self.remove_container(function_name, &container_id, redis_manager)
+                .await;
+            self.reconfigure_balancer(function_name).await;
+        }
+
+        self.maintain_warm_pool(function_name).await
+    }
+
+    async fn reconfigure_balancer(&self, function_name: &str) {
+        let (container_ids, replica_weights, balancer_name) = {
+            let deployed = self.deployed_functions.read().await;
+            match deployed.get(function_name) {
+                Some(running) => (
+                    running.container_ids.clone(),
+                    running.config.replica_weights.clone(),
+                    running.config.load_balancer.clone(),
+                ),
+                None => return,
+            }
+        };
+        let mut load_balancers = self.load_balancers.write().await;
+        if let Some(load_balancer) = load_balancers.get(function_name) {
+            load_balancer.configure_function(function_name, &container_ids, &replica_weights);
+        } else if !container_ids.is_empty() {
+            let kind = parse_load_balancer_kind(&balancer_name)
+                .unwrap_or(LoadBalancingKind::RoundRobin);
+            let load_balancer = create_balancer(kind);
+            load_balancer.configure_function(function_name, &container_ids, &replica_weights);
+            load_balancers.insert(function_name.to_string(), load_balancer);
+        }
+    }
+
+    /// Resizes a running container function in place instead of redeploying.
+    pub async fn scale_function(
+        &self,
+        function_name: &str,
+        replicas: u16,
+        warm_pool: u16,
+        redis_manager: &RedisManager,
+    ) -> Result<()> {
+        let lock = self.replica_change_lock(function_name);
+        let _changing = lock.lock().await;
+        let current = {
+            let mut deployed = self.deployed_functions.write().await;
+            let running = deployed
+                .get_mut(function_name)
+                .ok_or(FunctionError::FunctionNotDeployed)?;
+            running.config.replicas = replicas;
+            running.config.warm_pool = warm_pool;
+            running.container_ids.clone()
+        };
+
+        if current.len() < replicas as usize {
+            for _ in current.len()..replicas as usize {
+                self.add_container_replica(function_name, redis_manager)
+                    .await?;
+            }
+        } else {
+            for container_id in current.iter().skip(replicas as usize) {
+                self.remove_container(function_name, container_id, redis_manager)
+                    .await;
+            }
+            self.reconfigure_balancer(function_name).await;
+        }
+
+        self.maintain_warm_pool(function_name).await
+    }
+
+    /// Tops up (or trims) the warm pool to the configured size.
+    pub async fn maintain_warm_pool(&self, function_name: &str) -> Result<()> {
+        let (target, current) = {
+            let deployed = self.deployed_functions.read().await;
+            let Some(running) = deployed.get(function_name) else {
+                return Ok(());
+            };
+            if running.config.runtime != FunctionRuntime::Docker {
+                return Ok(());
+            }
+            (
+                running.config.warm_pool as usize,
+                running.warm_container_ids.clone(),
+            )
+        };
+
+        for container_id in current.iter().skip(target) {
+            self.remove_replica(container_id).await;
+            let mut deployed = self.deployed_functions.write().await;
+            if let Some(running) = deployed.get_mut(function_name) {
+                running.warm_container_ids.retain(|id| id != container_id);
+                running.replica_addrs.remove(container_id);
+            }
+        }
+
+        if current.len() >= target {
+            return Ok(());
+        }
+        let template = self.container_template(function_name).await?;
+        for _ in current.len()..target {
+            let (container_id, addr, _) = self
+                .start_container_replica(function_name, &template)
+                .await?;
+            let mut deployed = self.deployed_functions.write().await;
+            match deployed.get_mut(function_name) {
+                Some(running) => {
+                    running.warm_container_ids.push(container_id.clone());
+                    running
+                        .replica_addrs
+                        .insert(container_id, addr);
+                }
+                None => {
+                    drop(deployed);
+                    self.remove_replica(&container_id).await;
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn replica_change_lock(&self, function_name: &str) -> Arc<Mutex<()>> {
+        let mut locks = self
+            .replica_changes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        Arc::clone(locks.entry(function_name.to_string()).or_default())
+    }
+
+    /// Drops replicas whose containers are no longer running, brings every
+    /// function back to its configured replica count and refills warm pools;
+    /// driven periodically from `main`. Functions being scaled or restarted
+    /// are left for the next round.
+    pub async fn reconcile_replicas(&self, redis_manager: &RedisManager) {
+        let function_names: Vec<String> = {
+            let deployed = self.deployed_functions.read().await;
+            deployed
+                .iter()
+                .filter(|(_, running)| {
+                    matches!(running.config.runtime, FunctionRuntime::Docker | FunctionRuntime::Process)
+                })
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+
+        for function_name in function_names {
+            let lock = self.replica_change_lock(&function_name);
+            let Ok(_changing) = lock.try_lock() else {
+                continue;
+            };
+            let replicas = {
+                let deployed = self.deployed_functions.read().await;
+                deployed.get(&function_name).map(|running| {
+                    (
+                        running.container_ids.clone(),
+                        running.warm_container_ids.clone(),
+                    )
+                })
+            };
+            let Some((container_ids, warm_container_ids)) = replicas else {
+                continue;
+            };
+
+            for container_id in warm_container_ids {
+                if !self.container_manager.is_running(&container_id).await {
+                    warn!("Warm container '{container_id}' of '{function_name}' is not running");
+                    self.remove_replica(&container_id).await;
+                    let mut deployed = self.deployed_functions.write().await;
+                    if let Some(running) = deployed.get_mut(&function_name) {
+                        running.warm_container_ids.retain(|id| id != &container_id);
+                        running.replica_addrs.remove(&container_id);
+                    }
+                }
+            }
+
+            for container_id in container_ids {
+                if self.replica_is_running(&container_id).await {
+                    continue;
+                }
+                // A stop or redeploy may have replaced the deployment since
+                // the snapshot was taken.
+                let still_deployed = self
+                    .deployed_functions
+                    .read()
+                    .await
+                    .get(&function_name)
+                    .is_some_and(|running| running.container_ids.contains(&container_id));
+                if still_deployed {
+                    warn!("Replica '{container_id}' of '{function_name}' crashed, removing");
+                    self.remove_container(&function_name, &container_id, redis_manager)
+                        .await;
+                }
+            }
+
+            let missing = {
+                let deployed = self.deployed_functions.read().await;
+                deployed.get(&function_name).map_or(0, |running| {
+                    (running.config.replicas as usize).saturating_sub(running.container_ids.len())
+                })
+            };
+            for _ in 0..missing {
+                if let Err(e) = self
+                    .add_container_replica(&function_name, redis_manager)
+                    .await
+                {
+                    error!("Failed to replace replica of '{function_name}': {e}");
+                    break;
+                }
+            }
+
+            if let Err(e) = self.maintain_warm_pool(&function_name).await {
+                error!("Failed to refill warm pool of '{function_name}': {e}");
+            }
+        }
+
+        self.refresh_admission_capacity().await;
+    }
+
+    /// Process replicas are gone once their supervisor gives up; wasm replicas
+    /// have nothing that can die.
+    async fn replica_is_running(&self, replica_id: &str) -> bool {
+        if ProcessManager::is_process_replica(replica_id) {
+            self.process_manager.is_running(replica_id).await
+        } else if WasmRuntime::is_wasm_replica(replica_id) {
+            true
+        } else {
+            self.container_manager.is_running(replica_id).await
+        }
+    }
+
+    /// Applies replica count changes to admission queues so waiting requests
+    /// move as soon as replicas are added, not only when a slot frees up.
+    async fn refresh_admission_capacity(&self) {
+        let deployed = self.deployed_functions.read().await;
+        for (function_name, running) in deployed.iter() {
+            if let Some(limits) = running.admission_limits()
+                && let Some(queue) = self.admission.existing_queue(function_name)
+            {
+                queue.configure(limits);
+            }
+        }
+    }
+
+    /// Per-function counters merged with admission queue state.
+    pub fn metrics_report(&self) -> HashMap<String, FunctionMetricsReport> {
+        let mut report = self.metrics.report();
+        for (function_name, queue) in self.admission.stats() {
+            report.entry(function_name).or_default().admission_queue = Some(queue);
+        }
+        report
+    }
+
+    /// Spawns a process replica and waits until its port accepts
+    /// connections, e.g. until `go run` has compiled the function.
+    async fn start_process_replica(&self, config: &FunctionConfig) -> Result<(String, SocketAddr)> {
+        let (replica_id, port) = self.process_manager.spawn_replica(config).await?;
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        if let Err(e) = self
+            .container_manager
+            .wait_until_ready(addr, readiness_timeout(config))
+            .await
+        {
+            self.process_manager.remove_process(&replica_id).await;
+            return Err(e.context(format!("Процесс-реплика функции '{}' не начала принимать соединения", config.name)));
+        }
+        Ok((replica_id, addr))
+    }
+
+    async fn deploy_processes(
+        &self,
+        config: &FunctionConfig,
+    ) -> Result<(Vec<String>, HashMap<String, SocketAddr>)> {
+        info!("Starting {} process replicas for '{}'", config.replicas, config.name);
+        let mut replica_ids = Vec::with_capacity(config.replicas as usize);
+        let mut addrs_by_replica = HashMap::with_capacity(config.replicas as usize);
+        for _ in 0..config.replicas {
+            match self.start_process_replica(config).await {
+                Ok((replica_id, addr)) => {
+                    addrs_by_replica.insert(replica_id.clone(), addr);
+                    replica_ids.push(replica_id);
+                }
+                Err(e) => {
+                    for replica_id in &replica_ids {
+                        self.process_manager.remove_process(replica_id).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok((replica_ids, addrs_by_replica))
+    }
+}
+
+fn readiness_timeout(config: &FunctionConfig) -> Duration {
+    Duration::from_secs(config.timeout.max(1) as u64)
+}
+
+fn elapsed_ms(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64() * 1000.0
 }
 
 fn parse_load_balancer_kind(raw: &str) -> Option<LoadBalancingKind> {
@@ -382,9 +1999,9 @@ mod tests {
 
     use futures_util::FutureExt;
 
-    use crate::redis_manager::RedisManager;
+    use crate::redis_manager::{RedisManager, StateCondition};
 
-    use super::FunctionManager;
+    use super::{FunctionConfig, FunctionConfigUpdate, FunctionManager, RunningFunction};
 
     const MB_TO_BYTES: i64 = 1024 * 1024;
 
@@ -405,8 +2022,8 @@ mod tests {
 
         let host_config = running
             .container_config
-            .host_config
             .as_ref()
+            .and_then(|container_config| container_config.host_config.as_ref())
             .expect("host config should be configured");
         assert_eq!(
             host_config.memory,
@@ -444,6 +2061,104 @@ mod tests {
         }
     }
 
+    #[test]
+    fn replica_only_updates_are_scale_only() {
+        let update: FunctionConfigUpdate =
+            serde_json::from_value(serde_json::json!({ "replicas": 4, "warmPool": 2 }))
+                .expect("update should parse");
+        assert!(update.is_scale_only());
+
+        let update: FunctionConfigUpdate =
+            serde_json::from_value(serde_json::json!({ "replicas": 4, "memory": 512 }))
+                .expect("update should parse");
+        assert!(!update.is_scale_only());
+    }
+
+    #[test]
+    fn listed_functions_do_not_expose_secret_values() {
+        let config: FunctionConfig = serde_json::from_value(serde_json::json!({
+            "name": "example",
+            "innerPort": 8080,
+            "memory": 128,
+            "timeout": 5,
+            "version": "1",
+            "env": { "LOG_LEVEL": "debug" },
+            "secrets": { "DB_PASSWORD": { "secretRef": "db-password" } }
+        }))
+        .expect("config should parse");
+        let running = RunningFunction {
+            config,
+            container_config: Some(bollard::secret::ContainerCreateBody {
+                env: Some(vec!["DB_PASSWORD=hunter2".to_string()]),
+                ..Default::default()
+            }),
+            container_ids: Vec::new(),
+            warm_container_ids: Vec::new(),
+            replica_addrs: Default::default(),
+            checkpoint: None,
+        };
+
+        let listed = serde_json::to_value(&running).expect("function should serialize");
+        assert!(!listed.to_string().contains("hunter2"));
+        assert_eq!(listed["config"]["secrets"]["DB_PASSWORD"]["secretRef"], "db-password");
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local redis on 127.0.0.1:6379"]
+    async fn state_quota_update_applies_without_redeploy() {
+        let manager = FunctionManager::new().expect("function manager should be created");
+        let redis = RedisManager::new().expect("redis should be available for this test");
+        let function_name = format!("state-quota-{}", uuid::Uuid::now_v7().simple());
+        let function_dir = format!("functions/{function_name}");
+        tokio::fs::create_dir_all(&function_dir).await.unwrap();
+        let config = serde_json::json!({
+            "name": function_name,
+            "innerPort": 8080,
+            "memory": 128,
+            "timeout": 5,
+            "version": "1"
+        });
+        tokio::fs::write(format!("{function_dir}/function.json"), config.to_string())
+            .await
+            .unwrap();
+        let config = FunctionManager::read_function_config(&function_name)
+            .await
+            .expect("function config should be readable");
+        manager.deployed_functions.write().await.insert(
+            function_name.clone(),
+            RunningFunction {
+                config,
+                container_config: None,
+                container_ids: Vec::new(),
+                warm_container_ids: Vec::new(),
+                replica_addrs: Default::default(),
+                checkpoint: None,
+            },
+        );
+
+        let update: FunctionConfigUpdate =
+            serde_json::from_value(serde_json::json!({ "state": { "maxKeys": 1 } }))
+                .expect("update should parse");
+        assert!(update.is_policy_only());
+        manager
+            .update_function_config(&function_name, update, &redis)
+            .await
+            .expect("update should succeed");
+
+        let max_keys = manager
+            .state_config(&function_name)
+            .await
+            .expect("function should be deployed")
+            .max_keys;
+        assert_eq!(max_keys, 1);
+        let write = |key| redis.state_write(&function_name, key, "1", 0, max_keys, StateCondition::Always);
+        assert!(write("a").expect("first key should fit the quota"));
+        assert!(write("b").is_err());
+
+        redis.state_delete(&function_name, "a").expect("delete");
+        let _ = tokio::fs::remove_dir_all(&function_dir).await;
+    }
+
     #[tokio::test]
     #[ignore = "requires docker daemon, tar and local redis"]
     async fn deploy_and_invoke_example_main_flow() {
@@ -460,7 +2175,7 @@ mod tests {
             let expected_memory_bytes = config.memory * MB_TO_BYTES;
 
             manager
-                .deploy_function(config, &redis)
+                .deploy_function(config, &redis, false)
                 .await
                 .expect("deploy should succeed");
 
@@ -524,6 +2239,34 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    #[ignore = "requires docker daemon, tar and local redis"]
+    async fn default_security_profile_can_write_the_shared_volume() {
+        let manager = FunctionManager::new().expect("docker should be available for this test");
+        let redis = RedisManager::new().expect("redis should be available for this test");
+        let function_name = "example-js";
+
+        run_with_cleanup(&manager, &redis, || async {
+            let config = FunctionManager::read_function_config(function_name)
+                .await
+                .expect("example function config should be readable");
+            assert_eq!(config.security, Default::default(), "example-js should use the defaults");
+            manager
+                .deploy_function(config, &redis, false)
+                .await
+                .expect("deploy should succeed");
+
+            let container_id = manager.deployed_functions.read().await[function_name].container_ids[0].clone();
+            // example-js runs as root in its image, so replicas fall back to nobody.
+            manager
+                .container_manager
+                .exec(&container_id, &["sh", "-c", "[ \"$(id -u)\" = 65534 ] && touch /shared_data/written"])
+                .await
+                .expect("replica should write the shared volume as nobody");
+        })
+        .await;
+    }
+
     #[tokio::test]
     #[ignore = "requires docker daemon, tar and local redis"]
     async fn integration_example_go() {
@@ -540,7 +2283,7 @@ mod tests {
             let expected_memory_bytes = config.memory * MB_TO_BYTES;
 
             manager
-                .deploy_function(config, &redis)
+                .deploy_function(config, &redis, false)
                 .await
                 .expect("deploy should succeed");
 
@@ -554,7 +2297,7 @@ mod tests {
             .await;
 
             let array = [9, 4, 6, 32, 5, 7, 82, 3];
-            let mut expected = array.clone();
+            let mut expected = array;
             expected.sort();
             let invoke_result = manager
                 .try_invoke(function_name, serde_json::json!({"numbers": array}))
@@ -586,7 +2329,7 @@ mod tests {
             let expected_replicas = config.replicas as usize;
 
             manager
-                .deploy_function(config, &redis)
+                .deploy_function(config, &redis, false)
                 .await
                 .expect("deploy should succeed");
 
@@ -622,7 +2365,7 @@ mod tests {
             let expected_replicas = config.replicas as usize;
             let expected_memory_bytes = config.memory * MB_TO_BYTES;
 
-            if let Err(error) = manager.deploy_function(config, &redis).await {
+            if let Err(error) = manager.deploy_function(config, &redis, false).await {
                 if is_infra_network_error(&error.to_string()) {
                     eprintln!(
                         "Skipping integration_example_rust because Docker network access is unavailable: {error}"
@@ -0,0 +1,299 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use bollard::secret::ImageSummary;
+use serde::{Deserialize, Serialize};
+
+use crate::{container_manager::VERSION_LABEL, volume::FUNCTION_LABEL};
+
+const KEEP_VERSIONS_ENV: &str = "SERVERLESS_GC_KEEP_VERSIONS";
+const MAX_DISK_ENV: &str = "SERVERLESS_GC_MAX_DISK_MB";
+const INTERVAL_ENV: &str = "SERVERLESS_GC_INTERVAL_SECS";
+const DEFAULT_KEEP_VERSIONS: usize = 3;
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MB_TO_BYTES: u64 = 1024 * 1024;
+
+/// What garbage collection keeps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Newest images kept per function, deployed ones are kept regardless.
+    pub keep_versions: usize,
+    /// Budget for function images plus the build cache.
+    pub max_disk_bytes: Option<u64>,
+}
+
+impl GcPolicy {
+    fn validate(&self) -> Result<()> {
+        if self.keep_versions == 0 {
+            bail!("'keepVersions' должен быть больше 0");
+        }
+        Ok(())
+    }
+}
+
+pub struct GcSettings {
+    pub policy: GcPolicy,
+    /// `None` when scheduled collection is disabled.
+    pub interval: Option<Duration>,
+}
+
+fn env_number<T: FromStr>(name: &str) -> Result<Option<T>> {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| anyhow::anyhow!("Некорректное значение {name}: '{value}'")),
+        Err(_) => Ok(None),
+    }
+}
+
+impl GcSettings {
+    pub fn from_env() -> Result<Self> {
+        let policy = GcPolicy {
+            keep_versions: env_number(KEEP_VERSIONS_ENV)?.unwrap_or(DEFAULT_KEEP_VERSIONS),
+            max_disk_bytes: env_number::<u64>(MAX_DISK_ENV)?.map(|mb| mb * MB_TO_BYTES),
+        };
+        policy.validate().with_context(|| format!("Некорректное значение {KEEP_VERSIONS_ENV}"))?;
+        let interval = match env_number::<u64>(INTERVAL_ENV)? {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(DEFAULT_INTERVAL),
+        };
+        Ok(Self { policy, interval })
+    }
+}
+
+/// Body of `POST /admin/gc`; unset fields fall back to the configured policy.
+#[derive(Debug, Default, Deserialize)]
+pub struct GcRequest {
+    #[serde(default, rename = "dryRun")]
+    pub dry_run: bool,
+    #[serde(rename = "keepVersions")]
+    pub keep_versions: Option<usize>,
+    #[serde(rename = "maxDiskMb")]
+    pub max_disk_mb: Option<u64>,
+}
+
+impl GcRequest {
+    pub fn policy(&self, configured: &GcPolicy) -> Result<GcPolicy> {
+        let policy = GcPolicy {
+            keep_versions: self.keep_versions.unwrap_or(configured.keep_versions),
+            max_disk_bytes: self
+                .max_disk_mb
+                .map(|mb| mb * MB_TO_BYTES)
+                .or(configured.max_disk_bytes),
+        };
+        policy.validate()?;
+        Ok(policy)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GcItemKind {
+    Image,
+    /// A function image whose tag moved to a newer build.
+    DanglingImage,
+    BuildCache,
+    /// A tarball left in the temp dir by an interrupted build.
+    BuildContext,
+    Network,
+    Volume,
+}
+
+/// Something garbage collection removes, or would remove in a dry run.
+#[derive(Debug, Clone, Serialize)]
+pub struct GcItem {
+    pub kind: GcItemKind,
+    pub name: String,
+    #[serde(rename = "sizeBytes", skip_serializing_if = "Option::is_none")]
+    pub size_bytes: Option<u64>,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GcFailure {
+    pub name: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GcReport {
+    #[serde(rename = "dryRun")]
+    pub dry_run: bool,
+    pub removed: Vec<GcItem>,
+    pub failed: Vec<GcFailure>,
+    #[serde(rename = "reclaimedBytes")]
+    pub reclaimed_bytes: u64,
+}
+
+/// A tagged image built for a function version.
+#[derive(Debug, Clone)]
+pub struct FunctionImage {
+    pub function: String,
+    pub version: String,
+    /// Unix seconds.
+    pub created: i64,
+    pub size_bytes: u64,
+    pub deployed: bool,
+}
+
+impl FunctionImage {
+    pub fn tag(&self) -> String {
+        format!("{}:{}", self.function, self.version)
+    }
+
+    fn removal(&self, reason: String) -> GcItem {
+        GcItem {
+            kind: GcItemKind::Image,
+            name: self.tag(),
+            size_bytes: Some(self.size_bytes),
+            reason,
+        }
+    }
+}
+
+/// Splits images labeled by the platform into tagged function versions and
+/// dangling leftovers of rebuilt versions, which are always removed.
+pub fn classify_images(
+    summaries: Vec<ImageSummary>,
+    is_deployed: impl Fn(&str) -> bool,
+) -> (Vec<FunctionImage>, Vec<GcItem>) {
+    let mut images = Vec::new();
+    let mut dangling = Vec::new();
+    for summary in summaries {
+        let size_bytes = u64::try_from(summary.size).unwrap_or_default();
+        let (Some(function), Some(version)) = (
+            summary.labels.get(FUNCTION_LABEL),
+            summary.labels.get(VERSION_LABEL),
+        ) else {
+            continue;
+        };
+        let tag = format!("{function}:{version}");
+        if summary.repo_tags.contains(&tag) {
+            images.push(FunctionImage {
+                function: function.clone(),
+                version: version.clone(),
+                created: summary.created,
+                size_bytes,
+                deployed: is_deployed(&tag),
+            });
+        } else if summary.containers <= 0 {
+            dangling.push(GcItem {
+                kind: GcItemKind::DanglingImage,
+                name: summary.id,
+                size_bytes: Some(size_bytes),
+                reason: format!("прежняя сборка {tag}"),
+            });
+        }
+    }
+    (images, dangling)
+}
+
+/// Picks function images to remove: all but the `keep_versions` newest of
+/// every function, then, while images and build cache together exceed
+/// `max_disk_bytes`, the oldest remaining ones. Deployed images and the
+/// newest image of each function are always kept. Also returns whether the
+/// build cache has to be pruned to fit the budget.
+pub fn plan_image_removals(
+    images: &[FunctionImage],
+    build_cache_bytes: u64,
+    policy: &GcPolicy,
+) -> (Vec<GcItem>, bool) {
+    let mut by_function: HashMap<&str, Vec<&FunctionImage>> = HashMap::new();
+    for image in images {
+        by_function.entry(&image.function).or_default().push(image);
+    }
+
+    let mut removals = Vec::new();
+    let mut kept = Vec::new();
+    for group in by_function.values_mut() {
+        group.sort_by_key(|image| std::cmp::Reverse(image.created));
+        for (rank, image) in group.iter().enumerate() {
+            if image.deployed || rank < policy.keep_versions {
+                kept.push((*image, rank == 0));
+            } else {
+                removals.push(image.removal(format!(
+                    "старше {} последних версий",
+                    policy.keep_versions
+                )));
+            }
+        }
+    }
+
+    let mut prune_build_cache = false;
+    if let Some(max_disk_bytes) = policy.max_disk_bytes {
+        let mut usage = kept.iter().map(|(image, _)| image.size_bytes).sum::<u64>() + build_cache_bytes;
+        kept.sort_by_key(|(image, _)| image.created);
+        for (image, newest) in kept {
+            if usage <= max_disk_bytes {
+                break;
+            }
+            if image.deployed || newest {
+                continue;
+            }
+            usage -= image.size_bytes;
+            removals.push(image.removal("превышен лимит диска".to_string()));
+        }
+        prune_build_cache = usage > max_disk_bytes && build_cache_bytes > 0;
+    }
+    removals.sort_by(|a, b| a.name.cmp(&b.name));
+    (removals, prune_build_cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FunctionImage, GcPolicy, plan_image_removals};
+
+    fn image(function: &str, version: &str, created: i64, deployed: bool) -> FunctionImage {
+        FunctionImage {
+            function: function.to_string(),
+            version: version.to_string(),
+            created,
+            size_bytes: 100,
+            deployed,
+        }
+    }
+
+    fn removed_tags(images: &[FunctionImage], build_cache_bytes: u64, policy: &GcPolicy) -> (Vec<String>, bool) {
+        let (removals, prune_build_cache) = plan_image_removals(images, build_cache_bytes, policy);
+        (removals.into_iter().map(|item| item.name).collect(), prune_build_cache)
+    }
+
+    #[test]
+    fn old_versions_are_removed_but_deployed_ones_kept() {
+        let images = [
+            image("orders", "1", 1, true),
+            image("orders", "2", 2, false),
+            image("orders", "3", 3, false),
+            image("orders", "4", 4, false),
+            image("billing", "1", 1, false),
+        ];
+        let policy = GcPolicy {
+            keep_versions: 2,
+            max_disk_bytes: None,
+        };
+        assert_eq!(removed_tags(&images, 0, &policy), (vec!["orders:2".to_string()], false));
+    }
+
+    #[test]
+    fn disk_budget_removes_oldest_images_then_build_cache() {
+        let images = [
+            image("orders", "1", 1, false),
+            image("orders", "2", 2, false),
+            image("billing", "1", 3, true),
+            image("billing", "2", 4, false),
+        ];
+        let policy = GcPolicy {
+            keep_versions: 5,
+            max_disk_bytes: Some(300),
+        };
+        assert_eq!(removed_tags(&images, 0, &policy), (vec!["orders:1".to_string()], false));
+
+        // Only the newest image of each function and the deployed one remain,
+        // so the build cache has to go as well.
+        let (removed, prune_build_cache) = removed_tags(&images, 200, &policy);
+        assert_eq!(removed, ["orders:1"]);
+        assert!(prune_build_cache);
+    }
+}
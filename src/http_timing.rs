@@ -0,0 +1,107 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use tower::{Layer, Service};
+
+tokio::task_local! {
+    /// Microseconds spent establishing connections for the current invocation.
+    static CONNECT_MICROS: Arc<AtomicU64>;
+}
+
+/// Runs `future` and returns how long the reqwest connector spent opening new
+/// connections on its behalf. Reused pooled connections cost nothing here.
+pub async fn with_connect_timer<F: Future>(future: F) -> (F::Output, Duration) {
+    let timer = Arc::new(AtomicU64::new(0));
+    let output = CONNECT_MICROS.scope(Arc::clone(&timer), future).await;
+    (output, Duration::from_micros(timer.load(Ordering::Relaxed)))
+}
+
+/// Connector layer that charges connection setup time to the invocation whose
+/// request triggered it (see [`with_connect_timer`]).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectTimingLayer;
+
+impl<S> Layer<S> for ConnectTimingLayer {
+    type Service = ConnectTiming<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ConnectTiming { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectTiming<S> {
+    inner: S,
+}
+
+impl<S, Request> Service<Request> for ConnectTiming<S>
+where
+    S: Service<Request>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let timer = CONNECT_MICROS.try_with(Arc::clone).ok();
+        let started = Instant::now();
+        let connecting = self.inner.call(request);
+        Box::pin(async move {
+            let connection = connecting.await;
+            if let Some(timer) = timer {
+                timer.fetch_add(started.elapsed().as_micros() as u64, Ordering::Relaxed);
+            }
+            connection
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::with_connect_timer;
+
+    #[tokio::test]
+    async fn new_connections_are_charged_to_the_invocation() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("listener should bind");
+        let port = listener.local_addr().expect("local addr").port();
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buffer = [0_u8; 1024];
+                    let _ = tokio::io::AsyncReadExt::read(&mut socket, &mut buffer).await;
+                    let _ = socket
+                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                        .await;
+                });
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .connector_layer(super::ConnectTimingLayer)
+            .build()
+            .expect("client should build");
+        let url = format!("http://127.0.0.1:{port}/");
+        let (response, connect) = with_connect_timer(client.get(&url).send()).await;
+
+        assert!(response.expect("request should succeed").status().is_success());
+        assert!(connect > Duration::ZERO);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::ApiKeyPrincipal, errors::idempotency_error::IdempotencyError, jwt::VerifiedClaims,
+    redis_manager::RedisManager,
+};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+const IN_PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How often the owner of a key refreshes its in-progress marker, as a
+/// share of the marker's TTL.
+const HEARTBEATS_PER_TTL: u32 = 3;
+
+fn default_response_cache_ttl_secs() -> u64 {
+    60
+}
+
+/// Opt-in cache of successful responses keyed by payload (`"responseCache"`
+/// in `function.json`). Only meant for pure functions.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ResponseCacheConfig {
+    #[serde(default = "default_response_cache_ttl_secs", rename = "ttlSecs")]
+    pub ttl_secs: u64,
+}
+
+/// What `/invoke` needs to know about a function to dedupe and cache calls.
+#[derive(Debug, Clone)]
+pub struct InvokeCacheSettings {
+    pub version: String,
+    pub idempotency_ttl_secs: u64,
+    /// How long a duplicate waits for the first call before giving up; also
+    /// the lifetime of the in-progress marker if the server dies mid-call.
+    pub in_progress_timeout: Duration,
+    pub response_cache: Option<ResponseCacheConfig>,
+}
+
+/// Stored under an idempotency key: pending until `response` is set.
+#[derive(Debug, Serialize, Deserialize)]
+struct IdempotencyRecord {
+    #[serde(rename = "payloadHash")]
+    payload_hash: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<Value>,
+}
+
+/// SHA-256 of the payload. `serde_json` keeps object keys sorted, so equal
+/// payloads hash equally regardless of key order in the request.
+pub fn payload_hash(payload: &Value) -> String {
+    format!("{:x}", Sha256::digest(payload.to_string().as_bytes()))
+}
+
+pub fn validate_idempotency_key(key: &str) -> Result<(), IdempotencyError> {
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(IdempotencyError::InvalidKey);
+    }
+    Ok(())
+}
+
+/// An `Idempotency-Key` together with the caller that sent it. Keys are
+/// scoped per caller, so two callers picking the same key never get each
+/// other's responses.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey {
+    pub key: String,
+    caller: String,
+}
+
+impl IdempotencyKey {
+    /// The caller is the token subject (the whole claims set without `sub`),
+    /// otherwise the API key, otherwise anonymous.
+    pub fn new(key: String, principal: Option<&ApiKeyPrincipal>, claims: Option<&VerifiedClaims>) -> Self {
+        let caller = match (claims, principal) {
+            (Some(claims), _) => match claims.claims.get("sub").and_then(Value::as_str) {
+                Some(subject) => format!("sub:{subject}"),
+                None => format!("claims:{}", claims.header_value()),
+            },
+            (None, Some(principal)) => format!("apikey:{}", principal.key_id),
+            (None, None) => "anonymous".to_string(),
+        };
+        Self { key, caller }
+    }
+
+    /// Stored as `{caller hash}:{key}`; the hash keeps a caller id containing
+    /// `:` from colliding with another caller's key.
+    fn scoped(&self) -> String {
+        format!("{:x}:{}", Sha256::digest(self.caller.as_bytes()), self.key)
+    }
+}
+
+pub enum IdempotentStart {
+    /// This request runs the function and must complete or abandon the key.
+    Owner,
+    /// The key was already used with the same payload; here is its response.
+    Replay(Value),
+}
+
+/// Claims the key or waits for the request that holds it. A duplicate that
+/// arrives while the first call is running gets its response once it is
+/// stored; if the first call fails the key is freed and taken over.
+pub async fn start_idempotent(
+    redis_manager: &RedisManager,
+    function_name: &str,
+    idempotency_key: &IdempotencyKey,
+    payload_hash: &str,
+    settings: &InvokeCacheSettings,
+) -> Result<IdempotentStart> {
+    let pending = pending_record(payload_hash)?;
+    let deadline = Instant::now() + settings.in_progress_timeout;
+    loop {
+        let existing = redis_manager.begin_idempotent_invoke(
+            function_name,
+            &idempotency_key.scoped(),
+            &pending,
+            settings.in_progress_timeout.as_secs().max(1),
+        )?;
+        let Some(existing) = existing else {
+            return Ok(IdempotentStart::Owner);
+        };
+        let record: IdempotencyRecord = serde_json::from_str(&existing)?;
+        if record.payload_hash != payload_hash {
+            return Err(IdempotencyError::KeyReused(idempotency_key.key.clone()).into());
+        }
+        if let Some(response) = record.response {
+            return Ok(IdempotentStart::Replay(response));
+        }
+        if Instant::now() >= deadline {
+            return Err(IdempotencyError::InProgress(idempotency_key.key.clone()).into());
+        }
+        tokio::time::sleep(IN_PROGRESS_POLL_INTERVAL).await;
+    }
+}
+
+fn pending_record(payload_hash: &str) -> Result<String> {
+    Ok(serde_json::to_string(&IdempotencyRecord {
+        payload_hash: payload_hash.to_string(),
+        response: None,
+    })?)
+}
+
+/// Runs the owner's invocation while refreshing its in-progress marker, so
+/// a call that outlives the marker's TTL (retries, slow replicas) is never
+/// taken over and run again by a duplicate.
+pub async fn hold_idempotent<T>(
+    redis_manager: &RedisManager,
+    function_name: &str,
+    idempotency_key: &IdempotencyKey,
+    payload_hash: &str,
+    settings: &InvokeCacheSettings,
+    work: impl Future<Output = T>,
+) -> T {
+    let pending = match pending_record(payload_hash) {
+        Ok(pending) => pending,
+        Err(_) => return work.await,
+    };
+    let ttl_secs = settings.in_progress_timeout.as_secs().max(1);
+    let interval = Duration::from_secs(ttl_secs) / HEARTBEATS_PER_TTL;
+    let scoped = idempotency_key.scoped();
+    tokio::pin!(work);
+    loop {
+        tokio::select! {
+            output = &mut work => return output,
+            _ = tokio::time::sleep(interval) => {
+                if let Err(e) = redis_manager.refresh_idempotent_invoke(function_name, &scoped, &pending, ttl_secs) {
+                    warn!(
+                        "Failed to refresh idempotency key '{}' of '{function_name}': {e}",
+                        idempotency_key.key
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub fn complete_idempotent(
+    redis_manager: &RedisManager,
+    function_name: &str,
+    idempotency_key: &IdempotencyKey,
+    payload_hash: &str,
+    response: &Value,
+    settings: &InvokeCacheSettings,
+) -> Result<()> {
+    let record = serde_json::to_string(&IdempotencyRecord {
+        payload_hash: payload_hash.to_string(),
+        response: Some(response.clone()),
+    })?;
+    redis_manager.complete_idempotent_invoke(
+        function_name,
+        &idempotency_key.scoped(),
+        &record,
+        settings.idempotency_ttl_secs,
+    )
+}
+
+/// Frees the key after a failed invocation so the client can retry.
+pub fn abandon_idempotent(
+    redis_manager: &RedisManager,
+    function_name: &str,
+    idempotency_key: &IdempotencyKey,
+) -> Result<()> {
+    redis_manager.abandon_idempotent_invoke(function_name, &idempotency_key.scoped())
+}
+
+/// Cache entries are per version, so a redeploy with a new version never
+/// serves responses of the old code. Headers forwarded to the replica, such
+/// as verified JWT claims, are part of the key as well, so a response
+/// computed for one user is never served to another.
+pub fn response_cache_key(
+    settings: &InvokeCacheSettings,
+    payload_hash: &str,
+    forwarded_headers: &[(String, String)],
+) -> String {
+    if forwarded_headers.is_empty() {
+        return format!("{}:{payload_hash}", settings.version);
+    }
+    let mut hasher = Sha256::new();
+    for (name, value) in forwarded_headers {
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update(value.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{}:{payload_hash}:{:x}", settings.version, hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::{auth::ApiKeyPrincipal, jwt::VerifiedClaims, redis_manager::RedisManager};
+
+    use super::{
+        IdempotencyKey, IdempotentStart, InvokeCacheSettings, complete_idempotent, hold_idempotent,
+        payload_hash, response_cache_key, start_idempotent, validate_idempotency_key,
+    };
+
+    fn settings() -> InvokeCacheSettings {
+        InvokeCacheSettings {
+            version: "1".to_string(),
+            idempotency_ttl_secs: 60,
+            in_progress_timeout: Duration::from_millis(200),
+            response_cache: None,
+        }
+    }
+
+    fn claims(subject: &str) -> VerifiedClaims {
+        VerifiedClaims {
+            header: "x-jwt-claims".to_string(),
+            claims: json!({ "sub": subject, "exp": 1 }),
+        }
+    }
+
+    #[test]
+    fn payload_hash_ignores_key_order() {
+        let first: serde_json::Value = serde_json::from_str(r#"{"b": [3, 1], "a": 1}"#).unwrap();
+        let second = json!({ "a": 1, "b": [3, 1] });
+        assert_eq!(payload_hash(&first), payload_hash(&second));
+        assert_ne!(payload_hash(&first), payload_hash(&json!({ "a": 1, "b": [1, 3] })));
+    }
+
+    #[test]
+    fn idempotency_keys_must_be_short_and_non_empty() {
+        assert!(validate_idempotency_key("order-42").is_ok());
+        assert!(validate_idempotency_key("").is_err());
+        assert!(validate_idempotency_key(&"k".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn idempotency_keys_and_cached_responses_are_scoped_to_the_caller() {
+        let principal = |key_id: &str| ApiKeyPrincipal {
+            key_id: key_id.to_string(),
+            scopes: Vec::new(),
+            limits: None,
+        };
+        let scoped = |principal: Option<&ApiKeyPrincipal>, claims: Option<&VerifiedClaims>| {
+            IdempotencyKey::new("order-42".to_string(), principal, claims).scoped()
+        };
+        let (first, second) = (principal("first"), principal("second"));
+        assert_eq!(scoped(Some(&first), None), scoped(Some(&first), None));
+        assert_ne!(scoped(Some(&first), None), scoped(Some(&second), None));
+        assert_ne!(scoped(Some(&first), None), scoped(None, None));
+        assert_ne!(scoped(None, Some(&claims("alice"))), scoped(None, Some(&claims("bob"))));
+        assert!(scoped(None, None).ends_with(":order-42"));
+
+        let settings = settings();
+        let hash = payload_hash(&json!({ "name": "first" }));
+        let alice = claims("alice");
+        let bob = claims("bob");
+        let forwarded = |claims: &VerifiedClaims| vec![(claims.header.clone(), claims.header_value())];
+        assert_eq!(response_cache_key(&settings, &hash, &[]), format!("1:{hash}"));
+        assert_ne!(
+            response_cache_key(&settings, &hash, &forwarded(&alice)),
+            response_cache_key(&settings, &hash, &forwarded(&bob))
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local redis on 127.0.0.1:6379"]
+    async fn repeats_replay_the_first_response() {
+        let manager = RedisManager::new().expect("redis should be available for this test");
+        let settings = settings();
+        let key = IdempotencyKey::new(uuid::Uuid::now_v7().simple().to_string(), None, None);
+        let hash = payload_hash(&json!({ "name": "first" }));
+
+        let start = start_idempotent(&manager, "example-test", &key, &hash, &settings).await;
+        assert!(matches!(start, Ok(IdempotentStart::Owner)));
+        let other = payload_hash(&json!({ "name": "other" }));
+        assert!(start_idempotent(&manager, "example-test", &key, &other, &settings).await.is_err());
+        assert!(start_idempotent(&manager, "example-test", &key, &hash, &settings).await.is_err());
+
+        let response = json!({ "result": 42 });
+        complete_idempotent(&manager, "example-test", &key, &hash, &response, &settings)
+            .expect("response should be stored");
+        match start_idempotent(&manager, "example-test", &key, &hash, &settings).await {
+            Ok(IdempotentStart::Replay(replayed)) => assert_eq!(replayed, response),
+            _ => panic!("repeat should replay the stored response"),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "requires local redis on 127.0.0.1:6379"]
+    async fn slow_owners_keep_their_key() {
+        let manager = RedisManager::new().expect("redis should be available for this test");
+        let settings = settings();
+        let key = IdempotencyKey::new(uuid::Uuid::now_v7().simple().to_string(), None, None);
+        let hash = payload_hash(&json!({ "name": "slow" }));
+
+        let start = start_idempotent(&manager, "example-test", &key, &hash, &settings).await;
+        assert!(matches!(start, Ok(IdempotentStart::Owner)));
+        // The marker lives one second; the owner runs for well over two.
+        let owner = hold_idempotent(
+            &manager,
+            "example-test",
+            &key,
+            &hash,
+            &settings,
+            tokio::time::sleep(Duration::from_millis(2500)),
+        );
+        let duplicate = async {
+            tokio::time::sleep(Duration::from_millis(1800)).await;
+            start_idempotent(&manager, "example-test", &key, &hash, &settings).await
+        };
+        let ((), duplicate) = tokio::join!(owner, duplicate);
+        assert!(duplicate.is_err(), "a duplicate must not take over a running call");
+    }
+}
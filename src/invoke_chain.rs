@@ -0,0 +1,210 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::{hmac, rand::SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::invoke_chain_error::InvokeChainError;
+
+/// Port the platform API listens on.
+pub const SERVER_PORT: u16 = 5000;
+/// Host name containers resolve to the platform, mapped to the gateway of
+/// their network.
+pub const PLATFORM_HOST: &str = "serverless-platform";
+/// Base URL of the platform API injected into every container replica.
+pub const INTERNAL_URL_ENV: &str = "SERVERLESS_INTERNAL_URL";
+
+pub const INVOKE_TOKEN_HEADER: &str = "x-serverless-invoke-token";
+pub const CHAIN_ID_HEADER: &str = "x-serverless-chain-id";
+pub const CHAIN_DEPTH_HEADER: &str = "x-serverless-chain-depth";
+
+const MAX_DEPTH_ENV: &str = "SERVERLESS_MAX_CALL_DEPTH";
+const DEFAULT_MAX_DEPTH: u32 = 8;
+/// Slack on top of the function timeout, so a token is still valid for
+/// nested calls made right before the replica answers.
+const TOKEN_GRACE: Duration = Duration::from_secs(5);
+
+pub fn internal_url() -> String {
+    format!("http://{PLATFORM_HOST}:{SERVER_PORT}")
+}
+
+/// Position of an invocation in a chain of nested calls. Public invokes
+/// start a new chain at depth 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallChain {
+    pub id: String,
+    pub depth: u32,
+    /// Function whose replica made the call.
+    pub caller: Option<String>,
+}
+
+impl CallChain {
+    pub fn root() -> Self {
+        Self {
+            id: uuid::Uuid::now_v7().simple().to_string(),
+            depth: 0,
+            caller: None,
+        }
+    }
+}
+
+/// What an invoke token vouches for: a replica of `function` is serving a
+/// call at `depth` of chain `chain_id`.
+#[derive(Debug, Serialize, Deserialize)]
+struct InvokeClaims {
+    function: String,
+    #[serde(rename = "chainId")]
+    chain_id: String,
+    depth: u32,
+    /// Expiry as unix seconds.
+    exp: u64,
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Issues and checks the short-lived tokens replicas present to
+/// `/internal/invoke`. The key lives only in memory, so tokens do not
+/// survive a restart of the platform, which no in-flight call does either.
+pub struct InvokeTokens {
+    key: hmac::Key,
+    max_depth: u32,
+}
+
+impl InvokeTokens {
+    pub fn from_env() -> Result<Self> {
+        let max_depth = match std::env::var(MAX_DEPTH_ENV) {
+            Ok(value) => value
+                .parse::<u32>()
+                .with_context(|| format!("Некорректное значение {MAX_DEPTH_ENV}: '{value}'"))?,
+            Err(_) => DEFAULT_MAX_DEPTH,
+        };
+        Self::new(max_depth)
+    }
+
+    fn new(max_depth: u32) -> Result<Self> {
+        let key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .map_err(|_| anyhow::anyhow!("Не удалось сгенерировать ключ токенов вызова"))?;
+        Ok(Self { key, max_depth })
+    }
+
+    /// Headers sent to a replica of `function_name` serving a call of
+    /// `chain`, valid for `timeout` plus some slack.
+    pub fn headers(&self, function_name: &str, chain: &CallChain, timeout: Duration) -> Vec<(String, String)> {
+        let claims = InvokeClaims {
+            function: function_name.to_string(),
+            chain_id: chain.id.clone(),
+            depth: chain.depth,
+            exp: unix_secs() + (timeout + TOKEN_GRACE).as_secs(),
+        };
+        vec![
+            (INVOKE_TOKEN_HEADER.to_string(), self.issue(&claims)),
+            (CHAIN_ID_HEADER.to_string(), chain.id.clone()),
+            (CHAIN_DEPTH_HEADER.to_string(), chain.depth.to_string()),
+        ]
+    }
+
+    fn issue(&self, claims: &InvokeClaims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+        let tag = hmac::sign(&self.key, payload.as_bytes());
+        format!("{payload}.{}", URL_SAFE_NO_PAD.encode(tag.as_ref()))
+    }
+
+    fn verify(&self, token: &str) -> Result<InvokeClaims, InvokeChainError> {
+        let (payload, tag) = token
+            .trim()
+            .split_once('.')
+            .ok_or(InvokeChainError::InvalidToken)?;
+        let tag = URL_SAFE_NO_PAD
+            .decode(tag)
+            .map_err(|_| InvokeChainError::InvalidToken)?;
+        hmac::verify(&self.key, payload.as_bytes(), &tag).map_err(|_| InvokeChainError::InvalidToken)?;
+        let claims: InvokeClaims = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or(InvokeChainError::InvalidToken)?;
+        if claims.exp < unix_secs() {
+            return Err(InvokeChainError::Expired);
+        }
+        Ok(claims)
+    }
+
+    /// Function whose replica presented the token.
+    pub fn function_name(&self, token: &str) -> Result<String, InvokeChainError> {
+        Ok(self.verify(token)?.function)
+    }
+
+    /// Verifies a token and returns the chain of the nested call it allows.
+    pub fn nested_call(&self, token: &str) -> Result<CallChain, InvokeChainError> {
+        let claims = self.verify(token)?;
+        let depth = claims.depth + 1;
+        if depth > self.max_depth {
+            return Err(InvokeChainError::DepthExceeded {
+                chain_id: claims.chain_id,
+                max_depth: self.max_depth,
+            });
+        }
+        Ok(CallChain {
+            id: claims.chain_id,
+            depth,
+            caller: Some(claims.function),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::errors::invoke_chain_error::InvokeChainError;
+
+    use super::{CallChain, INVOKE_TOKEN_HEADER, InvokeTokens};
+
+    fn token(tokens: &InvokeTokens, function_name: &str, chain: &CallChain) -> String {
+        tokens
+            .headers(function_name, chain, Duration::from_secs(30))
+            .into_iter()
+            .find(|(name, _)| name == INVOKE_TOKEN_HEADER)
+            .map(|(_, value)| value)
+            .expect("token header should be set")
+    }
+
+    #[test]
+    fn nested_calls_continue_the_chain_until_the_depth_limit() {
+        let tokens = InvokeTokens::new(2).unwrap();
+        let root = CallChain::root();
+
+        let first = tokens.nested_call(&token(&tokens, "orders", &root)).unwrap();
+        assert_eq!(first.id, root.id);
+        assert_eq!(first.depth, 1);
+        assert_eq!(first.caller.as_deref(), Some("orders"));
+
+        let second = tokens.nested_call(&token(&tokens, "billing", &first)).unwrap();
+        assert_eq!((second.depth, second.caller.as_deref()), (2, Some("billing")));
+        assert!(matches!(
+            tokens.nested_call(&token(&tokens, "orders", &second)),
+            Err(InvokeChainError::DepthExceeded { max_depth: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn forged_and_foreign_tokens_are_rejected() {
+        let tokens = InvokeTokens::new(8).unwrap();
+        let token = token(&tokens, "orders", &CallChain::root());
+        let (payload, tag) = token.split_once('.').unwrap();
+
+        let forged = format!("{payload}x.{tag}");
+        assert!(matches!(tokens.nested_call(&forged), Err(InvokeChainError::InvalidToken)));
+        assert!(matches!(tokens.nested_call("garbage"), Err(InvokeChainError::InvalidToken)));
+
+        let other = InvokeTokens::new(8).unwrap();
+        assert!(matches!(other.nested_call(&token), Err(InvokeChainError::InvalidToken)));
+        assert_eq!(tokens.function_name(&token).unwrap(), "orders");
+    }
+}
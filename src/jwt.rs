@@ -0,0 +1,336 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{Jwk, JwkSet},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::RwLock;
+
+use crate::errors::auth_error::AuthError;
+
+fn default_claims_header() -> String {
+    "x-jwt-claims".to_string()
+}
+
+fn default_jwks_cache_secs() -> u64 {
+    300
+}
+
+/// An unknown `kid` refetches the key set at most this often, so rotated
+/// keys are picked up without letting bad tokens hammer the JWKS endpoint.
+const JWKS_REFRESH_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Per-function bearer token verification (`"jwt"` in `function.json`).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JwtConfig {
+    #[serde(default, rename = "jwksUrl", skip_serializing_if = "Option::is_none")]
+    pub jwks_url: Option<String>,
+    /// Local key set, relative paths are resolved against the function directory.
+    #[serde(default, rename = "jwksPath", skip_serializing_if = "Option::is_none")]
+    pub jwks_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Accepted `aud` values; empty skips the audience check.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audience: Vec<String>,
+    /// Header the verified claims are forwarded to the replica in.
+    #[serde(default = "default_claims_header", rename = "claimsHeader")]
+    pub claims_header: String,
+    #[serde(default = "default_jwks_cache_secs", rename = "jwksCacheSecs")]
+    pub jwks_cache_secs: u64,
+}
+
+impl JwtConfig {
+    fn source(&self, function_name: &str) -> Result<JwksSource> {
+        match (&self.jwks_url, &self.jwks_path) {
+            (Some(url), _) => Ok(JwksSource::Url(url.clone())),
+            (None, Some(path)) => {
+                let path = PathBuf::from(path);
+                Ok(JwksSource::File(if path.is_absolute() {
+                    path
+                } else {
+                    PathBuf::from("functions").join(function_name).join(path)
+                }))
+            }
+            (None, None) => Err(anyhow!(
+                "Для функции '{function_name}' в 'jwt' нужно задать 'jwksUrl' или 'jwksPath'"
+            )),
+        }
+    }
+}
+
+/// Claims of a verified token, attached to the request extensions.
+#[derive(Debug, Clone)]
+pub struct VerifiedClaims {
+    pub header: String,
+    pub claims: Value,
+}
+
+impl VerifiedClaims {
+    /// Claims as base64url JSON, since header values must stay ASCII.
+    pub fn header_value(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.claims.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum JwksSource {
+    Url(String),
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+struct CachedJwks {
+    keys: JwkSet,
+    fetched: Instant,
+}
+
+/// Verifies bearer tokens against JWKS loaded from files or URLs and cached
+/// per source for `jwksCacheSecs`.
+#[derive(Debug)]
+pub struct JwtVerifier {
+    http_client: reqwest::Client,
+    cache: RwLock<HashMap<JwksSource, CachedJwks>>,
+}
+
+impl JwtVerifier {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            http_client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(5))
+                .build()?,
+            cache: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub async fn verify(
+        &self,
+        function_name: &str,
+        config: &JwtConfig,
+        token: &str,
+    ) -> Result<VerifiedClaims> {
+        let claims_header = config.claims_header.to_ascii_lowercase();
+        if reqwest::header::HeaderName::from_bytes(claims_header.as_bytes()).is_err() {
+            return Err(anyhow!(
+                "Некорректный 'claimsHeader' '{}' у функции '{function_name}'",
+                config.claims_header
+            ));
+        }
+        let header = decode_header(token).map_err(invalid_token)?;
+        let source = config.source(function_name)?;
+        let ttl = Duration::from_secs(config.jwks_cache_secs);
+
+        let mut jwk = self.find_key(&source, ttl, header.kid.as_deref(), false).await?;
+        if jwk.is_none() {
+            jwk = self.find_key(&source, ttl, header.kid.as_deref(), true).await?;
+        }
+        let jwk = jwk.ok_or_else(|| AuthError::InvalidToken("неизвестный ключ подписи".to_string()))?;
+
+        if let Some(key_algorithm) = jwk.common.key_algorithm
+            && Algorithm::from_str(&key_algorithm.to_string()).ok() != Some(header.alg)
+        {
+            return Err(AuthError::InvalidToken("алгоритм не совпадает с ключом".to_string()).into());
+        }
+        let key = DecodingKey::from_jwk(&jwk).map_err(invalid_token)?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_required_spec_claims(&["exp"]);
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&config.audience);
+        }
+
+        let data = decode::<Value>(token, &key, &validation).map_err(invalid_token)?;
+        Ok(VerifiedClaims {
+            header: claims_header,
+            claims: data.claims,
+        })
+    }
+
+    /// Looks the key up in the cached set, loading it when missing or stale.
+    /// `refresh` forces a reload (subject to the cooldown) for rotated keys.
+    async fn find_key(
+        &self,
+        source: &JwksSource,
+        ttl: Duration,
+        kid: Option<&str>,
+        refresh: bool,
+    ) -> Result<Option<Jwk>> {
+        {
+            let cache = self.cache.read().await;
+            if let Some(cached) = cache.get(source) {
+                let age = cached.fetched.elapsed();
+                let reload = if refresh { age >= JWKS_REFRESH_COOLDOWN } else { age >= ttl };
+                if !reload {
+                    return Ok(select_key(&cached.keys, kid));
+                }
+            }
+        }
+
+        let keys = self.load(source).await?;
+        let jwk = select_key(&keys, kid);
+        self.cache.write().await.insert(
+            source.clone(),
+            CachedJwks {
+                keys,
+                fetched: Instant::now(),
+            },
+        );
+        Ok(jwk)
+    }
+
+    async fn load(&self, source: &JwksSource) -> Result<JwkSet> {
+        match source {
+            JwksSource::Url(url) => self
+                .http_client
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<JwkSet>()
+                .await
+                .with_context(|| format!("Не удалось загрузить JWKS '{url}'")),
+            JwksSource::File(path) => {
+                let content = tokio::fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Не удалось прочитать JWKS '{}'", path.display()))?;
+                serde_json::from_str(&content)
+                    .with_context(|| format!("Некорректный JWKS '{}'", path.display()))
+            }
+        }
+    }
+}
+
+/// Tokens without `kid` are accepted only when the set has a single key.
+fn select_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+fn invalid_token(error: jsonwebtoken::errors::Error) -> anyhow::Error {
+    AuthError::InvalidToken(error.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header, encode};
+    use ring::{
+        rand::SystemRandom,
+        signature::{Ed25519KeyPair, KeyPair},
+    };
+    use serde_json::json;
+
+    use super::{JwtConfig, JwtVerifier};
+
+    struct TestIssuer {
+        encoding_key: EncodingKey,
+        jwks_path: std::path::PathBuf,
+    }
+
+    impl TestIssuer {
+        fn new() -> Self {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).expect("key should generate");
+            let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("key should parse");
+            let jwks = json!({
+                "keys": [{
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "kid": "test-key",
+                    "alg": "EdDSA",
+                    "x": URL_SAFE_NO_PAD.encode(pair.public_key().as_ref()),
+                }]
+            });
+            let jwks_path = std::env::temp_dir().join(format!("jwks-{}.json", uuid::Uuid::now_v7()));
+            std::fs::write(&jwks_path, jwks.to_string()).expect("jwks should be written");
+            Self {
+                encoding_key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+                jwks_path,
+            }
+        }
+
+        fn config(&self) -> JwtConfig {
+            serde_json::from_value(json!({
+                "jwksPath": self.jwks_path,
+                "issuer": "https://issuer.test",
+                "audience": ["functions"],
+            }))
+            .expect("config should parse")
+        }
+
+        fn token(&self, issuer: &str, audience: &str, expires_in: i64) -> String {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some("test-key".to_string());
+            let claims = json!({
+                "sub": "user-1",
+                "iss": issuer,
+                "aud": audience,
+                "exp": now + expires_in,
+            });
+            encode(&header, &claims, &self.encoding_key).expect("token should sign")
+        }
+    }
+
+    impl Drop for TestIssuer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.jwks_path);
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_tokens_signed_by_the_configured_keys() {
+        let issuer = TestIssuer::new();
+        let verifier = JwtVerifier::new().expect("verifier should build");
+        let token = issuer.token("https://issuer.test", "functions", 300);
+
+        let verified = verifier
+            .verify("example", &issuer.config(), &token)
+            .await
+            .expect("token should verify");
+
+        assert_eq!(verified.header, "x-jwt-claims");
+        assert_eq!(verified.claims["sub"], "user-1");
+        let decoded = URL_SAFE_NO_PAD
+            .decode(verified.header_value())
+            .expect("header value should be base64url");
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&decoded).unwrap()["sub"],
+            "user-1"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_wrong_issuer_audience_expiry_and_key() {
+        let issuer = TestIssuer::new();
+        let verifier = JwtVerifier::new().expect("verifier should build");
+        let config = issuer.config();
+
+        for token in [
+            issuer.token("https://other.test", "functions", 300),
+            issuer.token("https://issuer.test", "other", 300),
+            issuer.token("https://issuer.test", "functions", -600),
+            TestIssuer::new().token("https://issuer.test", "functions", 300),
+        ] {
+            assert!(verifier.verify("example", &config, &token).await.is_err());
+        }
+    }
+}
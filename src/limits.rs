@@ -0,0 +1,120 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::limit_error::LimitError, redis_manager::RedisManager};
+
+/// How long a concurrency slot survives a server that died mid-invocation.
+const CONCURRENCY_LEASE_MS: u64 = 5 * 60 * 1000;
+/// A full concurrency cap has no refill rate to derive a wait from.
+const CONCURRENCY_RETRY_AFTER_SECS: u64 = 1;
+
+/// Invoke quotas of a function (`"limits"` in `function.json`) or an API key.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct InvokeLimits {
+    /// Token bucket refill rate.
+    #[serde(default, rename = "requestsPerSecond", skip_serializing_if = "Option::is_none")]
+    pub requests_per_second: Option<f64>,
+    /// Bucket size, defaults to one second worth of requests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(default, rename = "maxConcurrency", skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<u32>,
+}
+
+impl InvokeLimits {
+    fn rate(&self) -> Option<(f64, u32)> {
+        let rate = self.requests_per_second.filter(|rate| *rate > 0.0)?;
+        let burst = self.burst.unwrap_or(rate.ceil() as u32).max(1);
+        Some((rate, burst))
+    }
+}
+
+/// Limits applied to one invocation, keyed by what they belong to, e.g.
+/// `function:example` or `apikey:<id>`.
+#[derive(Debug)]
+pub struct LimitScope {
+    pub key: String,
+    pub limits: InvokeLimits,
+}
+
+/// Concurrency slots held for an invocation, released when dropped.
+#[derive(Debug)]
+pub struct InvokePermit<'a> {
+    redis_manager: &'a RedisManager,
+    slot_id: String,
+    scopes: Vec<String>,
+}
+
+impl Drop for InvokePermit<'_> {
+    fn drop(&mut self) {
+        for scope in &self.scopes {
+            if let Err(e) = self.redis_manager.release_concurrency_slot(scope, &self.slot_id) {
+                warn!("Failed to release concurrency slot for '{scope}': {e}");
+            }
+        }
+    }
+}
+
+/// Checks rate limits, then takes a concurrency slot in every scope. Redis
+/// errors let the invocation through rather than failing it.
+pub fn admit<'a>(redis_manager: &'a RedisManager, scopes: &[LimitScope]) -> Result<InvokePermit<'a>> {
+    for scope in scopes {
+        let Some((rate, burst)) = scope.limits.rate() else {
+            continue;
+        };
+        match redis_manager.take_rate_token(&scope.key, rate, burst) {
+            Ok(None) => {}
+            Ok(Some(retry_ms)) => {
+                return Err(LimitError::RateLimited {
+                    scope: scope.key.clone(),
+                    retry_after_secs: retry_ms.div_ceil(1000).max(1),
+                }
+                .into());
+            }
+            Err(e) => warn!("Rate limit check for '{}' skipped: {e}", scope.key),
+        }
+    }
+
+    let mut permit = InvokePermit {
+        redis_manager,
+        slot_id: uuid::Uuid::now_v7().simple().to_string(),
+        scopes: Vec::new(),
+    };
+    for scope in scopes {
+        let Some(max) = scope.limits.max_concurrency else {
+            continue;
+        };
+        match redis_manager.acquire_concurrency_slot(&scope.key, &permit.slot_id, max, CONCURRENCY_LEASE_MS) {
+            Ok(true) => permit.scopes.push(scope.key.clone()),
+            Ok(false) => {
+                return Err(LimitError::ConcurrencyLimited {
+                    scope: scope.key.clone(),
+                    retry_after_secs: CONCURRENCY_RETRY_AFTER_SECS,
+                }
+                .into());
+            }
+            Err(e) => warn!("Concurrency check for '{}' skipped: {e}", scope.key),
+        }
+    }
+    Ok(permit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InvokeLimits;
+
+    #[test]
+    fn burst_defaults_to_one_second_of_requests() {
+        let limits: InvokeLimits =
+            serde_json::from_str(r#"{"requestsPerSecond": 2.5}"#).expect("limits should parse");
+        assert_eq!(limits.rate(), Some((2.5, 3)));
+
+        let limits = InvokeLimits {
+            requests_per_second: Some(0.2),
+            ..Default::default()
+        };
+        assert_eq!(limits.rate(), Some((0.2, 1)));
+        assert_eq!(InvokeLimits::default().rate(), None);
+    }
+}
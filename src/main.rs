@@ -1,31 +1,68 @@
 use crate::{
+    auth::{AuthConfig, require_api_key},
     container_manager::MANAGED_CONTAINER_LABEL,
-    function_manager::FunctionManager, logger::setup_logger, redis_manager::RedisManager,
+    function_manager::FunctionManager, invoke_chain::SERVER_PORT, jwt::JwtVerifier, logger::setup_logger, recorder::InvokeRecorder,
+    redis_manager::RedisManager,
     routes::{
+        api_keys::{create_api_key, list_api_keys, revoke_api_key},
+        cache::invalidate_response_cache,
+        gc::collect_garbage,
         deploy::deploy_function, get_status::get_deployment_status,
-        invoke::invoke_function, list_functions::list_functions,
-        replicas::get_function_replicas, stop::stop_function,
+        invoke::{internal_invoke, invoke_function}, list_functions::list_functions, metrics::get_metrics,
+        replicas::get_function_replicas,
+        secrets::{delete_secret, list_secrets, put_secret},
+        state::{
+            compare_and_set_state, delete_state, get_state, increment_state, list_state, put_state,
+        },
+        stop::stop_function,
         update_config::update_function_config,
+        volume::{
+            create_volume_snapshot, delete_volume_snapshot, get_function_volume,
+            restore_volume_snapshot,
+        },
+        workflows::{get_workflow_execution, list_workflow_executions, start_workflow_execution},
     },
     shutdown::shutdown_signal,
+    workflows::engine::resume_executions,
 };
 use anyhow::{Context, Result};
-use axum::{Router, routing::{get, patch, post}};
+use axum::{Router, middleware, routing::{delete, get, patch, post, put}};
 use log::{error, info, warn};
-use std::{fs, sync::Arc};
+use std::{fs, sync::Arc, time::Duration};
 
 extern crate redis;
 
+mod admission;
+mod auth;
 mod container_manager;
 mod deployed_functions;
+mod egress_proxy;
 mod errors;
 mod function_manager;
+mod gc;
+mod http_timing;
+mod invoke_chain;
+mod invoke_cache;
+mod jwt;
+mod limits;
 mod logger;
 mod balancers;
+mod build_hash;
+mod metrics;
 mod models;
+mod network;
+mod process_manager;
+mod recorder;
 mod redis_manager;
+mod resources;
 mod routes;
+mod secrets;
+mod security;
 mod shutdown;
+mod state;
+mod volume;
+mod wasm_runtime;
+mod workflows;
 
 fn cleanup_managed_containers_sync() -> Result<()> {
     let output = std::process::Command::new("docker")
@@ -89,6 +126,45 @@ fn install_panic_cleanup_hook() {
     }));
 }
 
+const REPLICA_RECONCILE_INTERVAL: Duration = Duration::from_secs(2);
+
+fn spawn_replica_reconciler(state: Arc<AppState>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REPLICA_RECONCILE_INTERVAL);
+        loop {
+            interval.tick().await;
+            state
+                .function_manager
+                .reconcile_replicas(&state.redis_manager)
+                .await;
+        }
+    });
+}
+
+fn spawn_garbage_collector(state: Arc<AppState>) {
+    let Some(period) = state.function_manager.gc.interval else {
+        info!("Scheduled garbage collection is disabled");
+        return;
+    };
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // The first tick fires immediately; nothing has piled up at startup.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let policy = state.function_manager.gc.policy.clone();
+            match state.function_manager.collect_garbage(&policy, false).await {
+                Ok(report) => info!(
+                    "Garbage collection removed {} items, reclaimed {} bytes",
+                    report.removed.len(),
+                    report.reclaimed_bytes
+                ),
+                Err(e) => warn!("Garbage collection failed: {e:#}"),
+            }
+        }
+    });
+}
+
 fn read_function_paths() -> Vec<String> {
     fs::read_dir("functions")
         .expect("Missing functions directory")
@@ -106,14 +182,21 @@ fn read_function_paths() -> Vec<String> {
 pub(crate) struct AppState {
     function_manager: FunctionManager,
     redis_manager: RedisManager,
+    invoke_recorder: Option<InvokeRecorder>,
+    auth: AuthConfig,
+    jwt_verifier: JwtVerifier,
 }
 impl AppState {
     pub async fn new() -> Result<Self> {
         let function_manager = FunctionManager::new()?;
         let redis_manager = RedisManager::new().context("Redis error")?;
+        let invoke_recorder = InvokeRecorder::from_env().await?;
         Ok(Self {
             function_manager,
             redis_manager,
+            invoke_recorder,
+            auth: AuthConfig::from_env(),
+            jwt_verifier: JwtVerifier::new()?,
         })
     }
 }
@@ -138,15 +221,55 @@ async fn main() -> Result<()> {
         Arc::new(state)
     };
     let cleanup_state = Arc::clone(&state);
-    let port = 5000;
+    spawn_replica_reconciler(Arc::clone(&state));
+    spawn_garbage_collector(Arc::clone(&state));
+    tokio::spawn(resume_executions(Arc::clone(&state)));
+    let port = SERVER_PORT;
     let app = Router::new()
         .route("/deploy/{function_name}", post(deploy_function))
         .route("/deploy/status/{deployment_id}", get(get_deployment_status))
         .route("/invoke/{function_name}", post(invoke_function))
+        .route("/internal/invoke/{function_name}", post(internal_invoke))
+        .route("/internal/state", get(list_state))
+        .route(
+            "/internal/state/{key}",
+            get(get_state).put(put_state).delete(delete_state),
+        )
+        .route("/internal/state/{key}/cas", post(compare_and_set_state))
+        .route("/internal/state/{key}/increment", post(increment_state))
         .route("/functions/{function_name}", patch(update_function_config))
         .route("/functions/{function_name}/stop", post(stop_function))
         .route("/functions/{function_name}/replicas", get(get_function_replicas))
+        .route("/functions/{function_name}/cache", delete(invalidate_response_cache))
+        .route("/functions/{function_name}/volume", get(get_function_volume))
+        .route(
+            "/functions/{function_name}/volume/snapshots",
+            post(create_volume_snapshot),
+        )
+        .route(
+            "/functions/{function_name}/volume/snapshots/{snapshot_id}",
+            delete(delete_volume_snapshot),
+        )
+        .route(
+            "/functions/{function_name}/volume/snapshots/{snapshot_id}/restore",
+            post(restore_volume_snapshot),
+        )
         .route("/functions", get(list_functions))
+        .route("/metrics", get(get_metrics))
+        .route(
+            "/workflows/{workflow_name}/executions",
+            post(start_workflow_execution).get(list_workflow_executions),
+        )
+        .route(
+            "/workflows/{workflow_name}/executions/{execution_id}",
+            get(get_workflow_execution),
+        )
+        .route("/admin/gc", post(collect_garbage))
+        .route("/admin/keys", post(create_api_key).get(list_api_keys))
+        .route("/admin/keys/{key_id}", delete(revoke_api_key))
+        .route("/admin/secrets", get(list_secrets))
+        .route("/admin/secrets/{secret_name}", put(put_secret).delete(delete_secret))
+        .layer(middleware::from_fn_with_state(Arc::clone(&state), require_api_key))
         .with_state(state);
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await?;
     println!("Running on port {port}");
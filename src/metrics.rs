@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+use crate::admission::QueueStats;
+
+type FunctionName = String;
+
+/// Server-side breakdown of how a replica was brought into the balancer.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct StartPhases {
+    pub mode: StartMode,
+    #[serde(rename = "containerId")]
+    pub container_id: String,
+    #[serde(rename = "imageMs")]
+    pub image_ms: f64,
+    #[serde(rename = "createMs")]
+    pub create_ms: f64,
+    #[serde(rename = "startMs")]
+    pub start_ms: f64,
+    #[serde(rename = "readinessMs")]
+    pub readiness_ms: f64,
+    #[serde(rename = "firstRequestMs")]
+    pub first_request_ms: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartMode {
+    #[default]
+    Boot,
+    Checkpoint,
+    Warm,
+}
+
+#[derive(Debug, Default, Clone)]
+struct FunctionCounters {
+    warm_hits: u64,
+    cold_starts: u64,
+    checkpoint_restores: u64,
+    checkpoint_restore_failures: u64,
+    last_start: Option<StartPhases>,
+    nested_invocations: u64,
+    callers: HashMap<FunctionName, u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FunctionMetricsReport {
+    #[serde(rename = "warmHits")]
+    pub warm_hits: u64,
+    #[serde(rename = "coldStarts")]
+    pub cold_starts: u64,
+    #[serde(rename = "warmHitRatio")]
+    pub warm_hit_ratio: f64,
+    #[serde(rename = "checkpointRestores")]
+    pub checkpoint_restores: u64,
+    #[serde(rename = "checkpointRestoreFailures")]
+    pub checkpoint_restore_failures: u64,
+    #[serde(rename = "lastStart")]
+    pub last_start: Option<StartPhases>,
+    /// Calls made by other functions through `/internal/invoke`.
+    #[serde(rename = "nestedInvocations")]
+    pub nested_invocations: u64,
+    /// Nested calls by calling function.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub callers: HashMap<FunctionName, u64>,
+    #[serde(rename = "admissionQueue", skip_serializing_if = "Option::is_none")]
+    pub admission_queue: Option<QueueStats>,
+}
+
+/// In-memory per-function counters exposed through `GET /metrics`.
+#[derive(Debug, Default)]
+pub struct Metrics(Mutex<HashMap<FunctionName, FunctionCounters>>);
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&self, function_name: &str, apply: impl FnOnce(&mut FunctionCounters)) {
+        if let Ok(mut counters) = self.0.lock() {
+            apply(counters.entry(function_name.to_string()).or_default());
+        }
+    }
+
+    pub fn record_warm_hit(&self, function_name: &str) {
+        self.update(function_name, |counters| counters.warm_hits += 1);
+    }
+
+    pub fn record_cold_start(&self, function_name: &str) {
+        self.update(function_name, |counters| counters.cold_starts += 1);
+    }
+
+    pub fn record_checkpoint_restore(&self, function_name: &str) {
+        self.update(function_name, |counters| counters.checkpoint_restores += 1);
+    }
+
+    pub fn record_checkpoint_restore_failure(&self, function_name: &str) {
+        self.update(function_name, |counters| {
+            counters.checkpoint_restore_failures += 1
+        });
+    }
+
+    pub fn record_nested_invocation(&self, function_name: &str, caller: &str) {
+        self.update(function_name, |counters| {
+            counters.nested_invocations += 1;
+            *counters.callers.entry(caller.to_string()).or_default() += 1;
+        });
+    }
+
+    pub fn record_replica_start(&self, function_name: &str, phases: StartPhases) {
+        self.update(function_name, |counters| counters.last_start = Some(phases));
+    }
+
+    /// Completes the last start record with the latency of the first request
+    /// served by that replica.
+    pub fn record_invocation(&self, function_name: &str, container_id: &str, elapsed_ms: f64) {
+        self.update(function_name, |counters| {
+            if let Some(last_start) = counters.last_start.as_mut()
+                && last_start.container_id == container_id
+                && last_start.first_request_ms.is_none()
+            {
+                last_start.first_request_ms = Some(elapsed_ms);
+            }
+        });
+    }
+
+    pub fn report(&self) -> HashMap<FunctionName, FunctionMetricsReport> {
+        let counters = match self.0.lock() {
+            Ok(value) => value.clone(),
+            Err(_) => return HashMap::new(),
+        };
+        counters
+            .into_iter()
+            .map(|(function_name, counters)| {
+                let starts = counters.warm_hits + counters.cold_starts;
+                let warm_hit_ratio = if starts == 0 {
+                    0.0
+                } else {
+                    counters.warm_hits as f64 / starts as f64
+                };
+                (
+                    function_name,
+                    FunctionMetricsReport {
+                        warm_hits: counters.warm_hits,
+                        cold_starts: counters.cold_starts,
+                        warm_hit_ratio,
+                        checkpoint_restores: counters.checkpoint_restores,
+                        checkpoint_restore_failures: counters.checkpoint_restore_failures,
+                        last_start: counters.last_start,
+                        nested_invocations: counters.nested_invocations,
+                        callers: counters.callers,
+                        admission_queue: None,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, StartPhases};
+
+    #[test]
+    fn warm_hit_ratio_counts_promotions_against_all_starts() {
+        let metrics = Metrics::new();
+        metrics.record_cold_start("example");
+        metrics.record_warm_hit("example");
+        metrics.record_warm_hit("example");
+        metrics.record_warm_hit("example");
+
+        let report = metrics.report();
+        let example = report.get("example").expect("function should be reported");
+        assert_eq!(example.warm_hits, 3);
+        assert_eq!(example.cold_starts, 1);
+        assert!((example.warm_hit_ratio - 0.75).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn first_request_is_attributed_to_last_started_replica_once() {
+        let metrics = Metrics::new();
+        metrics.record_replica_start(
+            "example",
+            StartPhases {
+                container_id: "c1".to_string(),
+                ..Default::default()
+            },
+        );
+        metrics.record_invocation("example", "c0", 5.0);
+        metrics.record_invocation("example", "c1", 12.0);
+        metrics.record_invocation("example", "c1", 3.0);
+
+        let report = metrics.report();
+        let last_start = report["example"].last_start.as_ref().expect("start recorded");
+        assert_eq!(last_start.first_request_ms, Some(12.0));
+    }
+
+    #[test]
+    fn nested_invocations_are_attributed_to_callers() {
+        let metrics = Metrics::new();
+        metrics.record_nested_invocation("billing", "orders");
+        metrics.record_nested_invocation("billing", "orders");
+        metrics.record_nested_invocation("billing", "checkout");
+
+        let report = metrics.report();
+        assert_eq!(report["billing"].nested_invocations, 3);
+        assert_eq!(report["billing"].callers["orders"], 2);
+        assert_eq!(report["billing"].callers["checkout"], 1);
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FunctionRequest {
     pub fn_name: String,
@@ -8,6 +9,7 @@ pub struct FunctionRequest {
     pub headers: HashMap<String, String>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FunctionResponse {
     pub status: u16,
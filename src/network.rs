@@ -0,0 +1,194 @@
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+const SHARED_NETWORK_PREFIX: &str = "serverless-shared-";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// Own bridge network with unrestricted egress.
+    #[default]
+    Open,
+    /// Own internal network: no egress, shared networks allowed.
+    Internal,
+    /// Internal network without container-to-container traffic, so replicas
+    /// only talk to the platform.
+    None,
+    /// Internal network with egress through the platform proxy, limited to
+    /// `egressAllowlist`.
+    Egress,
+}
+
+/// Network policy of a container function (`"network"` in `function.json`).
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub mode: NetworkMode,
+    /// `host`, `host:port`, `*.domain` or `*.domain:port`; no port allows any.
+    #[serde(default, rename = "egressAllowlist", skip_serializing_if = "Vec::is_empty")]
+    pub egress_allowlist: Vec<String>,
+    /// Internal networks joined in addition to the function's own; functions
+    /// on the same shared network reach each other by function name.
+    #[serde(default, rename = "sharedNetworks", skip_serializing_if = "Vec::is_empty")]
+    pub shared_networks: Vec<String>,
+}
+
+impl NetworkConfig {
+    /// Isolated functions sit on internal networks, where Docker does not
+    /// publish ports, so the platform reaches them by container address.
+    pub fn isolated(&self) -> bool {
+        self.mode != NetworkMode::Open
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Name of the function's own network; each mode gets its own network
+    /// since Docker cannot change the settings of an existing one.
+    pub fn function_network_name(&self, resource_name: &str) -> String {
+        match self.mode {
+            NetworkMode::Open => resource_name.to_string(),
+            NetworkMode::Internal => format!("{resource_name}-internal"),
+            NetworkMode::None => format!("{resource_name}-none"),
+            NetworkMode::Egress => format!("{resource_name}-egress"),
+        }
+    }
+
+    pub fn shared_network_names(&self) -> Vec<String> {
+        self.shared_networks
+            .iter()
+            .map(|name| format!("{SHARED_NETWORK_PREFIX}{name}"))
+            .collect()
+    }
+
+    pub fn validate(&self, function_name: &str) -> Result<()> {
+        match self.mode {
+            NetworkMode::Egress if self.egress_allowlist.is_empty() => bail!(
+                "Для режима сети 'egress' функции '{function_name}' нужен непустой 'egressAllowlist'"
+            ),
+            NetworkMode::Egress => {}
+            _ if !self.egress_allowlist.is_empty() => bail!(
+                "'egressAllowlist' функции '{function_name}' действует только в режиме сети 'egress'"
+            ),
+            _ => {}
+        }
+        if self.mode == NetworkMode::None && !self.shared_networks.is_empty() {
+            bail!("Функция '{function_name}' в режиме сети 'none' не может подключаться к 'sharedNetworks'");
+        }
+        for name in &self.shared_networks {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+            if !valid {
+                bail!("Некорректное имя общей сети '{name}' у функции '{function_name}'");
+            }
+        }
+        self.egress_rules()?;
+        Ok(())
+    }
+
+    pub fn egress_rules(&self) -> Result<Vec<EgressRule>> {
+        self.egress_allowlist
+            .iter()
+            .map(|entry| EgressRule::parse(entry))
+            .collect()
+    }
+}
+
+/// One `egressAllowlist` entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EgressRule {
+    host: HostPattern,
+    port: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    /// `*.example.com` matches subdomains, not `example.com` itself.
+    Suffix(String),
+}
+
+impl EgressRule {
+    pub fn parse(entry: &str) -> Result<Self> {
+        let entry = entry.trim().to_ascii_lowercase();
+        let invalid = || anyhow!("Некорректное правило egress '{entry}'");
+        let (host, port) = match entry.rsplit_once(':') {
+            Some((host, port)) if !host.contains(':') => {
+                (host.to_string(), Some(port.parse::<u16>().map_err(|_| invalid())?))
+            }
+            _ => (entry.clone(), None),
+        };
+        let host = match host.strip_prefix("*.") {
+            Some(suffix) => HostPattern::Suffix(format!(".{suffix}")),
+            None => HostPattern::Exact(host),
+        };
+        let name = match &host {
+            HostPattern::Exact(name) | HostPattern::Suffix(name) => name.trim_start_matches('.'),
+        };
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !valid {
+            return Err(invalid());
+        }
+        Ok(Self { host, port })
+    }
+
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        if self.port.is_some_and(|allowed| allowed != port) {
+            return false;
+        }
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        match &self.host {
+            HostPattern::Exact(allowed) => host == *allowed,
+            HostPattern::Suffix(suffix) => host.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EgressRule, NetworkConfig};
+
+    #[test]
+    fn egress_rules_match_hosts_and_ports() {
+        let exact = EgressRule::parse("api.example.com:443").unwrap();
+        assert!(exact.allows("API.example.com", 443));
+        assert!(!exact.allows("api.example.com", 80));
+        assert!(!exact.allows("evil-api.example.com", 443));
+
+        let wildcard = EgressRule::parse("*.example.com").unwrap();
+        assert!(wildcard.allows("a.b.example.com", 8080));
+        assert!(!wildcard.allows("example.com", 443));
+        assert!(!wildcard.allows("example.com.evil.io", 443));
+
+        assert!(EgressRule::parse("host:http").is_err());
+        assert!(EgressRule::parse("*.").is_err());
+    }
+
+    #[test]
+    fn network_policies_are_validated() {
+        let config = |value: serde_json::Value| -> NetworkConfig { serde_json::from_value(value).unwrap() };
+
+        assert!(config(serde_json::json!({})).validate("example").is_ok());
+        assert!(config(serde_json::json!({ "mode": "egress" })).validate("example").is_err());
+        assert!(config(serde_json::json!({ "egressAllowlist": ["a.io"] })).validate("example").is_err());
+        assert!(
+            config(serde_json::json!({ "mode": "none", "sharedNetworks": ["payments"] }))
+                .validate("example")
+                .is_err()
+        );
+        let egress = config(serde_json::json!({
+            "mode": "egress",
+            "egressAllowlist": ["api.stripe.com:443"],
+            "sharedNetworks": ["payments"]
+        }));
+        egress.validate("example").expect("policy should be valid");
+        assert_eq!(egress.function_network_name("example-1.0"), "example-1.0-egress");
+        assert_eq!(egress.shared_network_names(), ["serverless-shared-payments"]);
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::{Level, error, info, log, warn};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::{Mutex, oneshot},
+    task::JoinHandle,
+    time::{Duration, sleep},
+};
+
+use crate::{
+    function_manager::FunctionConfig,
+    invoke_chain::{INTERNAL_URL_ENV, SERVER_PORT},
+};
+
+const MB_TO_BYTES: u64 = 1024 * 1024;
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CGROUP_PARENT: &str = "serverless";
+const RESTART_BACKOFF_MS: u64 = 250;
+
+type ProcessId = String;
+
+/// Everything needed to (re)spawn one replica of a `process` function.
+#[derive(Debug, Clone)]
+struct ProcessSpec {
+    replica_id: ProcessId,
+    function_name: String,
+    command: Vec<String>,
+    working_dir: PathBuf,
+    env: HashMap<String, String>,
+    port: u16,
+    memory_bytes: u64,
+    max_restarts: u32,
+}
+
+#[derive(Debug)]
+struct SupervisedProcess {
+    stop: oneshot::Sender<()>,
+    supervisor: JoinHandle<()>,
+}
+
+#[derive(Debug, Default)]
+pub struct ProcessManager {
+    processes: Arc<Mutex<HashMap<ProcessId, SupervisedProcess>>>,
+}
+
+impl ProcessManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn replica_id(function_name: &str) -> ProcessId {
+        format!("process-{}-{}", function_name, uuid::Uuid::now_v7())
+    }
+
+    pub fn is_process_replica(replica_id: &str) -> bool {
+        replica_id.starts_with("process-")
+    }
+
+    /// Reserves a free loopback port by binding to port 0 and releasing it.
+    pub fn allocate_port() -> Result<u16> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .context("Failed to allocate port for process replica")?;
+        Ok(listener.local_addr()?.port())
+    }
+
+    /// Spawns a supervised replica and returns its id together with the
+    /// loopback port it was told to listen on via `PORT`.
+    pub async fn spawn_replica(&self, config: &FunctionConfig) -> Result<(ProcessId, u16)> {
+        let command = config.process_command()?;
+        let port = Self::allocate_port()?;
+        let replica_id = Self::replica_id(&config.name);
+        let working_dir = config
+            .working_dir
+            .as_ref()
+            .map(|dir| config.build_context_path.join(dir))
+            .unwrap_or_else(|| config.build_context_path.clone());
+
+        let mut env = config.env.clone();
+        env.insert("PORT".to_string(), port.to_string());
+        env.insert("FUNCTION_NAME".to_string(), config.name.clone());
+        env.insert("FUNCTION_VERSION".to_string(), config.version.clone());
+        env.insert(
+            INTERNAL_URL_ENV.to_string(),
+            format!("http://127.0.0.1:{SERVER_PORT}"),
+        );
+
+        let spec = ProcessSpec {
+            replica_id: replica_id.clone(),
+            function_name: config.name.clone(),
+            command,
+            working_dir,
+            env,
+            port,
+            memory_bytes: (config.memory.max(0) as u64) * MB_TO_BYTES,
+            max_restarts: config.max_restarts,
+        };
+
+        self.start(spec).await?;
+        Ok((replica_id, port))
+    }
+
+    async fn start(&self, spec: ProcessSpec) -> Result<()> {
+        let child = spawn_child(&spec)?;
+        info!(
+            "Started process replica '{}' for '{}' on port {}",
+            spec.replica_id, spec.function_name, spec.port
+        );
+
+        let replica_id = spec.replica_id.clone();
+        let (stop, stop_receiver) = oneshot::channel();
+        let supervisor = tokio::spawn(supervise(spec, child, stop_receiver));
+        self.processes
+            .lock()
+            .await
+            .insert(replica_id, SupervisedProcess { stop, supervisor });
+        Ok(())
+    }
+
+    /// A replica stops running for good once its supervisor gives up, after
+    /// `maxRestarts` restarts or a failed respawn.
+    pub async fn is_running(&self, replica_id: &str) -> bool {
+        self.processes
+            .lock()
+            .await
+            .get(replica_id)
+            .is_some_and(|process| !process.supervisor.is_finished())
+    }
+
+    pub async fn remove_process(&self, replica_id: &str) {
+        let process = self.processes.lock().await.remove(replica_id);
+        if let Some(process) = process {
+            let _ = process.stop.send(());
+            let _ = process.supervisor.await;
+        }
+    }
+}
+
+async fn supervise(spec: ProcessSpec, mut child: Child, mut stop: oneshot::Receiver<()>) {
+    let mut restarts = 0_u32;
+    let mut process_group = child.id();
+    loop {
+        tokio::select! {
+            status = child.wait() => {
+                // Whatever the replica forked may still hold its port.
+                kill_process_group(process_group);
+                match status {
+                    Ok(status) => warn!(
+                        "Process replica '{}' of '{}' exited with {status}",
+                        spec.replica_id, spec.function_name
+                    ),
+                    Err(e) => warn!(
+                        "Failed to wait for process replica '{}': {e}",
+                        spec.replica_id
+                    ),
+                }
+                if restarts >= spec.max_restarts {
+                    error!(
+                        "Process replica '{}' reached restart limit ({}), giving up",
+                        spec.replica_id, spec.max_restarts
+                    );
+                    break;
+                }
+                restarts += 1;
+                sleep(Duration::from_millis(RESTART_BACKOFF_MS * restarts as u64)).await;
+                match spawn_child(&spec) {
+                    Ok(new_child) => {
+                        info!(
+                            "Restarted process replica '{}' (attempt {restarts}/{})",
+                            spec.replica_id, spec.max_restarts
+                        );
+                        child = new_child;
+                        process_group = child.id();
+                    }
+                    Err(e) => {
+                        error!("Failed to restart process replica '{}': {e}", spec.replica_id);
+                        break;
+                    }
+                }
+            }
+            _ = &mut stop => {
+                kill_process_group(process_group);
+                let _ = child.kill().await;
+                break;
+            }
+        }
+    }
+    remove_cgroup(&spec.replica_id);
+}
+
+/// Spawns the replica in its cgroup when one can be created, otherwise (or
+/// when moving it into the cgroup fails) under rlimits, so the memory limit
+/// always applies.
+fn spawn_child(spec: &ProcessSpec) -> Result<Child> {
+    let cgroup = create_cgroup(&spec.replica_id, spec.memory_bytes);
+    let mut child = spawn_process(spec, cgroup.is_none())?;
+    if let (Some(cgroup), Some(pid)) = (cgroup, child.id())
+        && let Err(e) = std::fs::write(cgroup.join("cgroup.procs"), pid.to_string())
+    {
+        warn!(
+            "Failed to move '{}' into cgroup, restarting it under rlimits: {e}",
+            spec.replica_id
+        );
+        kill_process_group(Some(pid));
+        let _ = child.start_kill();
+        remove_cgroup(&spec.replica_id);
+        child = spawn_process(spec, true)?;
+    }
+
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_output(spec.replica_id.clone(), stdout, Level::Info));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_output(spec.replica_id.clone(), stderr, Level::Warn));
+    }
+    Ok(child)
+}
+
+fn spawn_process(spec: &ProcessSpec, rlimits: bool) -> Result<Child> {
+    let (program, args) = spec
+        .command
+        .split_first()
+        .ok_or_else(|| anyhow!("Empty command for process replica '{}'", spec.replica_id))?;
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .current_dir(&spec.working_dir)
+        .envs(&spec.env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Its own process group, so stopping the replica also stops whatever it
+    // started, e.g. the binary `go run` compiles and runs.
+    #[cfg(unix)]
+    command.process_group(0);
+
+    if rlimits {
+        apply_rlimits(&mut command, spec.memory_bytes);
+    }
+
+    command.spawn().with_context(|| {
+        format!(
+            "Failed to spawn '{}' in '{}'",
+            spec.command.join(" "),
+            spec.working_dir.display()
+        )
+    })
+}
+
+/// Copies a replica's output into the server log line by line, stdout at
+/// info and stderr at warn level, the way container logs are kept by Docker.
+async fn forward_output(replica_id: String, output: impl AsyncRead + Unpin, level: Level) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log!(target: "process", level, "[{replica_id}] {line}");
+    }
+}
+
+/// Creates a cgroup v2 leaf with `memory.max` when the hierarchy is writable.
+fn create_cgroup(replica_id: &str, memory_bytes: u64) -> Option<PathBuf> {
+    let root = Path::new(CGROUP_ROOT);
+    if !root.join("cgroup.controllers").exists() {
+        return None;
+    }
+    let path = root.join(CGROUP_PARENT).join(replica_id);
+    if std::fs::create_dir_all(&path).is_err() {
+        return None;
+    }
+    if memory_bytes > 0 && std::fs::write(path.join("memory.max"), memory_bytes.to_string()).is_err()
+    {
+        let _ = std::fs::remove_dir(&path);
+        return None;
+    }
+    Some(path)
+}
+
+fn remove_cgroup(replica_id: &str) {
+    let path = Path::new(CGROUP_ROOT).join(CGROUP_PARENT).join(replica_id);
+    if path.exists() {
+        let _ = std::fs::remove_dir(path);
+    }
+}
+
+/// The group id of a replica is the pid of the process it was spawned as.
+#[cfg(unix)]
+fn kill_process_group(process_group: Option<u32>) {
+    if let Some(process_group) = process_group.and_then(|pid| libc::pid_t::try_from(pid).ok()) {
+        // SAFETY: `kill` has no memory safety requirements.
+        unsafe {
+            libc::kill(-process_group, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn kill_process_group(_process_group: Option<u32>) {}
+
+#[cfg(unix)]
+fn apply_rlimits(command: &mut Command, memory_bytes: u64) {
+    // SAFETY: the closure only calls async-signal-safe `setrlimit`.
+    unsafe {
+        command.pre_exec(move || {
+            let no_core = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+            if memory_bytes > 0 {
+                let memory = libc::rlimit {
+                    rlim_cur: memory_bytes as libc::rlim_t,
+                    rlim_max: memory_bytes as libc::rlim_t,
+                };
+                libc::setrlimit(libc::RLIMIT_DATA, &memory);
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn apply_rlimits(_command: &mut Command, _memory_bytes: u64) {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProcessManager, ProcessSpec, kill_process_group, remove_cgroup, spawn_child};
+
+    #[test]
+    fn process_replica_ids_are_recognised() {
+        let replica_id = ProcessManager::replica_id("example");
+        assert!(replica_id.starts_with("process-example-"));
+        assert!(ProcessManager::is_process_replica(&replica_id));
+        assert!(!ProcessManager::is_process_replica("3f2c9a1b7e"));
+    }
+
+    #[test]
+    fn allocated_ports_are_bindable() {
+        let port = ProcessManager::allocate_port().expect("port should be allocated");
+        assert_ne!(port, 0);
+        std::net::TcpListener::bind(("127.0.0.1", port)).expect("port should be free");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stopping_a_replica_stops_the_processes_it_started() {
+        let dir = std::env::temp_dir().join(format!("process-group-test-{}", uuid::Uuid::now_v7()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let spec = ProcessSpec {
+            replica_id: ProcessManager::replica_id("group-test"),
+            function_name: "group-test".to_string(),
+            command: ["sh", "-c", "sleep 30 & echo $! > grandchild.pid; wait"]
+                .map(ToOwned::to_owned)
+                .to_vec(),
+            working_dir: dir.clone(),
+            env: Default::default(),
+            port: 0,
+            memory_bytes: 0,
+            max_restarts: 0,
+        };
+        let mut child = spawn_child(&spec).expect("replica should start");
+
+        let pid_file = dir.join("grandchild.pid");
+        let mut grandchild = None;
+        for _ in 0..100 {
+            if let Ok(pid) = tokio::fs::read_to_string(&pid_file).await
+                && let Ok(pid) = pid.trim().parse::<libc::pid_t>()
+            {
+                grandchild = Some(pid);
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let grandchild = grandchild.expect("grandchild pid should be written");
+
+        kill_process_group(child.id());
+        child.wait().await.expect("replica should exit");
+        let mut alive = true;
+        for _ in 0..100 {
+            // SAFETY: signal 0 only checks that the process exists.
+            alive = unsafe { libc::kill(grandchild, 0) } == 0;
+            if !alive {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(!alive, "grandchild should be killed with the replica");
+        remove_cgroup(&spec.replica_id);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn replicas_stop_running_once_restarts_are_exhausted() {
+        let manager = ProcessManager::new();
+        let spec = ProcessSpec {
+            replica_id: ProcessManager::replica_id("crash-test"),
+            function_name: "crash-test".to_string(),
+            command: ["sh", "-c", "exit 3"].map(ToOwned::to_owned).to_vec(),
+            working_dir: std::env::temp_dir(),
+            env: Default::default(),
+            port: 0,
+            memory_bytes: 0,
+            max_restarts: 1,
+        };
+        let replica_id = spec.replica_id.clone();
+        manager.start(spec).await.expect("replica should start");
+
+        let mut running = true;
+        for _ in 0..100 {
+            running = manager.is_running(&replica_id).await;
+            if !running {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(!running, "replica should be reported dead after its last restart");
+        manager.remove_process(&replica_id).await;
+        assert!(!manager.is_running(&replica_id).await);
+    }
+}
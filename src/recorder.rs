@@ -0,0 +1,138 @@
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+const RECORD_PATH_ENV: &str = "INVOKE_RECORD_PATH";
+const RECORD_SAMPLE_RATE_ENV: &str = "INVOKE_RECORD_SAMPLE_RATE";
+
+/// Headers that must never end up in a traffic recording. A replayed
+/// `Idempotency-Key` would be answered from the stored result instead of
+/// reaching a replica.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "x-api-key",
+    "proxy-authorization",
+    "x-serverless-invoke-token",
+    "idempotency-key",
+];
+
+/// One line of a recording, in the format `invoke_bench --replay` reads.
+#[derive(Debug, Serialize)]
+struct InvokeRecord<'a> {
+    #[serde(rename = "offsetMs")]
+    offset_ms: f64,
+    function: &'a str,
+    payload: &'a Value,
+    headers: HashMap<String, String>,
+}
+
+/// Appends a sampled share of live `/invoke` traffic to a JSONL file. Only
+/// admitted calls that reach a replica are recorded: rejected calls,
+/// idempotent replays and response cache hits are not.
+pub struct InvokeRecorder {
+    path: PathBuf,
+    sample_rate: f64,
+    started: Instant,
+    file: Mutex<File>,
+}
+
+impl InvokeRecorder {
+    /// Builds a recorder from `INVOKE_RECORD_PATH` and
+    /// `INVOKE_RECORD_SAMPLE_RATE` (0..1, default 1). Recording is disabled
+    /// when the path is not set.
+    pub async fn from_env() -> Result<Option<Self>> {
+        let Ok(path) = std::env::var(RECORD_PATH_ENV) else {
+            return Ok(None);
+        };
+        let sample_rate = match std::env::var(RECORD_SAMPLE_RATE_ENV) {
+            Ok(value) => value
+                .parse::<f64>()
+                .with_context(|| format!("Некорректное значение {RECORD_SAMPLE_RATE_ENV}: '{value}'"))?
+                .clamp(0.0, 1.0),
+            Err(_) => 1.0,
+        };
+        let path = PathBuf::from(path);
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Не удалось открыть файл записи '{}'", path.display()))?;
+        info!(
+            "Recording {:.1}% of invoke traffic to '{}'",
+            sample_rate * 100.0,
+            path.display()
+        );
+        Ok(Some(Self {
+            path,
+            sample_rate,
+            started: Instant::now(),
+            file: Mutex::new(file),
+        }))
+    }
+
+    /// `received` is when the request arrived; the offset is taken from it
+    /// rather than from when it was admitted.
+    pub async fn record(&self, function_name: &str, payload: &Value, headers: &HeaderMap, received: Instant) {
+        if self.sample_rate < 1.0 && rand::random::<f64>() >= self.sample_rate {
+            return;
+        }
+
+        let record = InvokeRecord {
+            offset_ms: received.saturating_duration_since(self.started).as_secs_f64() * 1000.0,
+            function: function_name,
+            payload,
+            headers: recordable_headers(headers),
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("Failed to serialize invoke record: {e}");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if let Err(e) = self.file.lock().await.write_all(&line).await {
+            warn!("Failed to append invoke record to '{}': {e}", self.path.display());
+        }
+    }
+}
+
+fn recordable_headers(headers: &HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter(|(name, _)| !SENSITIVE_HEADERS.contains(&name.as_str()))
+        .filter(|(name, _)| !matches!(name.as_str(), "host" | "content-length"))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::recordable_headers;
+
+    #[test]
+    fn credentials_are_not_recorded() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("x-api-key", HeaderValue::from_static("key"));
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.insert("x-tenant", HeaderValue::from_static("acme"));
+        headers.insert("idempotency-key", HeaderValue::from_static("order-1"));
+
+        let recorded = recordable_headers(&headers);
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded["x-tenant"], "acme");
+        assert!(!recorded.contains_key("authorization"));
+        assert!(!recorded.contains_key("idempotency-key"));
+    }
+}
@@ -4,10 +4,13 @@ use std::{
     collections::HashSet,
     fmt::Display,
     ops::{Deref, DerefMut},
+    sync::LazyLock,
 };
 
 use r2d2::Pool;
 
+use crate::errors::state_error::StateError;
+
 #[derive(Debug)]
 pub enum DeploymentState {
     Running,
@@ -41,6 +44,149 @@ impl DeploymentState {
 
 const ONE_HOUR: i64 = 3600;
 
+/// Token bucket refilled at `ARGV[1]` tokens per second up to `ARGV[2]`.
+/// Returns 0 when a token was taken, otherwise milliseconds until one is
+/// available. Uses the Redis clock so all server instances agree.
+static TOKEN_BUCKET: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local rate = tonumber(ARGV[1])
+        local burst = tonumber(ARGV[2])
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+        local tokens = tonumber(state[1]) or burst
+        local ts = tonumber(state[2]) or now
+        tokens = math.min(burst, tokens + math.max(0, now - ts) * rate / 1000)
+        local retry_ms = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+        else
+            retry_ms = math.ceil((1 - tokens) * 1000 / rate)
+        end
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+        redis.call('PEXPIRE', KEYS[1], math.ceil(burst * 1000 / rate) + 1000)
+        return retry_ms
+        ",
+    )
+});
+
+/// Adds slot `ARGV[1]` to a sorted set of leases expiring after `ARGV[3]` ms
+/// unless `ARGV[2]` live leases exist. Leases of crashed servers expire.
+static CONCURRENCY_SLOT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now)
+        if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[2]) then
+            return 0
+        end
+        redis.call('ZADD', KEYS[1], now + tonumber(ARGV[3]), ARGV[1])
+        redis.call('PEXPIRE', KEYS[1], ARGV[3])
+        return 1
+        ",
+    )
+});
+
+/// Extends the TTL of the idempotency key `KEYS[1]` to `ARGV[2]` seconds
+/// while it still holds the in-progress record `ARGV[1]`.
+static IDEMPOTENCY_HEARTBEAT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('EXPIRE', KEYS[1], ARGV[2])
+        end
+        return 0
+        ",
+    )
+});
+
+/// Quota check shared by the state scripts: true when a new key would
+/// exceed `ARGV[4]` keys. The index `KEYS[2]` is pruned of expired keys
+/// (`ARGV[5]` is the value key prefix) before giving up.
+const STATE_QUOTA_LUA: &str = r"
+        local function quota_full()
+            if redis.call('ZCARD', KEYS[2]) < tonumber(ARGV[4]) then
+                return false
+            end
+            for _, member in ipairs(redis.call('ZRANGE', KEYS[2], 0, -1)) do
+                if redis.call('EXISTS', ARGV[5] .. member) == 0 then
+                    redis.call('ZREM', KEYS[2], member)
+                end
+            end
+            return redis.call('ZCARD', KEYS[2]) >= tonumber(ARGV[4])
+        end
+";
+
+/// Writes `ARGV[2]` to the state key `KEYS[1]` with a TTL of `ARGV[3]` ms
+/// (0 keeps it forever). `ARGV[6]` is `set`, `cas` (current value must equal
+/// `ARGV[7]`) or `cas-absent`. Returns 1 when written, 0 on a failed
+/// compare, -1 when the key quota is reached.
+static STATE_WRITE: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        &[
+            STATE_QUOTA_LUA,
+            r"
+        local current = redis.call('GET', KEYS[1])
+        if ARGV[6] == 'cas-absent' and current then
+            return 0
+        end
+        if ARGV[6] == 'cas' and current ~= ARGV[7] then
+            return 0
+        end
+        if not current and quota_full() then
+            return -1
+        end
+        if tonumber(ARGV[3]) > 0 then
+            redis.call('SET', KEYS[1], ARGV[2], 'PX', ARGV[3])
+        else
+            redis.call('SET', KEYS[1], ARGV[2])
+        end
+        redis.call('ZADD', KEYS[2], 0, ARGV[1])
+        return 1
+        ",
+        ]
+        .concat(),
+    )
+});
+
+/// Adds `ARGV[2]` to the integer at `KEYS[1]`, refreshing the TTL when
+/// `ARGV[3]` ms is set. Returns `{1, value}`, `{-1, 0}` when the key quota is
+/// reached or `{-2, 0}` when the value is not an integer.
+static STATE_INCREMENT: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        &[
+            STATE_QUOTA_LUA,
+            r"
+        if redis.call('EXISTS', KEYS[1]) == 0 and quota_full() then
+            return {-1, 0}
+        end
+        local value = redis.pcall('INCRBY', KEYS[1], ARGV[2])
+        if type(value) == 'table' and value.err then
+            return {-2, 0}
+        end
+        if tonumber(ARGV[3]) > 0 then
+            redis.call('PEXPIRE', KEYS[1], ARGV[3])
+        end
+        redis.call('ZADD', KEYS[2], 0, ARGV[1])
+        return {1, value}
+        ",
+        ]
+        .concat(),
+    )
+});
+
+/// How a state write is conditioned on the current value.
+#[derive(Debug, Clone, Copy)]
+pub enum StateCondition<'a> {
+    Always,
+    /// Only when the key does not exist.
+    Absent,
+    /// Only when the current value equals this one.
+    Equals(&'a str),
+}
+
 #[derive(Debug)]
 pub struct RedisManager(Pool<redis::Client>);
 
@@ -56,6 +202,7 @@ impl RedisManager {
         self.get().map_err(|e| e.into())
     }
 
+    #[allow(dead_code)]
     pub fn set_deployment_state(&self, deployment_id: &str, state: DeploymentState) -> Result<()> {
         let key = format!("deployment:{}:state", deployment_id);
         let mut conn = self.get_connection()?;
@@ -92,6 +239,7 @@ impl RedisManager {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn set_deployment_error(&self, deployment_id: &str, error_message: &str) -> Result<()> {
         let key = format!("deployment:{}:error", deployment_id);
         let mut conn = self.get_connection()?;
@@ -108,6 +256,7 @@ impl RedisManager {
         Ok(())
     }
 
+    #[allow(dead_code)]
     pub fn append_deploy_logs(&self, deployment_id: &str, log: &str) -> Result<()> {
         let key = format!("deployment:{}:log", deployment_id);
         let mut conn = self.get_connection()?;
@@ -191,7 +340,6 @@ impl RedisManager {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn add_function_replica(&self, function_name: &str, container_id: &str) -> Result<()> {
         let key = format!("function:{}:replicas", function_name);
         let mut conn = self.get_connection()?;
@@ -214,6 +362,352 @@ impl RedisManager {
         let replicas: HashSet<String> = conn.smembers(key)?;
         Ok(replicas.into_iter().collect())
     }
+
+    /// Takes one token from the bucket `ratelimit:{scope}`. Returns `None` when
+    /// allowed, otherwise how long to wait in milliseconds.
+    pub fn take_rate_token(&self, scope: &str, rate: f64, burst: u32) -> Result<Option<u64>> {
+        let mut conn = self.get_connection()?;
+        let retry_ms: u64 = TOKEN_BUCKET
+            .key(format!("ratelimit:{scope}"))
+            .arg(rate)
+            .arg(burst)
+            .invoke(&mut *conn)?;
+        Ok((retry_ms > 0).then_some(retry_ms))
+    }
+
+    pub fn acquire_concurrency_slot(
+        &self,
+        scope: &str,
+        slot_id: &str,
+        max: u32,
+        lease_ms: u64,
+    ) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let acquired: i64 = CONCURRENCY_SLOT
+            .key(format!("concurrency:{scope}"))
+            .arg(slot_id)
+            .arg(max)
+            .arg(lease_ms)
+            .invoke(&mut *conn)?;
+        Ok(acquired == 1)
+    }
+
+    pub fn release_concurrency_slot(&self, scope: &str, slot_id: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.zrem(format!("concurrency:{scope}"), slot_id)?;
+        Ok(())
+    }
+
+    /// Claims `idempotency:{function}:{key}` for `ttl_secs`. Returns the record
+    /// already stored there when the key was used before.
+    pub fn begin_idempotent_invoke(
+        &self,
+        function_name: &str,
+        idempotency_key: &str,
+        record: &str,
+        ttl_secs: u64,
+    ) -> Result<Option<String>> {
+        let key = format!("idempotency:{function_name}:{idempotency_key}");
+        let mut conn = self.get_connection()?;
+        let options = redis::SetOptions::default()
+            .conditional_set(redis::ExistenceCheck::NX)
+            .with_expiration(redis::SetExpiry::EX(ttl_secs));
+        if conn.set_options(&key, record, options)?.is_some() {
+            return Ok(None);
+        }
+        conn.get(&key).map_err(|e| e.into())
+    }
+
+    pub fn complete_idempotent_invoke(
+        &self,
+        function_name: &str,
+        idempotency_key: &str,
+        record: &str,
+        ttl_secs: u64,
+    ) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.set_ex(format!("idempotency:{function_name}:{idempotency_key}"), record, ttl_secs)?;
+        Ok(())
+    }
+
+    /// Keeps the in-progress record alive while its owner still runs;
+    /// false once the key holds something else.
+    pub fn refresh_idempotent_invoke(
+        &self,
+        function_name: &str,
+        idempotency_key: &str,
+        record: &str,
+        ttl_secs: u64,
+    ) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let refreshed: i64 = IDEMPOTENCY_HEARTBEAT
+            .key(format!("idempotency:{function_name}:{idempotency_key}"))
+            .arg(record)
+            .arg(ttl_secs)
+            .invoke(&mut *conn)?;
+        Ok(refreshed == 1)
+    }
+
+    /// Frees the key after a failed invocation so the client can retry.
+    pub fn abandon_idempotent_invoke(&self, function_name: &str, idempotency_key: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.del(format!("idempotency:{function_name}:{idempotency_key}"))?;
+        Ok(())
+    }
+
+    pub fn get_cached_response(&self, function_name: &str, cache_key: &str) -> Result<Option<String>> {
+        let mut conn = self.get_connection()?;
+        conn.get(format!("responsecache:{function_name}:{cache_key}"))
+            .map_err(|e| e.into())
+    }
+
+    pub fn cache_response(
+        &self,
+        function_name: &str,
+        cache_key: &str,
+        response: &str,
+        ttl_secs: u64,
+    ) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.set_ex(format!("responsecache:{function_name}:{cache_key}"), response, ttl_secs)?;
+        Ok(())
+    }
+
+    /// Removes every cached response of the function, returns how many.
+    pub fn invalidate_response_cache(&self, function_name: &str) -> Result<usize> {
+        let mut conn = self.get_connection()?;
+        let keys = conn
+            .scan_match::<_, String>(format!("responsecache:{function_name}:*"))?
+            .collect::<Vec<_>>();
+        if keys.is_empty() {
+            return Ok(0);
+        }
+        conn.del(keys).map_err(|e| e.into())
+    }
+
+    /// Stores an API key record under the hash of the key; the plaintext key
+    /// is never written. `apikeys` maps key ids to hashes for listing/revoking.
+    pub fn store_api_key(&self, key_id: &str, key_hash: &str, record: &str) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.set(format!("apikey:{key_hash}"), record)?;
+        conn.hset("apikeys", key_id, key_hash)?;
+        Ok(())
+    }
+
+    pub fn get_api_key(&self, key_hash: &str) -> Result<Option<String>> {
+        let mut conn = self.get_connection()?;
+        conn.get(format!("apikey:{key_hash}")).map_err(|e| e.into())
+    }
+
+    pub fn list_api_keys(&self) -> Result<Vec<String>> {
+        let mut conn = self.get_connection()?;
+        let hashes: Vec<String> = conn.hvals("apikeys")?;
+        let mut records = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            if let Some(record) = conn.get(format!("apikey:{hash}"))? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Returns false when no key with this id exists.
+    pub fn delete_api_key(&self, key_id: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let Some(hash) = conn.hget("apikeys", key_id)? else {
+            return Ok(false);
+        };
+        let _: usize = conn.del(format!("apikey:{hash}"))?;
+        let _: usize = conn.hdel("apikeys", key_id)?;
+        Ok(true)
+    }
+
+    /// Secrets live in the `secrets` hash, name to encrypted record. Returns
+    /// true when an existing secret was replaced.
+    pub fn store_secret(&self, name: &str, record: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let added = conn.hset("secrets", name, record)?;
+        Ok(added == 0)
+    }
+
+    pub fn get_secret(&self, name: &str) -> Result<Option<String>> {
+        let mut conn = self.get_connection()?;
+        conn.hget("secrets", name).map_err(|e| e.into())
+    }
+
+    pub fn list_secrets(&self) -> Result<Vec<(String, String)>> {
+        let mut conn = self.get_connection()?;
+        Ok(conn.hgetall("secrets")?.into_iter().collect())
+    }
+
+    /// Returns false when no secret with this name exists.
+    pub fn delete_secret(&self, name: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let removed = conn.hdel("secrets", name)?;
+        Ok(removed > 0)
+    }
+
+    /// Executions are stored as JSON under `workflow_execution:{id}`. Running
+    /// ones are also kept in `workflow_executions:running` to be resumed after
+    /// a restart, and `workflow:{name}:executions` orders ids by start time.
+    pub fn save_workflow_execution(
+        &self,
+        execution_id: &str,
+        workflow: &str,
+        started_at_unix_ms: u128,
+        running: bool,
+        record: &str,
+    ) -> Result<()> {
+        let mut conn = self.get_connection()?;
+        conn.set(format!("workflow_execution:{execution_id}"), record)?;
+        conn.zadd(
+            format!("workflow:{workflow}:executions"),
+            execution_id,
+            started_at_unix_ms as f64,
+        )?;
+        if running {
+            conn.sadd("workflow_executions:running", execution_id)?;
+        } else {
+            conn.srem("workflow_executions:running", execution_id)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_workflow_execution(&self, execution_id: &str) -> Result<Option<String>> {
+        let mut conn = self.get_connection()?;
+        conn.get(format!("workflow_execution:{execution_id}"))
+            .map_err(|e| e.into())
+    }
+
+    /// Execution ids of a workflow, newest first.
+    pub fn list_workflow_executions(&self, workflow: &str) -> Result<Vec<String>> {
+        let mut conn = self.get_connection()?;
+        conn.zrevrange(format!("workflow:{workflow}:executions"), 0, -1)
+            .map_err(|e| e.into())
+    }
+
+    pub fn running_workflow_executions(&self) -> Result<Vec<String>> {
+        let mut conn = self.get_connection()?;
+        Ok(conn.smembers("workflow_executions:running")?.into_iter().collect())
+    }
+
+    /// Function state lives in `state:{function}:{key}`, one Redis key per
+    /// entry so each can have its own TTL; `statekeys:{function}` indexes
+    /// the keys for listing and quotas. Returns the value and its remaining
+    /// TTL in milliseconds.
+    pub fn state_get(&self, function_name: &str, key: &str) -> Result<Option<(String, Option<u64>)>> {
+        let mut conn = self.get_connection()?;
+        let state_key = format!("state:{function_name}:{key}");
+        let (value, ttl_ms): (Option<String>, i64) = redis::pipe()
+            .get(&state_key)
+            .pttl(&state_key)
+            .query(&mut *conn)?;
+        Ok(value.map(|value| (value, u64::try_from(ttl_ms).ok())))
+    }
+
+    /// Returns false when the condition did not hold.
+    pub fn state_write(
+        &self,
+        function_name: &str,
+        key: &str,
+        value: &str,
+        ttl_ms: u64,
+        max_keys: u32,
+        condition: StateCondition,
+    ) -> Result<bool> {
+        let (mode, expected) = match condition {
+            StateCondition::Always => ("set", ""),
+            StateCondition::Absent => ("cas-absent", ""),
+            StateCondition::Equals(expected) => ("cas", expected),
+        };
+        let mut conn = self.get_connection()?;
+        let written: i64 = STATE_WRITE
+            .key(format!("state:{function_name}:{key}"))
+            .key(format!("statekeys:{function_name}"))
+            .arg(key)
+            .arg(value)
+            .arg(ttl_ms)
+            .arg(max_keys)
+            .arg(format!("state:{function_name}:"))
+            .arg(mode)
+            .arg(expected)
+            .invoke(&mut *conn)?;
+        match written {
+            -1 => Err(StateError::QuotaExceeded {
+                function: function_name.to_string(),
+                max_keys,
+            }
+            .into()),
+            written => Ok(written == 1),
+        }
+    }
+
+    pub fn state_increment(
+        &self,
+        function_name: &str,
+        key: &str,
+        by: i64,
+        ttl_ms: u64,
+        max_keys: u32,
+    ) -> Result<i64> {
+        let mut conn = self.get_connection()?;
+        let (status, value): (i64, i64) = STATE_INCREMENT
+            .key(format!("state:{function_name}:{key}"))
+            .key(format!("statekeys:{function_name}"))
+            .arg(key)
+            .arg(by)
+            .arg(ttl_ms)
+            .arg(max_keys)
+            .arg(format!("state:{function_name}:"))
+            .invoke(&mut *conn)?;
+        match status {
+            -1 => Err(StateError::QuotaExceeded {
+                function: function_name.to_string(),
+                max_keys,
+            }
+            .into()),
+            -2 => Err(StateError::NotInteger(key.to_string()).into()),
+            _ => Ok(value),
+        }
+    }
+
+    /// Returns false when the key did not exist.
+    pub fn state_delete(&self, function_name: &str, key: &str) -> Result<bool> {
+        let mut conn = self.get_connection()?;
+        let removed = conn.del(format!("state:{function_name}:{key}"))?;
+        conn.zrem(format!("statekeys:{function_name}"), key)?;
+        Ok(removed > 0)
+    }
+
+    /// Live keys starting with `prefix`, in lexicographic order. Expired keys
+    /// met on the way are dropped from the index.
+    pub fn state_list(&self, function_name: &str, prefix: &str, limit: usize) -> Result<Vec<String>> {
+        let mut conn = self.get_connection()?;
+        let index = format!("statekeys:{function_name}");
+        let (min, max) = if prefix.is_empty() {
+            (b"-".to_vec(), b"+".to_vec())
+        } else {
+            let min = [b"[", prefix.as_bytes()].concat();
+            let max = [b"[", prefix.as_bytes(), &[0xff]].concat();
+            (min, max)
+        };
+        let keys: Vec<String> = conn.zrangebylex_limit(&index, min, max, 0, limit as isize)?;
+        let mut exists = redis::pipe();
+        for key in &keys {
+            exists.exists(format!("state:{function_name}:{key}"));
+        }
+        let exists: Vec<bool> = exists.query(&mut *conn)?;
+
+        let mut live = Vec::with_capacity(keys.len());
+        for (key, exists) in keys.into_iter().zip(exists) {
+            if exists {
+                live.push(key);
+            } else {
+                conn.zrem(&index, &key)?;
+            }
+        }
+        Ok(live)
+    }
 }
 
 impl Deref for RedisManager {
@@ -231,7 +725,7 @@ impl DerefMut for RedisManager {
 
 #[cfg(test)]
 mod tests {
-    use super::{DeploymentState, RedisManager};
+    use super::{DeploymentState, RedisManager, StateCondition};
 
     #[test]
     fn deployment_state_parse_works_for_known_values() {
@@ -278,4 +772,51 @@ mod tests {
         replicas.sort();
         assert_eq!(replicas, vec!["r2".to_string(), "r3".to_string()]);
     }
+
+    #[test]
+    #[ignore = "requires local redis on 127.0.0.1:6379"]
+    fn token_bucket_and_concurrency_slots_enforce_limits() {
+        let manager = RedisManager::new().expect("redis should be available for this test");
+        let scope = format!("test:{}", uuid::Uuid::now_v7().simple());
+
+        for _ in 0..2 {
+            assert_eq!(manager.take_rate_token(&scope, 1.0, 2).expect("bucket"), None);
+        }
+        let retry_ms = manager
+            .take_rate_token(&scope, 1.0, 2)
+            .expect("bucket")
+            .expect("third request should be limited");
+        assert!(retry_ms > 0 && retry_ms <= 1000);
+
+        assert!(manager.acquire_concurrency_slot(&scope, "a", 1, 60_000).expect("slot"));
+        assert!(!manager.acquire_concurrency_slot(&scope, "b", 1, 60_000).expect("slot"));
+        manager.release_concurrency_slot(&scope, "a").expect("release");
+        assert!(manager.acquire_concurrency_slot(&scope, "b", 1, 60_000).expect("slot"));
+        manager.release_concurrency_slot(&scope, "b").expect("release");
+    }
+
+    #[test]
+    #[ignore = "requires local redis on 127.0.0.1:6379"]
+    fn function_state_supports_cas_increment_and_quotas() {
+        let manager = RedisManager::new().expect("redis should be available for this test");
+        let function = format!("test-{}", uuid::Uuid::now_v7().simple());
+
+        assert!(manager.state_write(&function, "a", "1", 0, 2, StateCondition::Absent).expect("write"));
+        assert!(!manager.state_write(&function, "a", "2", 0, 2, StateCondition::Absent).expect("write"));
+        assert!(!manager.state_write(&function, "a", "3", 0, 2, StateCondition::Equals("2")).expect("write"));
+        assert!(manager.state_write(&function, "a", "3", 0, 2, StateCondition::Equals("1")).expect("write"));
+        assert_eq!(manager.state_increment(&function, "a", 4, 0, 2).expect("increment"), 7);
+
+        assert!(manager.state_write(&function, "b", "\"x\"", 60_000, 2, StateCondition::Always).expect("write"));
+        let (value, ttl_ms) = manager.state_get(&function, "b").expect("get").expect("value");
+        assert_eq!(value, "\"x\"");
+        assert!(ttl_ms.is_some_and(|ttl| ttl <= 60_000));
+        assert!(manager.state_increment(&function, "b", 1, 0, 2).is_err());
+        assert!(manager.state_write(&function, "c", "1", 0, 2, StateCondition::Always).is_err());
+
+        assert_eq!(manager.state_list(&function, "", 10).expect("list"), ["a", "b"]);
+        assert!(manager.state_delete(&function, "a").expect("delete"));
+        assert!(manager.state_delete(&function, "b").expect("delete"));
+        assert!(manager.state_list(&function, "", 10).expect("list").is_empty());
+    }
 }
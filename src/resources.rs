@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+
+use anyhow::{Result, bail};
+use bollard::secret::{HostConfig, ResourcesUlimits};
+use serde::{Deserialize, Serialize};
+
+const MB_TO_BYTES: i64 = 1024 * 1024;
+const NANO_CPUS_PER_CPU: f64 = 1_000_000_000.0;
+/// Docker's bounds for relative CPU weight.
+const MIN_CPU_SHARES: i64 = 2;
+const MAX_CPU_SHARES: i64 = 262_144;
+
+const ULIMIT_NAMES: &[&str] = &[
+    "core", "cpu", "data", "fsize", "locks", "memlock", "msgqueue", "nice", "nofile", "nproc",
+    "rss", "rtprio", "rttime", "sigpending", "stack",
+];
+
+/// A `setrlimit` limit for the container's processes; `-1` is unlimited.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Ulimit {
+    pub name: String,
+    pub soft: i64,
+    pub hard: i64,
+}
+
+/// Container limits beyond `memory`, flattened into `function.json`. Sizes
+/// are in MB like `memory`. Only applied by the `docker` runtime.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ResourceLimits {
+    /// CPU time as a number of CPUs, e.g. `0.5`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+    /// Relative CPU weight under contention, 1024 is the default.
+    #[serde(default, rename = "cpuShares", skip_serializing_if = "Option::is_none")]
+    pub cpu_shares: Option<i64>,
+    /// Caps processes and threads, which is what stops fork and thread bombs.
+    #[serde(default, rename = "pidsLimit", skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ulimits: Option<Vec<Ulimit>>,
+    /// Memory plus swap, `-1` for unlimited swap. Equal to `memory` disables swap.
+    #[serde(default, rename = "memorySwap", skip_serializing_if = "Option::is_none")]
+    pub memory_swap: Option<i64>,
+    /// Size of `/dev/shm`.
+    #[serde(default, rename = "shmSize", skip_serializing_if = "Option::is_none")]
+    pub shm_size: Option<i64>,
+}
+
+impl ResourceLimits {
+    /// Values given in `update` replace the current ones.
+    pub fn merge(&mut self, update: ResourceLimits) {
+        if update.cpus.is_some() {
+            self.cpus = update.cpus;
+        }
+        if update.cpu_shares.is_some() {
+            self.cpu_shares = update.cpu_shares;
+        }
+        if update.pids_limit.is_some() {
+            self.pids_limit = update.pids_limit;
+        }
+        if update.ulimits.is_some() {
+            self.ulimits = update.ulimits;
+        }
+        if update.memory_swap.is_some() {
+            self.memory_swap = update.memory_swap;
+        }
+        if update.shm_size.is_some() {
+            self.shm_size = update.shm_size;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Rejects values Docker would refuse at container creation, so a bad
+    /// config fails before the image is built.
+    pub fn validate(&self, function_name: &str, memory_mb: i64) -> Result<()> {
+        if let Some(cpus) = self.cpus {
+            let available = std::thread::available_parallelism().map_or(1, |n| n.get()) as f64;
+            if !cpus.is_finite() || cpus <= 0.0 || cpus > available {
+                bail!("'cpus' функции '{function_name}' должен быть больше 0 и не больше {available}");
+            }
+        }
+        if let Some(shares) = self.cpu_shares
+            && !(MIN_CPU_SHARES..=MAX_CPU_SHARES).contains(&shares)
+        {
+            bail!(
+                "'cpuShares' функции '{function_name}' должен быть от {MIN_CPU_SHARES} до {MAX_CPU_SHARES}"
+            );
+        }
+        if let Some(pids) = self.pids_limit
+            && pids <= 0
+        {
+            bail!("'pidsLimit' функции '{function_name}' должен быть больше 0");
+        }
+        if let Some(swap) = self.memory_swap
+            && swap != -1
+            && swap < memory_mb
+        {
+            bail!(
+                "'memorySwap' функции '{function_name}' должен быть -1 или не меньше 'memory' ({memory_mb})"
+            );
+        }
+        if let Some(shm) = self.shm_size
+            && shm <= 0
+        {
+            bail!("'shmSize' функции '{function_name}' должен быть больше 0");
+        }
+
+        let mut seen = HashSet::new();
+        for ulimit in self.ulimits.iter().flatten() {
+            if !ULIMIT_NAMES.contains(&ulimit.name.as_str()) {
+                bail!("Неизвестный ulimit '{}' у функции '{function_name}'", ulimit.name);
+            }
+            if !seen.insert(ulimit.name.as_str()) {
+                bail!("ulimit '{}' задан дважды у функции '{function_name}'", ulimit.name);
+            }
+            let soft_within_hard = ulimit.hard == -1 || (ulimit.soft != -1 && ulimit.soft <= ulimit.hard);
+            if ulimit.soft < -1 || ulimit.hard < -1 || !soft_within_hard {
+                bail!(
+                    "ulimit '{}' функции '{function_name}': нужно 0 <= soft <= hard или -1",
+                    ulimit.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply_to(&self, host_config: &mut HostConfig) {
+        host_config.nano_cpus = self.cpus.map(|cpus| (cpus * NANO_CPUS_PER_CPU).round() as i64);
+        host_config.cpu_shares = self.cpu_shares;
+        host_config.pids_limit = self.pids_limit;
+        host_config.memory_swap = self
+            .memory_swap
+            .map(|swap| if swap == -1 { -1 } else { swap * MB_TO_BYTES });
+        host_config.shm_size = self.shm_size.map(|shm| shm * MB_TO_BYTES);
+        host_config.ulimits = self.ulimits.as_ref().map(|ulimits| {
+            ulimits
+                .iter()
+                .map(|ulimit| ResourcesUlimits {
+                    name: Some(ulimit.name.clone()),
+                    soft: Some(ulimit.soft),
+                    hard: Some(ulimit.hard),
+                })
+                .collect()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bollard::secret::HostConfig;
+
+    use super::ResourceLimits;
+
+    fn limits(value: serde_json::Value) -> ResourceLimits {
+        serde_json::from_value(value).expect("limits should parse")
+    }
+
+    #[test]
+    fn limits_map_to_docker_host_config() {
+        let limits = limits(serde_json::json!({
+            "cpus": 0.5,
+            "cpuShares": 512,
+            "pidsLimit": 64,
+            "ulimits": [{ "name": "nofile", "soft": 1024, "hard": 2048 }],
+            "memorySwap": 256,
+            "shmSize": 64
+        }));
+        limits.validate("example", 128).expect("limits should be valid");
+
+        let mut host_config = HostConfig::default();
+        limits.apply_to(&mut host_config);
+        assert_eq!(host_config.nano_cpus, Some(500_000_000));
+        assert_eq!(host_config.cpu_shares, Some(512));
+        assert_eq!(host_config.pids_limit, Some(64));
+        assert_eq!(host_config.memory_swap, Some(256 * 1024 * 1024));
+        assert_eq!(host_config.shm_size, Some(64 * 1024 * 1024));
+        let ulimits = host_config.ulimits.expect("ulimits should be set");
+        assert_eq!(ulimits[0].name.as_deref(), Some("nofile"));
+        assert_eq!((ulimits[0].soft, ulimits[0].hard), (Some(1024), Some(2048)));
+    }
+
+    #[test]
+    fn invalid_limits_are_rejected() {
+        for invalid in [
+            serde_json::json!({ "cpus": 0 }),
+            serde_json::json!({ "cpuShares": 1 }),
+            serde_json::json!({ "pidsLimit": 0 }),
+            serde_json::json!({ "memorySwap": 64 }),
+            serde_json::json!({ "shmSize": 0 }),
+            serde_json::json!({ "ulimits": [{ "name": "threads", "soft": 1, "hard": 1 }] }),
+            serde_json::json!({ "ulimits": [{ "name": "nofile", "soft": 10, "hard": 5 }] }),
+            serde_json::json!({ "ulimits": [
+                { "name": "nproc", "soft": 1, "hard": 1 },
+                { "name": "nproc", "soft": 2, "hard": 2 }
+            ] }),
+        ] {
+            assert!(limits(invalid.clone()).validate("example", 128).is_err(), "{invalid}");
+        }
+        assert!(limits(serde_json::json!({ "memorySwap": -1 })).validate("example", 128).is_ok());
+    }
+}
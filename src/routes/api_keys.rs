@@ -0,0 +1,97 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    auth::{ApiKeyRecord, Scope, generate_key, hash_key},
+    errors::{auth_error::AuthError, serialize_err},
+    limits::InvokeLimits,
+};
+
+use super::EndpointResult;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    name: String,
+    scopes: Vec<String>,
+    #[serde(default)]
+    limits: Option<InvokeLimits>,
+}
+
+/// Creates a key with the given scopes. The plaintext key is only returned here.
+pub async fn create_api_key(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> EndpointResult {
+    let scopes = request
+        .scopes
+        .iter()
+        .map(|scope| Scope::parse(scope).map(|scope| scope.to_string()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| serialize_err(e.into()))?;
+
+    let (id, key) = generate_key();
+    let record = ApiKeyRecord {
+        id: id.clone(),
+        name: request.name,
+        scopes,
+        created_at_unix_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis(),
+        limits: request.limits,
+    };
+    let serialized = serde_json::to_string(&record).map_err(|e| serialize_err(e.into()))?;
+    state
+        .redis_manager
+        .store_api_key(&id, &hash_key(&key), &serialized)
+        .map_err(serialize_err)?;
+
+    Ok(Json(serde_json::json!({
+        "id": id,
+        "key": key,
+        "name": record.name,
+        "scopes": record.scopes,
+        "createdAtUnixMs": record.created_at_unix_ms,
+        "limits": record.limits
+    })))
+}
+
+pub async fn list_api_keys(State(state): State<Arc<AppState>>) -> EndpointResult {
+    let mut keys = state
+        .redis_manager
+        .list_api_keys()
+        .map_err(serialize_err)?
+        .iter()
+        .filter_map(|record| serde_json::from_str::<ApiKeyRecord>(record).ok())
+        .collect::<Vec<_>>();
+    keys.sort_by_key(|key| key.created_at_unix_ms);
+
+    Ok(Json(serde_json::json!({ "keys": keys })))
+}
+
+pub async fn revoke_api_key(
+    Path(key_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    let removed = state
+        .redis_manager
+        .delete_api_key(&key_id)
+        .map_err(serialize_err)?;
+    if !removed {
+        return Err(serialize_err(AuthError::KeyNotFound(key_id).into()));
+    }
+
+    Ok(Json(serde_json::json!({
+        "id": key_id,
+        "status": "revoked"
+    })))
+}
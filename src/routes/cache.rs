@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{AppState, errors::serialize_err};
+
+use super::EndpointResult;
+
+pub async fn invalidate_response_cache(
+    Path(function_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    let removed = state
+        .redis_manager
+        .invalidate_response_cache(&function_name)
+        .map_err(serialize_err)?;
+
+    Ok(Json(serde_json::json!({
+        "function": function_name,
+        "removedEntries": removed
+    })))
+}
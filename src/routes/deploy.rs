@@ -1,20 +1,29 @@
 use std::sync::Arc;
 
-use axum::{Json, extract::{Path, State}};
+use axum::{Json, extract::{Path, Query, State}};
 use log::info;
+use serde::Deserialize;
 
 use crate::{AppState, errors::serialize_err, redis_manager};
 
 use super::EndpointResult;
 
+#[derive(Debug, Deserialize)]
+pub struct DeployQuery {
+    /// Rebuilds the image even if the build context is unchanged.
+    #[serde(default)]
+    force: bool,
+}
+
 pub async fn deploy_function(
     Path(function_name): Path<String>,
     State(state): State<Arc<AppState>>,
+    Query(query): Query<DeployQuery>,
 ) -> EndpointResult {
     let deployment_id = uuid::Uuid::now_v7().simple();
     info!(
-        "Deploying function: '{}' with id: '{}'",
-        function_name, &deployment_id
+        "Deploying function: '{}' with id: '{}' (force: {})",
+        function_name, &deployment_id, query.force
     );
     state
         .redis_manager
@@ -35,7 +44,7 @@ pub async fn deploy_function(
     tokio::task::spawn(async move {
         let result = state
             .function_manager
-            .redeploy_function_by_name(&function_name, &state.redis_manager)
+            .redeploy_function_by_name(&function_name, &state.redis_manager, query.force)
             .await;
         match result {
             Ok(_) => {
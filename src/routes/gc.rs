@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State, http::StatusCode};
+
+use crate::{
+    AppState,
+    errors::{ApiErrorResponse, serialize_err},
+    gc::GcRequest,
+};
+
+use super::EndpointResult;
+
+/// Runs garbage collection now; `{"dryRun": true}` only lists what would go.
+pub async fn collect_garbage(
+    State(state): State<Arc<AppState>>,
+    request: Option<Json<GcRequest>>,
+) -> EndpointResult {
+    let request = request.map(|json| json.0).unwrap_or_default();
+    let policy = request
+        .policy(&state.function_manager.gc.policy)
+        .map_err(|e| ApiErrorResponse::new(StatusCode::BAD_REQUEST, "BAD_REQUEST", e.to_string(), Vec::new()))?;
+    let report = state
+        .function_manager
+        .collect_garbage(&policy, request.dry_run)
+        .await
+        .map_err(serialize_err)?;
+    Ok(Json(serde_json::to_value(report).map_err(|e| serialize_err(e.into()))?))
+}
@@ -1,30 +1,349 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
-use axum::{Json, extract::{Path, State}};
+use axum::{
+    Json,
+    Extension,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, header::HeaderName},
+};
+use log::{info, warn};
 use serde_json::Value;
 
-use crate::{AppState, errors::serialize_err};
+use crate::{
+    AppState,
+    auth::ApiKeyPrincipal,
+    errors::{ApiErrorResponse, invoke_chain_error::InvokeChainError, serialize_err},
+    function_manager::InvokeTimings,
+    invoke_chain::{CHAIN_ID_HEADER, CallChain, INVOKE_TOKEN_HEADER},
+    invoke_cache::{
+        IDEMPOTENCY_KEY_HEADER, IdempotencyKey, IdempotentStart, abandon_idempotent, complete_idempotent, hold_idempotent,
+        payload_hash, response_cache_key, start_idempotent, validate_idempotency_key,
+    },
+    jwt::VerifiedClaims,
+    limits::{LimitScope, admit},
+};
 
-use super::EndpointResult;
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+/// Request header that opts into the `Server-Timing` breakdown.
+const SERVER_TIMING_REQUEST: &str = "x-server-timing";
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+fn idempotency_key(headers: &HeaderMap) -> anyhow::Result<Option<String>> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+    let key = value.to_str().unwrap_or_default().trim();
+    validate_idempotency_key(key)?;
+    Ok(Some(key.to_string()))
+}
+
+fn wants_server_timing(headers: &HeaderMap) -> bool {
+    headers
+        .get(SERVER_TIMING_REQUEST)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| !matches!(value.trim(), "" | "0" | "false"))
+}
+
+/// Formats the breakdown as a `Server-Timing` header value. The attempt count
+/// is only added when the call to the replica was retried.
+fn server_timing(timings: &InvokeTimings, total_ms: f64) -> String {
+    let mut entries = [
+        ("queue", timings.queueing_ms),
+        ("balancer", timings.balancer_ms),
+        ("connect", timings.connection_ms),
+        ("container", timings.container_ms),
+        ("retries", timings.retries_ms),
+        ("upstream", timings.upstream_ms),
+        ("total", total_ms),
+    ]
+    .iter()
+    .map(|(name, ms)| format!("{name};dur={ms:.3}"))
+    .collect::<Vec<_>>();
+    if timings.attempts > 1 {
+        entries.push(format!("attempts;desc=\"{}\"", timings.attempts));
+    }
+    entries.join(", ")
+}
 
 pub async fn invoke_function(
     Path(function_name): Path<String>,
     State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    claims: Option<Extension<VerifiedClaims>>,
+    principal: Option<Extension<ApiKeyPrincipal>>,
     payload: Option<Json<Value>>,
-) -> EndpointResult {
+) -> Result<(HeaderMap, Json<Value>), ApiErrorResponse> {
+    let received = Instant::now();
     let payload_value = payload
         .map(|json| json.0)
         .unwrap_or_else(|| serde_json::json!({ "name": "test" }));
 
-    let result = state
+    let mut limit_scopes = Vec::new();
+    if let Some(limits) = state.function_manager.invoke_limits(&function_name).await {
+        limit_scopes.push(LimitScope {
+            key: format!("function:{function_name}"),
+            limits,
+        });
+    }
+    if let Some(Extension(principal)) = &principal
+        && let Some(limits) = &principal.limits
+    {
+        limit_scopes.push(LimitScope {
+            key: format!("apikey:{}", principal.key_id),
+            limits: limits.clone(),
+        });
+    }
+    let _permit = admit(&state.redis_manager, &limit_scopes).map_err(serialize_err)?;
+
+    let mut response_headers = HeaderMap::new();
+    let settings = state.function_manager.invoke_cache_settings(&function_name).await;
+    let payload_hash = payload_hash(&payload_value);
+    let idempotency = match (idempotency_key(&headers).map_err(serialize_err)?, &settings) {
+        (Some(key), Some(settings)) => {
+            let principal = principal.as_ref().map(|Extension(principal)| principal);
+            let claims = claims.as_ref().map(|Extension(claims)| claims);
+            Some((IdempotencyKey::new(key, principal, claims), settings))
+        }
+        _ => None,
+    };
+    if let Some((key, settings)) = &idempotency {
+        let start = start_idempotent(&state.redis_manager, &function_name, key, &payload_hash, settings)
+            .await
+            .map_err(serialize_err)?;
+        if let IdempotentStart::Replay(response) = start {
+            response_headers.insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+            return Ok((response_headers, Json(response)));
+        }
+    }
+
+    let forwarded_headers = claims
+        .map(|Extension(claims)| vec![(claims.header.clone(), claims.header_value())])
+        .unwrap_or_default();
+    let cache = settings.as_ref().and_then(|settings| {
+        let cache = settings.response_cache.as_ref()?;
+        Some((
+            response_cache_key(settings, &payload_hash, &forwarded_headers),
+            cache.ttl_secs,
+        ))
+    });
+    let cached = match &cache {
+        Some((cache_key, _)) => cached_response(&state, &function_name, cache_key, &mut response_headers),
+        None => None,
+    };
+    let work = async {
+        match cached {
+            Some(response) => Ok((response, None)),
+            None => {
+                // Only calls that reach a replica are recorded, so replaying
+                // the file produces the load that was actually served.
+                if let Some(recorder) = &state.invoke_recorder {
+                    recorder
+                        .record(&function_name, &payload_value, &headers, received)
+                        .await;
+                }
+                invoke_root(
+                    &state,
+                    &function_name,
+                    payload_value,
+                    &forwarded_headers,
+                    cache.as_ref(),
+                    &mut response_headers,
+                )
+                .await
+                .map(|(response, timings)| (response, Some(timings)))
+            }
+        }
+    };
+    let outcome = match &idempotency {
+        Some((key, settings)) => {
+            hold_idempotent(&state.redis_manager, &function_name, key, &payload_hash, settings, work).await
+        }
+        None => work.await,
+    };
+
+    let (response, timings) = match outcome {
+        Ok(outcome) => outcome,
+        Err(error) => {
+            if let Some((key, _)) = &idempotency
+                && let Err(e) = abandon_idempotent(&state.redis_manager, &function_name, key)
+            {
+                warn!("Failed to release idempotency key '{}' of '{function_name}': {e}", key.key);
+            }
+            return Err(serialize_err(error));
+        }
+    };
+    if let Some((key, settings)) = &idempotency
+        && let Err(e) = complete_idempotent(
+            &state.redis_manager,
+            &function_name,
+            key,
+            &payload_hash,
+            &response,
+            settings,
+        )
+    {
+        warn!("Failed to store idempotent response '{}' of '{function_name}': {e}", key.key);
+    }
+
+    if let Some(timings) = timings
+        && wants_server_timing(&headers)
+    {
+        let total_ms = received.elapsed().as_secs_f64() * 1000.0;
+        if let Ok(value) = HeaderValue::from_str(&server_timing(&timings, total_ms)) {
+            response_headers.insert(SERVER_TIMING, value);
+        }
+    }
+    Ok((response_headers, Json(response)))
+}
+
+fn insert_chain_id(response_headers: &mut HeaderMap, chain: &CallChain) {
+    if let Ok(value) = HeaderValue::from_str(&chain.id) {
+        response_headers.insert(CHAIN_ID_HEADER, value);
+    }
+}
+
+/// Invoke endpoint for replicas calling other functions. Instead of API keys
+/// it takes the token the platform sent with the caller's own invocation,
+/// which carries the call chain; the chain id and depth of the nested call
+/// are derived from it, never from the request. Invoke limits of the callee
+/// apply, idempotency keys and the response cache do not. Nested calls are
+/// not recorded, replaying the root call makes them again.
+pub async fn internal_invoke(
+    Path(function_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    payload: Option<Json<Value>>,
+) -> Result<(HeaderMap, Json<Value>), ApiErrorResponse> {
+    let token = headers
+        .get(INVOKE_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| serialize_err(InvokeChainError::MissingToken.into()))?;
+    let chain = state
         .function_manager
-        .try_invoke_with_meta(&function_name, payload_value)
+        .invoke_tokens
+        .nested_call(token)
+        .map_err(|e| serialize_err(e.into()))?;
+    let caller = chain.caller.clone().unwrap_or_default();
+    let payload_value = payload.map(|json| json.0).unwrap_or(Value::Null);
+
+    let limit_scopes = match state.function_manager.invoke_limits(&function_name).await {
+        Some(limits) => vec![LimitScope {
+            key: format!("function:{function_name}"),
+            limits,
+        }],
+        None => Vec::new(),
+    };
+    let _permit = admit(&state.redis_manager, &limit_scopes).map_err(serialize_err)?;
+
+    info!(
+        "Nested invoke '{caller}' -> '{function_name}' (chain {}, depth {})",
+        chain.id, chain.depth
+    );
+    state
+        .function_manager
+        .metrics
+        .record_nested_invocation(&function_name, &caller);
+    let outcome = state
+        .function_manager
+        .try_invoke_with_meta(&function_name, payload_value, &[], &chain)
         .await
         .map_err(serialize_err)?;
 
-    Ok(Json(serde_json::json!({
+    let mut response_headers = HeaderMap::new();
+    insert_chain_id(&mut response_headers, &chain);
+    Ok((
+        response_headers,
+        Json(serde_json::json!({
+            "function": function_name,
+            "containerId": outcome.container_id,
+            "result": outcome.result,
+            "caller": caller,
+            "chainId": chain.id,
+            "depth": chain.depth
+        })),
+    ))
+}
+
+/// Looks the payload up in the function's response cache. Cache errors only
+/// cost a cache miss.
+fn cached_response(
+    state: &AppState,
+    function_name: &str,
+    cache_key: &str,
+    response_headers: &mut HeaderMap,
+) -> Option<Value> {
+    match state.redis_manager.get_cached_response(function_name, cache_key) {
+        Ok(Some(cached)) => {
+            if let Ok(response) = serde_json::from_str(&cached) {
+                response_headers.insert(X_CACHE, HeaderValue::from_static("HIT"));
+                return Some(response);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Response cache lookup for '{function_name}' failed: {e}"),
+    }
+    response_headers.insert(X_CACHE, HeaderValue::from_static("MISS"));
+    None
+}
+
+/// Invokes a replica as the root of a new call chain and stores the response
+/// under `cache` (key and TTL) when the response cache is enabled.
+async fn invoke_root(
+    state: &AppState,
+    function_name: &str,
+    payload: Value,
+    forwarded_headers: &[(String, String)],
+    cache: Option<&(String, u64)>,
+    response_headers: &mut HeaderMap,
+) -> anyhow::Result<(Value, InvokeTimings)> {
+    let chain = CallChain::root();
+    insert_chain_id(response_headers, &chain);
+    let result = state
+        .function_manager
+        .try_invoke_with_meta(function_name, payload, forwarded_headers, &chain)
+        .await?;
+    let mut response = serde_json::json!({
         "function": function_name,
         "containerId": result.container_id,
         "result": result.result
-    })))
-}
\ No newline at end of file
+    });
+    if let Some(instantiate_ms) = result.instantiate_ms {
+        response["instantiateMs"] = serde_json::json!(instantiate_ms);
+    }
+
+    if let Some((cache_key, ttl_secs)) = cache
+        && let Err(e) = state.redis_manager.cache_response(
+            function_name,
+            cache_key,
+            &response.to_string(),
+            *ttl_secs,
+        )
+    {
+        warn!("Failed to cache response of '{function_name}': {e}");
+    }
+    Ok((response, result.timings))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::function_manager::InvokeTimings;
+
+    use super::server_timing;
+
+    #[test]
+    fn attempts_are_reported_only_after_retries() {
+        let mut timings = InvokeTimings {
+            balancer_ms: 0.25,
+            attempts: 1,
+            ..Default::default()
+        };
+        let header = server_timing(&timings, 3.0);
+        assert!(header.contains("balancer;dur=0.250"), "{header}");
+        assert!(header.ends_with("total;dur=3.000"), "{header}");
+        assert!(!header.contains("attempts"));
+
+        timings.attempts = 3;
+        assert!(server_timing(&timings, 3.0).ends_with("attempts;desc=\"3\""));
+    }
+}
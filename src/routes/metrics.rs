@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use axum::{Json, extract::State};
+
+use crate::AppState;
+
+use super::EndpointResult;
+
+pub async fn get_metrics(State(state): State<Arc<AppState>>) -> EndpointResult {
+    let functions = state.function_manager.metrics_report();
+    Ok(Json(serde_json::json!({
+        "functions": functions
+    })))
+}
@@ -1,12 +1,20 @@
 use axum::response::Json;
 use serde_json::Value;
 
+pub mod api_keys;
+pub mod cache;
 pub mod deploy;
+pub mod gc;
 pub mod get_status;
 pub mod invoke;
 pub mod list_functions;
+pub mod metrics;
 pub mod replicas;
+pub mod secrets;
+pub mod state;
 pub mod stop;
 pub mod update_config;
+pub mod volume;
+pub mod workflows;
 
 pub type EndpointResult = std::result::Result<Json<Value>, crate::errors::ApiErrorResponse>;
\ No newline at end of file
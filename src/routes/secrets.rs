@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use log::error;
+use serde::Deserialize;
+
+use crate::{
+    AppState,
+    errors::{secret_error::SecretError, serialize_err},
+    redis_manager::DeploymentState,
+    secrets::{SecretStore, validate_secret_name},
+};
+
+use super::EndpointResult;
+
+#[derive(Debug, Deserialize)]
+pub struct PutSecretRequest {
+    value: String,
+}
+
+/// Creates or rotates a secret. Running functions that reference a rotated
+/// secret get their replicas replaced one by one in the background, tracked
+/// as an operation under `/deploy/status/{id}`.
+pub async fn put_secret(
+    Path(secret_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<PutSecretRequest>,
+) -> EndpointResult {
+    let replaced = state
+        .function_manager
+        .secrets
+        .put(&state.redis_manager, &secret_name, &request.value)
+        .map_err(serialize_err)?;
+    let functions = if replaced {
+        state.function_manager.functions_using_secret(&secret_name).await
+    } else {
+        Vec::new()
+    };
+
+    let operation_id = if functions.is_empty() {
+        None
+    } else {
+        let operation_id = uuid::Uuid::now_v7().simple().to_string();
+        state
+            .redis_manager
+            .set_operation_kind(&operation_id, "secret_rotation")
+            .map_err(serialize_err)?;
+        state
+            .redis_manager
+            .set_operation_state(&operation_id, DeploymentState::Running)
+            .map_err(serialize_err)?;
+        tokio::task::spawn(restart_functions(
+            Arc::clone(&state),
+            operation_id.clone(),
+            functions.clone(),
+        ));
+        Some(operation_id)
+    };
+
+    Ok(Json(serde_json::json!({
+        "name": secret_name,
+        "status": if replaced { "rotated" } else { "created" },
+        "restartingFunctions": functions,
+        "id": operation_id
+    })))
+}
+
+async fn restart_functions(state: Arc<AppState>, operation_id: String, functions: Vec<String>) {
+    let mut failed = false;
+    for function_name in functions {
+        let log = match state
+            .function_manager
+            .rolling_restart(&function_name, &state.redis_manager)
+            .await
+        {
+            Ok(()) => format!("Реплики функции '{function_name}' перезапущены"),
+            Err(e) => {
+                error!("Rolling restart of '{function_name}' failed: {e}");
+                failed = true;
+                format!("Не удалось перезапустить функцию '{function_name}': {e}")
+            }
+        };
+        let _ = state.redis_manager.append_operation_logs(&operation_id, &log);
+    }
+
+    if failed {
+        let _ = state.redis_manager.set_operation_error(
+            &operation_id,
+            "Не все функции удалось перезапустить с новым значением секрета",
+        );
+        let _ = state
+            .redis_manager
+            .set_operation_state(&operation_id, DeploymentState::Failed);
+    } else {
+        let _ = state
+            .redis_manager
+            .set_operation_state(&operation_id, DeploymentState::Finished);
+    }
+}
+
+/// Lists secret names and update times; values are never returned.
+pub async fn list_secrets(State(state): State<Arc<AppState>>) -> EndpointResult {
+    let secrets = SecretStore::list(&state.redis_manager).map_err(serialize_err)?;
+    Ok(Json(serde_json::json!({ "secrets": secrets })))
+}
+
+pub async fn delete_secret(
+    Path(secret_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    validate_secret_name(&secret_name).map_err(|e| serialize_err(e.into()))?;
+    let functions = state.function_manager.functions_using_secret(&secret_name).await;
+    if !functions.is_empty() {
+        return Err(serialize_err(SecretError::InUse(secret_name, functions.join(", ")).into()));
+    }
+    let removed = state
+        .redis_manager
+        .delete_secret(&secret_name)
+        .map_err(serialize_err)?;
+    if !removed {
+        return Err(serialize_err(SecretError::NotFound(secret_name).into()));
+    }
+
+    Ok(Json(serde_json::json!({
+        "name": secret_name,
+        "status": "deleted"
+    })))
+}
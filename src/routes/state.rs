@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::HeaderMap,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    errors::{ApiErrorResponse, invoke_chain_error::InvokeChainError, serialize_err, state_error::StateError},
+    invoke_chain::INVOKE_TOKEN_HEADER,
+    redis_manager::StateCondition,
+    state::{StateConfig, validate_state_key},
+};
+
+use super::EndpointResult;
+
+const DEFAULT_LIST_LIMIT: usize = 100;
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// Resolves the calling function from its invoke token; every state
+/// operation is confined to that function's namespace.
+async fn caller(state: &AppState, headers: &HeaderMap) -> Result<(String, StateConfig), ApiErrorResponse> {
+    let token = headers
+        .get(INVOKE_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| serialize_err(InvokeChainError::MissingToken.into()))?;
+    let function_name = state
+        .function_manager
+        .invoke_tokens
+        .function_name(token)
+        .map_err(|e| serialize_err(e.into()))?;
+    let config = state
+        .function_manager
+        .state_config(&function_name)
+        .await
+        .map_err(serialize_err)?;
+    Ok((function_name, config))
+}
+
+fn checked_key(key: &str) -> Result<(), ApiErrorResponse> {
+    validate_state_key(key).map_err(|e| serialize_err(e.into()))
+}
+
+fn ttl_ms(ttl_secs: Option<u64>) -> u64 {
+    ttl_secs.unwrap_or_default().saturating_mul(1000)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListStateQuery {
+    #[serde(default)]
+    prefix: String,
+    limit: Option<usize>,
+}
+
+pub async fn list_state(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<ListStateQuery>,
+) -> EndpointResult {
+    let (function_name, _) = caller(&state, &headers).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIST_LIMIT).clamp(1, MAX_LIST_LIMIT);
+    let keys = state
+        .redis_manager
+        .state_list(&function_name, &query.prefix, limit)
+        .map_err(serialize_err)?;
+    Ok(Json(serde_json::json!({ "keys": keys })))
+}
+
+pub async fn get_state(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> EndpointResult {
+    let (function_name, _) = caller(&state, &headers).await?;
+    checked_key(&key)?;
+    let (value, ttl_ms) = state
+        .redis_manager
+        .state_get(&function_name, &key)
+        .map_err(serialize_err)?
+        .ok_or_else(|| serialize_err(StateError::NotFound(key.clone()).into()))?;
+    let value: Value = serde_json::from_str(&value).map_err(|e| serialize_err(e.into()))?;
+    Ok(Json(serde_json::json!({
+        "key": key,
+        "value": value,
+        "ttlSecs": ttl_ms.map(|ttl_ms| ttl_ms.div_ceil(1000))
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutStateRequest {
+    value: Value,
+    #[serde(rename = "ttlSecs")]
+    ttl_secs: Option<u64>,
+}
+
+/// Sets a value; without `ttlSecs` the key never expires.
+pub async fn put_state(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<PutStateRequest>,
+) -> EndpointResult {
+    let (function_name, config) = caller(&state, &headers).await?;
+    checked_key(&key)?;
+    let value = config
+        .encode_value(&function_name, &request.value)
+        .map_err(|e| serialize_err(e.into()))?;
+    state
+        .redis_manager
+        .state_write(
+            &function_name,
+            &key,
+            &value,
+            ttl_ms(request.ttl_secs),
+            config.max_keys,
+            StateCondition::Always,
+        )
+        .map_err(serialize_err)?;
+    Ok(Json(serde_json::json!({ "key": key, "status": "stored" })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompareAndSetRequest {
+    /// `null` or missing: the key must not exist.
+    #[serde(default)]
+    expected: Option<Value>,
+    value: Value,
+    #[serde(rename = "ttlSecs")]
+    ttl_secs: Option<u64>,
+}
+
+pub async fn compare_and_set_state(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<CompareAndSetRequest>,
+) -> EndpointResult {
+    let (function_name, config) = caller(&state, &headers).await?;
+    checked_key(&key)?;
+    let value = config
+        .encode_value(&function_name, &request.value)
+        .map_err(|e| serialize_err(e.into()))?;
+    let expected = request.expected.map(|expected| expected.to_string());
+    let condition = match &expected {
+        Some(expected) => StateCondition::Equals(expected),
+        None => StateCondition::Absent,
+    };
+    let swapped = state
+        .redis_manager
+        .state_write(
+            &function_name,
+            &key,
+            &value,
+            ttl_ms(request.ttl_secs),
+            config.max_keys,
+            condition,
+        )
+        .map_err(serialize_err)?;
+    Ok(Json(serde_json::json!({ "key": key, "swapped": swapped })))
+}
+
+fn default_increment() -> i64 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IncrementStateRequest {
+    #[serde(default = "default_increment")]
+    by: i64,
+    /// Refreshes the TTL; without it an existing TTL is kept.
+    #[serde(rename = "ttlSecs")]
+    ttl_secs: Option<u64>,
+}
+
+/// Adds to an integer value, a missing key counts as 0.
+pub async fn increment_state(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    request: Option<Json<IncrementStateRequest>>,
+) -> EndpointResult {
+    let (function_name, config) = caller(&state, &headers).await?;
+    checked_key(&key)?;
+    let request = request.map(|json| json.0).unwrap_or(IncrementStateRequest {
+        by: default_increment(),
+        ttl_secs: None,
+    });
+    let value = state
+        .redis_manager
+        .state_increment(
+            &function_name,
+            &key,
+            request.by,
+            ttl_ms(request.ttl_secs),
+            config.max_keys,
+        )
+        .map_err(serialize_err)?;
+    Ok(Json(serde_json::json!({ "key": key, "value": value })))
+}
+
+pub async fn delete_state(
+    Path(key): Path<String>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> EndpointResult {
+    let (function_name, _) = caller(&state, &headers).await?;
+    checked_key(&key)?;
+    let deleted = state
+        .redis_manager
+        .state_delete(&function_name, &key)
+        .map_err(serialize_err)?;
+    if !deleted {
+        return Err(serialize_err(StateError::NotFound(key).into()));
+    }
+    Ok(Json(serde_json::json!({ "key": key, "status": "deleted" })))
+}
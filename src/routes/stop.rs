@@ -15,7 +15,7 @@ pub async fn stop_function(
 ) -> EndpointResult {
     let removed = state
         .function_manager
-        .stop_function(&function_name, &state.redis_manager)
+        .stop_and_release_function(&function_name, &state.redis_manager)
         .await
         .map_err(serialize_err)?;
 
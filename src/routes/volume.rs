@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+
+use crate::{AppState, errors::serialize_err};
+
+use super::EndpointResult;
+
+pub async fn get_function_volume(
+    Path(function_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    let report = state
+        .function_manager
+        .volume_report(&function_name)
+        .await
+        .map_err(serialize_err)?;
+    Ok(Json(report))
+}
+
+pub async fn create_volume_snapshot(
+    Path(function_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    let snapshot = state
+        .function_manager
+        .snapshot_volume(&function_name)
+        .await
+        .map_err(serialize_err)?;
+
+    Ok(Json(serde_json::json!({
+        "function": function_name,
+        "snapshot": snapshot
+    })))
+}
+
+pub async fn restore_volume_snapshot(
+    Path((function_name, snapshot_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    state
+        .function_manager
+        .restore_volume(&function_name, &snapshot_id)
+        .await
+        .map_err(serialize_err)?;
+
+    Ok(Json(serde_json::json!({
+        "function": function_name,
+        "snapshot": snapshot_id,
+        "status": "restored"
+    })))
+}
+
+pub async fn delete_volume_snapshot(
+    Path((function_name, snapshot_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    state
+        .function_manager
+        .delete_volume_snapshot(&function_name, &snapshot_id)
+        .await
+        .map_err(serialize_err)?;
+
+    Ok(Json(serde_json::json!({
+        "function": function_name,
+        "snapshot": snapshot_id,
+        "status": "deleted"
+    })))
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+};
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    auth::ApiKeyPrincipal,
+    errors::{serialize_err, workflow_error::WorkflowError},
+    workflows::{
+        definition::WorkflowDefinition,
+        engine::{ExecutionCaller, WorkflowExecution, start_execution},
+    },
+};
+
+use super::EndpointResult;
+
+/// Starts an execution with the request body as input and returns its id
+/// right away; progress is read from `GET .../executions/{id}`.
+pub async fn start_workflow_execution(
+    Path(workflow_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<ApiKeyPrincipal>>,
+    input: Option<Json<Value>>,
+) -> EndpointResult {
+    let definition = WorkflowDefinition::load(&workflow_name)
+        .await
+        .map_err(serialize_err)?;
+    let input = input.map(|json| json.0).unwrap_or_else(|| serde_json::json!({}));
+    let caller = principal.map(|Extension(principal)| ExecutionCaller {
+        key_id: principal.key_id,
+        limits: principal.limits,
+    });
+    let execution = start_execution(state, definition, input, caller).map_err(serialize_err)?;
+    Ok(Json(serde_json::json!({
+        "id": execution.id,
+        "workflow": execution.workflow,
+        "status": execution.status
+    })))
+}
+
+fn load_execution(state: &AppState, workflow_name: &str, execution_id: &str) -> anyhow::Result<WorkflowExecution> {
+    let not_found = || WorkflowError::ExecutionNotFound(execution_id.to_string());
+    let record = state
+        .redis_manager
+        .get_workflow_execution(execution_id)?
+        .ok_or_else(not_found)?;
+    let execution: WorkflowExecution = serde_json::from_str(&record)?;
+    if execution.workflow != workflow_name {
+        return Err(not_found().into());
+    }
+    Ok(execution)
+}
+
+/// Executions of a workflow, newest first, without step details.
+pub async fn list_workflow_executions(
+    Path(workflow_name): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    let ids = state
+        .redis_manager
+        .list_workflow_executions(&workflow_name)
+        .map_err(serialize_err)?;
+    let executions = ids
+        .iter()
+        .filter_map(|id| load_execution(&state, &workflow_name, id).ok())
+        .map(|execution| {
+            serde_json::json!({
+                "id": execution.id,
+                "status": execution.status,
+                "startedAtUnixMs": execution.started_at_unix_ms,
+                "finishedAtUnixMs": execution.finished_at_unix_ms
+            })
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(serde_json::json!({
+        "workflow": workflow_name,
+        "executions": executions
+    })))
+}
+
+/// Full state of an execution, step by step.
+pub async fn get_workflow_execution(
+    Path((workflow_name, execution_id)): Path<(String, String)>,
+    State(state): State<Arc<AppState>>,
+) -> EndpointResult {
+    let execution = load_execution(&state, &workflow_name, &execution_id).map_err(serialize_err)?;
+    Ok(Json(serde_json::to_value(execution).map_err(|e| serialize_err(e.into()))?))
+}
@@ -0,0 +1,208 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::secret_error::SecretError, redis_manager::RedisManager};
+
+const SECRETS_KEY_ENV: &str = "SERVERLESS_SECRETS_KEY";
+const MAX_SECRET_NAME_LEN: usize = 128;
+
+/// Reference to a stored secret from `"secrets"` in `function.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SecretRef {
+    #[serde(rename = "secretRef")]
+    pub secret_ref: String,
+}
+
+/// What Redis holds for a secret; the value only as ciphertext.
+#[derive(Serialize, Deserialize)]
+struct StoredSecret {
+    #[serde(rename = "updatedAtUnixMs")]
+    updated_at_unix_ms: u128,
+    ciphertext: String,
+}
+
+/// A secret as listed by the API, without its value.
+#[derive(Debug, Serialize)]
+pub struct SecretInfo {
+    pub name: String,
+    #[serde(rename = "updatedAtUnixMs")]
+    pub updated_at_unix_ms: u128,
+}
+
+/// Secret names end up in Redis keys and API paths.
+pub fn validate_secret_name(name: &str) -> Result<(), SecretError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_SECRET_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(SecretError::InvalidName(name.to_string()))
+    }
+}
+
+/// Secrets encrypted with AES-256-GCM under the master key from
+/// `SERVERLESS_SECRETS_KEY` (32 bytes, base64). Without the key the store is
+/// disabled and functions referencing secrets fail to deploy.
+pub struct SecretStore {
+    key: Option<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretStore")
+            .field("enabled", &self.enabled())
+            .finish()
+    }
+}
+
+impl SecretStore {
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(SECRETS_KEY_ENV) {
+            Ok(encoded) if !encoded.trim().is_empty() => Self::with_key(encoded.trim()),
+            _ => Ok(Self {
+                key: None,
+                rng: SystemRandom::new(),
+            }),
+        }
+    }
+
+    fn with_key(encoded: &str) -> Result<Self> {
+        let bytes = STANDARD
+            .decode(encoded)
+            .with_context(|| format!("{SECRETS_KEY_ENV} должен быть в base64"))?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| anyhow!("{SECRETS_KEY_ENV} должен содержать ровно 32 байта"))?;
+        Ok(Self {
+            key: Some(LessSafeKey::new(key)),
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn key(&self) -> Result<&LessSafeKey, SecretError> {
+        self.key.as_ref().ok_or(SecretError::Disabled)
+    }
+
+    /// Nonce and ciphertext as base64. The name is bound as associated data,
+    /// so a ciphertext copied under another name does not decrypt.
+    fn encrypt(&self, name: &str, value: &str) -> Result<String> {
+        let key = self.key()?;
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Не удалось сгенерировать nonce"))?;
+        let mut sealed = value.as_bytes().to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(name.as_bytes()),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow!("Не удалось зашифровать секрет '{name}'"))?;
+        let mut stored = nonce.to_vec();
+        stored.extend_from_slice(&sealed);
+        Ok(STANDARD.encode(stored))
+    }
+
+    fn decrypt(&self, name: &str, ciphertext: &str) -> Result<String> {
+        let key = self.key()?;
+        let corrupted = || SecretError::Corrupted(name.to_string());
+        let mut stored = STANDARD.decode(ciphertext).map_err(|_| corrupted())?;
+        if stored.len() < NONCE_LEN {
+            return Err(corrupted().into());
+        }
+        let mut sealed = stored.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&stored).map_err(|_| corrupted())?;
+        let plain = key
+            .open_in_place(nonce, Aad::from(name.as_bytes()), &mut sealed)
+            .map_err(|_| corrupted())?;
+        Ok(String::from_utf8(plain.to_vec()).map_err(|_| corrupted())?)
+    }
+
+    /// Encrypts and stores the value, returning true when it replaced an
+    /// existing secret.
+    pub fn put(&self, redis_manager: &RedisManager, name: &str, value: &str) -> Result<bool> {
+        validate_secret_name(name)?;
+        let stored = StoredSecret {
+            updated_at_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            ciphertext: self.encrypt(name, value)?,
+        };
+        redis_manager.store_secret(name, &serde_json::to_string(&stored)?)
+    }
+
+    pub fn get(&self, redis_manager: &RedisManager, name: &str) -> Result<String> {
+        let serialized = redis_manager
+            .get_secret(name)?
+            .ok_or_else(|| SecretError::NotFound(name.to_string()))?;
+        let stored: StoredSecret = serde_json::from_str(&serialized)
+            .map_err(|_| SecretError::Corrupted(name.to_string()))?;
+        self.decrypt(name, &stored.ciphertext)
+    }
+
+    /// Names and update times only; values never leave the store this way.
+    pub fn list(redis_manager: &RedisManager) -> Result<Vec<SecretInfo>> {
+        let mut secrets = redis_manager
+            .list_secrets()?
+            .into_iter()
+            .filter_map(|(name, serialized)| {
+                let stored = serde_json::from_str::<StoredSecret>(&serialized).ok()?;
+                Some(SecretInfo {
+                    name,
+                    updated_at_unix_ms: stored.updated_at_unix_ms,
+                })
+            })
+            .collect::<Vec<_>>();
+        secrets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(secrets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+
+    use super::{SecretStore, validate_secret_name};
+
+    fn store() -> SecretStore {
+        SecretStore::with_key(&STANDARD.encode([7u8; 32])).expect("key should be accepted")
+    }
+
+    #[test]
+    fn values_round_trip_and_are_bound_to_their_name() {
+        let store = store();
+        let ciphertext = store.encrypt("db-password", "hunter2").unwrap();
+        assert!(!ciphertext.contains("hunter2"));
+        assert_ne!(ciphertext, store.encrypt("db-password", "hunter2").unwrap());
+        assert_eq!(store.decrypt("db-password", &ciphertext).unwrap(), "hunter2");
+
+        assert!(store.decrypt("other", &ciphertext).is_err());
+        let other_key = SecretStore::with_key(&STANDARD.encode([8u8; 32])).unwrap();
+        assert!(other_key.decrypt("db-password", &ciphertext).is_err());
+    }
+
+    #[test]
+    fn master_key_must_be_32_bytes_and_names_must_be_safe() {
+        assert!(SecretStore::with_key(&STANDARD.encode([1u8; 16])).is_err());
+        assert!(SecretStore::with_key("not base64!").is_err());
+
+        assert!(validate_secret_name("db.password_2").is_ok());
+        assert!(validate_secret_name("").is_err());
+        assert!(validate_secret_name("a:b").is_err());
+    }
+}
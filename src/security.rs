@@ -0,0 +1,238 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+use bollard::secret::HostConfig;
+use serde::{Deserialize, Serialize};
+
+/// `nobody`, present in practically every base image; used when neither the
+/// config nor the image names a non-root user.
+const DEFAULT_USER: &str = "65534:65534";
+const PRIVILEGED_PORTS_END: u16 = 1024;
+
+/// Capabilities that amount to running privileged, so they are refused even
+/// when listed explicitly.
+const REFUSED_CAPABILITIES: &[&str] = &["ALL", "SYS_ADMIN", "SYS_MODULE", "SYS_RAWIO"];
+
+const KNOWN_CAPABILITIES: &[&str] = &[
+    "AUDIT_CONTROL", "AUDIT_READ", "AUDIT_WRITE", "BLOCK_SUSPEND", "BPF", "CHECKPOINT_RESTORE",
+    "CHOWN", "DAC_OVERRIDE", "DAC_READ_SEARCH", "FOWNER", "FSETID", "IPC_LOCK", "IPC_OWNER",
+    "KILL", "LEASE", "LINUX_IMMUTABLE", "MAC_ADMIN", "MAC_OVERRIDE", "MKNOD", "NET_ADMIN",
+    "NET_BIND_SERVICE", "NET_BROADCAST", "NET_RAW", "PERFMON", "SETFCAP", "SETGID", "SETPCAP",
+    "SETUID", "SYS_BOOT", "SYS_CHROOT", "SYS_NICE", "SYS_PACCT", "SYS_PTRACE", "SYS_RESOURCE",
+    "SYS_TIME", "SYS_TTY_CONFIG", "SYSLOG", "WAKE_ALARM",
+];
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_tmpfs() -> HashMap<String, String> {
+    HashMap::from([("/tmp".to_string(), "rw,noexec,nosuid,size=64m".to_string())])
+}
+
+/// Hardening profile of a container function (`"security"` in
+/// `function.json`). The defaults are the secure settings; a function opts
+/// out of them explicitly.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct SecurityConfig {
+    #[serde(default = "default_true", rename = "readOnlyRootfs")]
+    pub read_only_rootfs: bool,
+    /// Writable in-memory mounts, path to mount options.
+    #[serde(default = "default_tmpfs")]
+    pub tmpfs: HashMap<String, String>,
+    #[serde(default = "default_true", rename = "noNewPrivileges")]
+    pub no_new_privileges: bool,
+    /// All capabilities are dropped; these are added back.
+    #[serde(default, rename = "capAdd")]
+    pub cap_add: Vec<String>,
+    /// `uid[:gid]` or a user name known to the image; root is refused. When
+    /// unset the image's own `USER` is kept unless it is root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Seccomp profile JSON, relative paths are resolved against the function
+    /// directory. Docker's default profile applies when unset.
+    #[serde(default, rename = "seccompProfile", skip_serializing_if = "Option::is_none")]
+    pub seccomp_profile: Option<String>,
+    /// Only accepted as `false`; kept so a config asking for it fails loudly
+    /// instead of being silently ignored.
+    #[serde(default)]
+    pub privileged: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            read_only_rootfs: true,
+            tmpfs: default_tmpfs(),
+            no_new_privileges: true,
+            cap_add: Vec::new(),
+            user: None,
+            seccomp_profile: None,
+            privileged: false,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Capabilities to add back, normalized to Docker's names without `CAP_`.
+    fn capabilities(&self) -> Vec<String> {
+        self.cap_add
+            .iter()
+            .map(|capability| {
+                let capability = capability.trim().to_ascii_uppercase();
+                capability
+                    .strip_prefix("CAP_")
+                    .map(ToOwned::to_owned)
+                    .unwrap_or(capability)
+            })
+            .collect()
+    }
+
+    pub fn validate(&self, function_name: &str, inner_port: u16) -> Result<()> {
+        if self.privileged {
+            bail!("Функция '{function_name}' не может запускаться в привилегированном режиме");
+        }
+
+        if self.user.as_deref().is_some_and(is_root) {
+            bail!("Функция '{function_name}' должна запускаться не от root: задайте 'security.user'");
+        }
+
+        let capabilities = self.capabilities();
+        for capability in &capabilities {
+            if REFUSED_CAPABILITIES.contains(&capability.as_str()) {
+                bail!(
+                    "Capability '{capability}' функции '{function_name}' равносильна привилегированному режиму"
+                );
+            }
+            if !KNOWN_CAPABILITIES.contains(&capability.as_str()) {
+                bail!("Неизвестная capability '{capability}' у функции '{function_name}'");
+            }
+        }
+        if inner_port < PRIVILEGED_PORTS_END && !capabilities.iter().any(|c| c == "NET_BIND_SERVICE") {
+            bail!(
+                "Функция '{function_name}' слушает порт {inner_port} < {PRIVILEGED_PORTS_END}: без root для этого нужна capability 'NET_BIND_SERVICE' в 'security.capAdd'"
+            );
+        }
+
+        if let Some(path) = self.tmpfs.keys().find(|path| !path.starts_with('/')) {
+            bail!("Путь tmpfs '{path}' функции '{function_name}' должен быть абсолютным");
+        }
+        Ok(())
+    }
+
+    /// User replicas run as: the configured one, otherwise the image's
+    /// `USER`, falling back to `nobody` when the image runs as root.
+    pub fn effective_user(&self, image_user: Option<&str>) -> String {
+        if let Some(user) = &self.user {
+            return user.clone();
+        }
+        match image_user.map(str::trim) {
+            Some(user) if !is_root(user) => user.to_string(),
+            _ => DEFAULT_USER.to_string(),
+        }
+    }
+
+    /// Applies the profile to a container's host config. The seccomp profile
+    /// is read here because Docker expects its content, not a path.
+    pub async fn apply_to(&self, function_name: &str, host_config: &mut HostConfig) -> Result<()> {
+        let mut security_opt = Vec::new();
+        if self.no_new_privileges {
+            security_opt.push("no-new-privileges:true".to_string());
+        }
+        if let Some(path) = &self.seccomp_profile {
+            security_opt.push(format!("seccomp={}", read_seccomp_profile(function_name, path).await?));
+        }
+
+        host_config.privileged = Some(false);
+        host_config.readonly_rootfs = Some(self.read_only_rootfs);
+        host_config.tmpfs = (!self.tmpfs.is_empty()).then(|| self.tmpfs.clone());
+        host_config.cap_drop = Some(vec!["ALL".to_string()]);
+        host_config.cap_add = Some(self.capabilities());
+        host_config.security_opt = Some(security_opt);
+        Ok(())
+    }
+}
+
+/// An empty user (the image default) is root as well.
+fn is_root(user: &str) -> bool {
+    let name = user.split(':').next().unwrap_or_default().trim();
+    name.is_empty() || name == "root" || name == "0"
+}
+
+async fn read_seccomp_profile(function_name: &str, path: &str) -> Result<String> {
+    let path = PathBuf::from(path);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        PathBuf::from("functions").join(function_name).join(path)
+    };
+    let content = tokio::fs::read_to_string(&path)
+        .await
+        .with_context(|| format!("Не удалось прочитать seccomp-профиль '{}'", path.display()))?;
+    let profile: serde_json::Value = serde_json::from_str(&content)
+        .with_context(|| format!("Некорректный seccomp-профиль '{}'", path.display()))?;
+    Ok(profile.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use bollard::secret::HostConfig;
+
+    use super::SecurityConfig;
+
+    fn security(value: serde_json::Value) -> SecurityConfig {
+        serde_json::from_value(value).expect("security config should parse")
+    }
+
+    #[tokio::test]
+    async fn defaults_harden_the_container() {
+        let security = security(serde_json::json!({}));
+        assert_eq!(security, SecurityConfig::default());
+        security.validate("example", 3000).expect("defaults should be valid");
+
+        let mut host_config = HostConfig::default();
+        security.apply_to("example", &mut host_config).await.unwrap();
+        assert_eq!(host_config.readonly_rootfs, Some(true));
+        assert_eq!(host_config.privileged, Some(false));
+        assert_eq!(host_config.cap_drop, Some(vec!["ALL".to_string()]));
+        assert_eq!(host_config.cap_add, Some(Vec::new()));
+        assert!(host_config.tmpfs.unwrap().contains_key("/tmp"));
+        assert_eq!(
+            host_config.security_opt,
+            Some(vec!["no-new-privileges:true".to_string()])
+        );
+    }
+
+    #[test]
+    fn privileged_root_and_dangerous_capabilities_are_refused() {
+        for invalid in [
+            serde_json::json!({ "privileged": true }),
+            serde_json::json!({ "user": "root" }),
+            serde_json::json!({ "user": "0:0" }),
+            serde_json::json!({ "user": "" }),
+            serde_json::json!({ "capAdd": ["SYS_ADMIN"] }),
+            serde_json::json!({ "capAdd": ["all"] }),
+            serde_json::json!({ "capAdd": ["MAKE_COFFEE"] }),
+            serde_json::json!({ "tmpfs": { "tmp": "" } }),
+        ] {
+            assert!(security(invalid.clone()).validate("example", 3000).is_err(), "{invalid}");
+        }
+
+        assert!(SecurityConfig::default().validate("example", 80).is_err());
+        let security = security(serde_json::json!({ "capAdd": ["cap_net_bind_service"] }));
+        assert!(security.validate("example", 80).is_ok());
+    }
+
+    #[test]
+    fn image_users_are_kept_unless_they_are_root() {
+        let defaults = SecurityConfig::default();
+        assert_eq!(defaults.effective_user(Some("node")), "node");
+        assert_eq!(defaults.effective_user(Some("1000:1000")), "1000:1000");
+        for root in [None, Some(""), Some("root"), Some("0:0")] {
+            assert_eq!(defaults.effective_user(root), "65534:65534", "{root:?}");
+        }
+
+        let configured = security(serde_json::json!({ "user": "app" }));
+        assert_eq!(configured.effective_user(Some("node")), "app");
+    }
+}
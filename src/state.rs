@@ -0,0 +1,100 @@
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::state_error::StateError;
+
+const MAX_KEY_LEN: usize = 256;
+/// Upper bound for `maxValueBytes`, values are kept in memory by Redis.
+const MAX_VALUE_BYTES_LIMIT: usize = 1024 * 1024;
+
+fn default_max_keys() -> u32 {
+    1000
+}
+
+fn default_max_value_bytes() -> usize {
+    64 * 1024
+}
+
+/// Quotas of a function's key/value state (`"state"` in `function.json`).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct StateConfig {
+    #[serde(default = "default_max_keys", rename = "maxKeys")]
+    pub max_keys: u32,
+    /// Size of a value serialized as JSON.
+    #[serde(default = "default_max_value_bytes", rename = "maxValueBytes")]
+    pub max_value_bytes: usize,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            max_keys: default_max_keys(),
+            max_value_bytes: default_max_value_bytes(),
+        }
+    }
+}
+
+impl StateConfig {
+    pub fn validate(&self, function_name: &str) -> Result<()> {
+        if self.max_keys == 0 {
+            bail!("'state.maxKeys' функции '{function_name}' должен быть больше 0");
+        }
+        if self.max_value_bytes == 0 || self.max_value_bytes > MAX_VALUE_BYTES_LIMIT {
+            bail!(
+                "'state.maxValueBytes' функции '{function_name}' должен быть от 1 до {MAX_VALUE_BYTES_LIMIT}"
+            );
+        }
+        Ok(())
+    }
+
+    /// Serializes a value, refusing it when it exceeds the quota.
+    pub fn encode_value(&self, function_name: &str, value: &serde_json::Value) -> Result<String, StateError> {
+        let encoded = value.to_string();
+        if encoded.len() > self.max_value_bytes {
+            return Err(StateError::ValueTooLarge {
+                function: function_name.to_string(),
+                size: encoded.len(),
+                max: self.max_value_bytes,
+            });
+        }
+        Ok(encoded)
+    }
+}
+
+/// Keys are path segments of the state API, so `/` is not allowed; `:` is
+/// the conventional separator.
+pub fn validate_state_key(key: &str) -> Result<(), StateError> {
+    if key.is_empty()
+        || key.len() > MAX_KEY_LEN
+        || key.contains('/')
+        || key.chars().any(char::is_control)
+    {
+        return Err(StateError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{StateConfig, validate_state_key};
+
+    #[test]
+    fn keys_and_values_are_checked_against_quotas() {
+        assert!(validate_state_key("orders:42:status").is_ok());
+        assert!(validate_state_key("").is_err());
+        assert!(validate_state_key("orders/42").is_err());
+        assert!(validate_state_key("line\nbreak").is_err());
+        assert!(validate_state_key(&"k".repeat(257)).is_err());
+
+        let config: StateConfig = serde_json::from_value(json!({ "maxValueBytes": 8 })).unwrap();
+        assert_eq!(config.max_keys, 1000);
+        assert_eq!(config.encode_value("example", &json!("short")).unwrap(), "\"short\"");
+        assert!(config.encode_value("example", &json!("too long value")).is_err());
+        assert!(serde_json::from_value::<StateConfig>(json!({ "maxKeys": 0 }))
+            .unwrap()
+            .validate("example")
+            .is_err());
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::volume_error::VolumeError;
+
+/// Where the volume is mounted in every replica.
+pub const SHARED_DATA_PATH: &str = "/shared_data";
+pub const FUNCTION_LABEL: &str = "serverless.function";
+pub const RETENTION_LABEL: &str = "serverless.retention";
+const SNAPSHOTS_DIR: &str = "snapshots";
+const SNAPSHOT_EXTENSION: &str = "tar";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeRetention {
+    /// One volume for all versions, kept when the function is stopped.
+    Function,
+    /// A volume per version, kept when the function is stopped and when a
+    /// new version is deployed; old volumes are only removed by hand.
+    #[default]
+    Version,
+    /// A volume per version, kept when the function is stopped; volumes of
+    /// other versions are removed once a new version is deployed.
+    Latest,
+    /// A volume per version, removed when the function is stopped.
+    None,
+}
+
+impl VolumeRetention {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Function => "function",
+            Self::Version => "version",
+            Self::Latest => "latest",
+            Self::None => "none",
+        }
+    }
+
+    /// Whether a volume with this `serverless.retention` label goes away once
+    /// the function runs another version. Volumes without a known label are
+    /// kept.
+    pub fn removed_with_old_versions(label: Option<&str>) -> bool {
+        label == Some(Self::Latest.as_str()) || label == Some(Self::None.as_str())
+    }
+}
+
+/// Lifecycle of the `/shared_data` volume of a container function
+/// (`"volume"` in `function.json`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VolumeConfig {
+    #[serde(default)]
+    pub retention: VolumeRetention,
+}
+
+impl VolumeConfig {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Per-version volumes keep the `{name}-{version}` name they always had.
+    pub fn volume_name(&self, function_name: &str, version: &str) -> String {
+        match self.retention {
+            VolumeRetention::Function => format!("{function_name}-data"),
+            VolumeRetention::Version | VolumeRetention::Latest | VolumeRetention::None => {
+                format!("{function_name}-{version}")
+            }
+        }
+    }
+}
+
+/// A tarball of a volume's contents, as listed by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    #[serde(rename = "sizeBytes")]
+    pub size_bytes: u64,
+    #[serde(rename = "createdAtUnixMs")]
+    pub created_at_unix_ms: u64,
+}
+
+/// Volume snapshots on the platform host, `snapshots/{function}/{id}.tar`.
+/// They belong to the function rather than a version, so data can be
+/// restored into the volume of a newer version.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    root: PathBuf,
+}
+
+impl Default for SnapshotStore {
+    fn default() -> Self {
+        Self::new(SNAPSHOTS_DIR)
+    }
+}
+
+impl SnapshotStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Names come from request paths, so anything that could leave the
+    /// snapshot root is rejected before it is joined.
+    fn function_dir(&self, function_name: &str) -> Result<PathBuf, VolumeError> {
+        let valid_name = !function_name.starts_with('.')
+            && function_name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if function_name.is_empty() || !valid_name {
+            return Err(VolumeError::UnknownFunction(function_name.to_string()));
+        }
+        Ok(self.root.join(function_name))
+    }
+
+    /// Path for a new snapshot; ids are time-ordered.
+    pub async fn create_path(&self, function_name: &str) -> Result<(String, PathBuf)> {
+        let dir = self.function_dir(function_name)?;
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("Не удалось создать каталог снимков '{}'", dir.display()))?;
+        let id = uuid::Uuid::now_v7().simple().to_string();
+        let path = dir.join(format!("{id}.{SNAPSHOT_EXTENSION}"));
+        Ok((id, path))
+    }
+
+    /// Path of an existing snapshot.
+    pub async fn path(&self, function_name: &str, id: &str) -> Result<PathBuf, VolumeError> {
+        let valid_id = !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit());
+        let path = self
+            .function_dir(function_name)?
+            .join(format!("{id}.{SNAPSHOT_EXTENSION}"));
+        if !valid_id || !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Err(VolumeError::SnapshotNotFound(id.to_string()));
+        }
+        Ok(path)
+    }
+
+    pub async fn info(&self, id: &str, path: &Path) -> Result<SnapshotInfo> {
+        let metadata = tokio::fs::metadata(path).await?;
+        let created_at_unix_ms = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_millis() as u64)
+            .unwrap_or_default();
+        Ok(SnapshotInfo {
+            id: id.to_string(),
+            size_bytes: metadata.len(),
+            created_at_unix_ms,
+        })
+    }
+
+    /// Snapshots of a function, newest first.
+    pub async fn list(&self, function_name: &str) -> Result<Vec<SnapshotInfo>> {
+        let mut entries = match tokio::fs::read_dir(self.function_dir(function_name)?).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut snapshots = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SNAPSHOT_EXTENSION) {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            snapshots.push(self.info(id, &path).await?);
+        }
+        snapshots.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(snapshots)
+    }
+
+    pub async fn remove(&self, function_name: &str, id: &str) -> Result<()> {
+        let path = self.path(function_name, id).await?;
+        tokio::fs::remove_file(&path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::errors::volume_error::VolumeError;
+
+    use super::{SnapshotStore, VolumeConfig, VolumeRetention};
+
+    #[test]
+    fn retention_decides_whether_versions_share_a_volume() {
+        let default = VolumeConfig::default();
+        assert_eq!(default.retention, VolumeRetention::Version);
+        assert_eq!(default.volume_name("orders", "1.2"), "orders-1.2");
+
+        let shared: VolumeConfig = serde_json::from_value(json!({ "retention": "function" })).unwrap();
+        assert_eq!(shared.volume_name("orders", "1.2"), shared.volume_name("orders", "1.3"));
+        assert!(serde_json::from_value::<VolumeConfig>(json!({ "retention": "forever" })).is_err());
+
+        assert!(!VolumeRetention::removed_with_old_versions(Some(default.retention.as_str())));
+        assert!(!VolumeRetention::removed_with_old_versions(Some("function")));
+        assert!(!VolumeRetention::removed_with_old_versions(None));
+        let latest: VolumeConfig = serde_json::from_value(json!({ "retention": "latest" })).unwrap();
+        assert_eq!(latest.volume_name("orders", "1.2"), "orders-1.2");
+        assert!(VolumeRetention::removed_with_old_versions(Some(latest.retention.as_str())));
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_listed_newest_first_and_removed() {
+        let root = std::env::temp_dir().join(format!("snapshots-test-{}", uuid::Uuid::now_v7()));
+        let store = SnapshotStore::new(&root);
+        assert!(store.list("orders").await.unwrap().is_empty());
+
+        let (first, path) = store.create_path("orders").await.unwrap();
+        tokio::fs::write(&path, b"first").await.unwrap();
+        let (second, path) = store.create_path("orders").await.unwrap();
+        tokio::fs::write(&path, b"second!").await.unwrap();
+
+        let listed = store.list("orders").await.unwrap();
+        assert_eq!(
+            listed.iter().map(|snapshot| snapshot.id.as_str()).collect::<Vec<_>>(),
+            [second.as_str(), first.as_str()]
+        );
+        assert_eq!(listed[0].size_bytes, 7);
+
+        store.remove("orders", &first).await.unwrap();
+        assert!(matches!(
+            store.path("orders", &first).await,
+            Err(VolumeError::SnapshotNotFound(_))
+        ));
+        assert!(store.path("orders", "../orders").await.is_err());
+        for name in ["..", "../orders", "..%2F..", "orders/../..", ""] {
+            assert!(
+                matches!(store.path(name, &second).await, Err(VolumeError::UnknownFunction(_))),
+                "{name:?}"
+            );
+        }
+        assert!(store.list("../..").await.is_err());
+        assert!(store.remove("../orders", &second).await.is_err());
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+}
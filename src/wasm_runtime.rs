@@ -0,0 +1,495 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::{debug, info};
+use serde_json::{Value, json};
+use tokio::time::Duration;
+use wasmtime::{
+    Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    component::{self, Component, ResourceTable},
+};
+use wasmtime_wasi::{
+    I32Exit, IoView, WasiCtx, WasiCtxBuilder, WasiView,
+    bindings::sync::Command,
+    pipe::{MemoryInputPipe, MemoryOutputPipe},
+    preview1::{self, WasiP1Ctx},
+};
+
+use crate::function_manager::FunctionConfig;
+
+const MB_TO_BYTES: usize = 1024 * 1024;
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+const MAX_OUTPUT_BYTES: usize = 16 * MB_TO_BYTES;
+
+type ReplicaId = String;
+
+/// Compiled function code: a WASI preview1 core module exporting `_start`,
+/// or a component exporting `wasi:cli/run`.
+enum WasmCode {
+    Module(Module),
+    Component(Component),
+}
+
+/// Compiled code plus the limits every invocation is instantiated with.
+struct WasmFunction {
+    function_name: String,
+    version: String,
+    code: WasmCode,
+    memory_bytes: usize,
+    timeout: Duration,
+    env: HashMap<String, String>,
+}
+
+struct WasmState {
+    wasi: WasiP1Ctx,
+    limits: StoreLimits,
+}
+
+struct ComponentState {
+    wasi: WasiCtx,
+    table: ResourceTable,
+    limits: StoreLimits,
+}
+
+impl IoView for ComponentState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiView for ComponentState {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+pub struct WasmInvocation {
+    pub result: Value,
+    pub instantiate_ms: f64,
+}
+
+pub struct WasmRuntime {
+    engine: Engine,
+    linker: Arc<Linker<WasmState>>,
+    component_linker: Arc<component::Linker<ComponentState>>,
+    replicas: RwLock<HashMap<ReplicaId, Arc<WasmFunction>>>,
+    /// Code of the last deployment of each function, kept while it is scaled
+    /// to zero so that new replicas need no recompilation.
+    functions: RwLock<HashMap<String, Arc<WasmFunction>>>,
+}
+
+impl WasmRuntime {
+    pub fn new() -> Result<Self> {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config)?;
+
+        let mut linker = Linker::new(&engine);
+        preview1::add_to_linker_sync(&mut linker, |state: &mut WasmState| &mut state.wasi)?;
+        let mut component_linker = component::Linker::new(&engine);
+        wasmtime_wasi::add_to_linker_sync(&mut component_linker)?;
+
+        let ticker = engine.weak();
+        std::thread::Builder::new()
+            .name("wasm-epoch".to_string())
+            .spawn(move || {
+                while let Some(engine) = ticker.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    std::thread::sleep(EPOCH_TICK);
+                }
+            })?;
+
+        Ok(Self {
+            engine,
+            linker: Arc::new(linker),
+            component_linker: Arc::new(component_linker),
+            replicas: RwLock::new(HashMap::new()),
+            functions: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn replica_id(function_name: &str) -> ReplicaId {
+        format!("wasm-{}-{}", function_name, uuid::Uuid::now_v7())
+    }
+
+    pub fn is_wasm_replica(replica_id: &str) -> bool {
+        replica_id.starts_with("wasm-")
+    }
+
+    /// Compiles the function module or component once and registers `replicas` logical
+    /// replicas that share it, so balancers see the usual replica ids.
+    pub async fn deploy(&self, config: &FunctionConfig) -> Result<Vec<ReplicaId>> {
+        let module_path = config.build_context_path.join(config.wasm_module()?);
+        let bytes = tokio::fs::read(&module_path)
+            .await
+            .with_context(|| format!("Не удалось прочитать модуль '{}'", module_path.display()))?;
+
+        let engine = self.engine.clone();
+        let started = Instant::now();
+        let code = tokio::task::spawn_blocking(move || compile(&engine, &bytes))
+            .await?
+            .with_context(|| format!("Не удалось скомпилировать модуль '{}'", module_path.display()))?;
+        info!(
+            "Compiled wasm {} for '{}' in {:.2}ms",
+            code.kind(),
+            config.name,
+            started.elapsed().as_secs_f64() * 1000.0
+        );
+
+        let function = Arc::new(WasmFunction {
+            function_name: config.name.clone(),
+            version: config.version.clone(),
+            code,
+            memory_bytes: (config.memory.max(0) as usize) * MB_TO_BYTES,
+            timeout: Duration::from_secs(config.timeout.max(1) as u64),
+            env: config.env.clone(),
+        });
+
+        self.register(function, config.replicas)
+    }
+
+    fn register(&self, function: Arc<WasmFunction>, replicas: u16) -> Result<Vec<ReplicaId>> {
+        self.functions
+            .write()
+            .map_err(|_| anyhow!("wasm function registry lock poisoned"))?
+            .insert(function.function_name.clone(), Arc::clone(&function));
+        (0..replicas)
+            .map(|_| self.add_replica(&function.function_name))
+            .collect()
+    }
+
+    /// Registers one more replica of the last deployed code of a function.
+    pub fn add_replica(&self, function_name: &str) -> Result<ReplicaId> {
+        let function = self
+            .functions
+            .read()
+            .map_err(|_| anyhow!("wasm function registry lock poisoned"))?
+            .get(function_name)
+            .cloned()
+            .ok_or_else(|| anyhow!("Wasm function '{function_name}' is not deployed"))?;
+        let replica_id = Self::replica_id(function_name);
+        self.replicas
+            .write()
+            .map_err(|_| anyhow!("wasm replica registry lock poisoned"))?
+            .insert(replica_id.clone(), function);
+        Ok(replica_id)
+    }
+
+    pub fn remove_replica(&self, replica_id: &str) {
+        if let Ok(mut replicas) = self.replicas.write() {
+            replicas.remove(replica_id);
+        }
+    }
+
+    /// Instantiates a fresh module or component for this call, feeds `payload` on stdin and
+    /// parses stdout as the JSON response.
+    pub async fn invoke(&self, replica_id: &str, payload: &Value) -> Result<WasmInvocation> {
+        let function = self
+            .replicas
+            .read()
+            .map_err(|_| anyhow!("wasm replica registry lock poisoned"))?
+            .get(replica_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("Wasm replica '{replica_id}' not found"))?;
+        let input = serde_json::to_vec(payload)?;
+        let linker = Arc::clone(&self.linker);
+        let component_linker = Arc::clone(&self.component_linker);
+        let engine = self.engine.clone();
+
+        tokio::task::spawn_blocking(move || match &function.code {
+            WasmCode::Module(module) => run_instance(&engine, &linker, &function, module, input),
+            WasmCode::Component(component) => {
+                run_component(&engine, &component_linker, &function, component, input)
+            }
+        })
+        .await?
+    }
+}
+
+impl WasmCode {
+    fn kind(&self) -> &'static str {
+        match self {
+            WasmCode::Module(_) => "module",
+            WasmCode::Component(_) => "component",
+        }
+    }
+}
+
+/// Components and core modules share the `\0asm` magic; the layer field
+/// after the version tells them apart (0 for modules, 1 for components).
+fn compile(engine: &Engine, bytes: &[u8]) -> Result<WasmCode> {
+    if bytes.starts_with(b"\0asm") && bytes.get(6..8) == Some(&[1, 0]) {
+        Ok(WasmCode::Component(Component::new(engine, bytes)?))
+    } else {
+        Ok(WasmCode::Module(Module::new(engine, bytes)?))
+    }
+}
+
+fn wasi_context(function: &WasmFunction, input: Vec<u8>, stdout: &MemoryOutputPipe) -> WasiCtxBuilder {
+    let mut wasi = WasiCtxBuilder::new();
+    wasi.stdin(MemoryInputPipe::new(input))
+        .stdout(stdout.clone())
+        .inherit_stderr()
+        .env("FUNCTION_NAME", &function.function_name)
+        .env("FUNCTION_VERSION", &function.version);
+    for (key, value) in &function.env {
+        wasi.env(key, value);
+    }
+    wasi
+}
+
+fn store_limits(function: &WasmFunction) -> StoreLimits {
+    let mut limits = StoreLimitsBuilder::new();
+    if function.memory_bytes > 0 {
+        limits = limits.memory_size(function.memory_bytes);
+    }
+    limits.build()
+}
+
+fn epoch_deadline(function: &WasmFunction) -> u64 {
+    (function.timeout.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64
+}
+
+/// Maps a failed entry point call to the function's error: a zero exit code
+/// is a normal return, an epoch interrupt is a timeout.
+fn check_exit(function: &WasmFunction, error: anyhow::Error) -> Result<()> {
+    match error.downcast_ref::<I32Exit>() {
+        Some(I32Exit(0)) => Ok(()),
+        Some(I32Exit(code)) => bail!("wasm function exited with code {code}"),
+        None if error.downcast_ref::<wasmtime::Trap>() == Some(&wasmtime::Trap::Interrupt) => {
+            bail!(
+                "wasm function '{}' timed out after {}s",
+                function.function_name,
+                function.timeout.as_secs()
+            )
+        }
+        None => Err(error),
+    }
+}
+
+fn parse_output(stdout: &MemoryOutputPipe) -> Value {
+    let raw = stdout.contents();
+    let raw = String::from_utf8_lossy(&raw);
+    if raw.trim().is_empty() {
+        json!({ "raw": raw })
+    } else {
+        serde_json::from_str::<Value>(&raw).unwrap_or_else(|_| json!({ "raw": raw }))
+    }
+}
+
+fn run_instance(
+    engine: &Engine,
+    linker: &Linker<WasmState>,
+    function: &WasmFunction,
+    module: &Module,
+    input: Vec<u8>,
+) -> Result<WasmInvocation> {
+    let started = Instant::now();
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT_BYTES);
+    let mut store = Store::new(
+        engine,
+        WasmState {
+            wasi: wasi_context(function, input, &stdout).build_p1(),
+            limits: store_limits(function),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store.set_epoch_deadline(epoch_deadline(function));
+
+    let instance = linker.instantiate(&mut store, module)?;
+    let entry = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+    let instantiate_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let executed = Instant::now();
+    if let Err(error) = entry.call(&mut store, ()) {
+        check_exit(function, error)?;
+    }
+    let execute_ms = executed.elapsed().as_secs_f64() * 1000.0;
+    debug!(
+        "wasm '{}' instantiate={instantiate_ms:.3}ms execute={execute_ms:.3}ms",
+        function.function_name
+    );
+
+    Ok(WasmInvocation {
+        result: parse_output(&stdout),
+        instantiate_ms,
+    })
+}
+
+/// Same contract as [`run_instance`] for components: `wasi:cli/run` is called
+/// with the payload on stdin and stdout is parsed as the JSON response.
+fn run_component(
+    engine: &Engine,
+    linker: &component::Linker<ComponentState>,
+    function: &WasmFunction,
+    component: &Component,
+    input: Vec<u8>,
+) -> Result<WasmInvocation> {
+    let started = Instant::now();
+    let stdout = MemoryOutputPipe::new(MAX_OUTPUT_BYTES);
+    let mut store = Store::new(
+        engine,
+        ComponentState {
+            wasi: wasi_context(function, input, &stdout).build(),
+            table: ResourceTable::new(),
+            limits: store_limits(function),
+        },
+    );
+    store.limiter(|state| &mut state.limits);
+    store.set_epoch_deadline(epoch_deadline(function));
+
+    let command = Command::instantiate(&mut store, component, linker)?;
+    let instantiate_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+    let executed = Instant::now();
+    match command.wasi_cli_run().call_run(&mut store) {
+        Ok(Ok(())) => {}
+        Ok(Err(())) => bail!("wasm function '{}' returned an error", function.function_name),
+        Err(error) => check_exit(function, error)?,
+    }
+    let execute_ms = executed.elapsed().as_secs_f64() * 1000.0;
+    debug!(
+        "wasm component '{}' instantiate={instantiate_ms:.3}ms execute={execute_ms:.3}ms",
+        function.function_name
+    );
+
+    Ok(WasmInvocation {
+        result: parse_output(&stdout),
+        instantiate_ms,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use tokio::time::Duration;
+    use wasmtime::{Module, component::Component};
+
+    use super::{WasmCode, WasmFunction, WasmRuntime, compile, run_component, run_instance};
+
+    const ECHO_WAT: &str = r#"
+        (module
+          (import "wasi_snapshot_preview1" "fd_write"
+            (func $fd_write (param i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 16) "{\"ok\":true}")
+          (func (export "_start")
+            (i32.store (i32.const 0) (i32.const 16))
+            (i32.store (i32.const 4) (i32.const 11))
+            (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))))
+    "#;
+
+    const LOOP_WAT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "_start") (loop $spin (br $spin))))
+    "#;
+
+    const RUN_COMPONENT_WAT: &str = r#"
+        (component
+          (core module $m (func (export "run") (result i32) (i32.const 0)))
+          (core instance $i (instantiate $m))
+          (func $run (result (result)) (canon lift (core func $i "run")))
+          (instance $cli (export "run" (func $run)))
+          (export "wasi:cli/run@0.2.3" (instance $cli)))
+    "#;
+
+    const LOOP_COMPONENT_WAT: &str = r#"
+        (component
+          (core module $m (func (export "run") (result i32) (loop $spin (br $spin)) (i32.const 0)))
+          (core instance $i (instantiate $m))
+          (func $run (result (result)) (canon lift (core func $i "run")))
+          (instance $cli (export "run" (func $run)))
+          (export "wasi:cli/run@0.2.3" (instance $cli)))
+    "#;
+
+    fn function(runtime: &WasmRuntime, wat: &str, timeout: Duration) -> WasmFunction {
+        let code = if wat.trim_start().starts_with("(component") {
+            WasmCode::Component(Component::new(&runtime.engine, wat).expect("component should compile"))
+        } else {
+            WasmCode::Module(Module::new(&runtime.engine, wat).expect("module should compile"))
+        };
+        WasmFunction {
+            function_name: "wasm-test".to_string(),
+            version: "1.0".to_string(),
+            code,
+            memory_bytes: 16 * super::MB_TO_BYTES,
+            timeout,
+            env: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn wasm_function_returns_json_from_stdout() {
+        let runtime = WasmRuntime::new().expect("engine should start");
+        let function = function(&runtime, ECHO_WAT, Duration::from_secs(5));
+        let WasmCode::Module(module) = &function.code else {
+            panic!("expected a core module");
+        };
+
+        let invocation = run_instance(&runtime.engine, &runtime.linker, &function, module, b"{}".to_vec())
+            .expect("invocation should succeed");
+        assert_eq!(invocation.result["ok"], true);
+    }
+
+    #[test]
+    fn wasm_function_is_interrupted_after_timeout() {
+        let runtime = WasmRuntime::new().expect("engine should start");
+        let function = function(&runtime, LOOP_WAT, Duration::from_millis(50));
+        let WasmCode::Module(module) = &function.code else {
+            panic!("expected a core module");
+        };
+
+        let error = run_instance(&runtime.engine, &runtime.linker, &function, module, Vec::new())
+            .err()
+            .expect("endless loop should be interrupted");
+        assert!(error.to_string().contains("timed out"), "{error}");
+    }
+
+    #[test]
+    fn components_are_detected_by_their_header() {
+        let runtime = WasmRuntime::new().expect("engine should start");
+        let empty_component = b"\0asm\x0d\x00\x01\x00";
+        let empty_module = b"\0asm\x01\x00\x00\x00";
+        assert!(matches!(compile(&runtime.engine, empty_component), Ok(WasmCode::Component(_))));
+        assert!(matches!(compile(&runtime.engine, empty_module), Ok(WasmCode::Module(_))));
+    }
+
+    #[test]
+    fn wasm_component_runs_and_times_out() {
+        let runtime = WasmRuntime::new().expect("engine should start");
+        let linker = Arc::clone(&runtime.component_linker);
+        let run = |wat: &str, timeout: Duration| {
+            let function = function(&runtime, wat, timeout);
+            let WasmCode::Component(component) = &function.code else {
+                panic!("expected a component");
+            };
+            run_component(&runtime.engine, &linker, &function, component, b"{}".to_vec())
+        };
+
+        let invocation = run(RUN_COMPONENT_WAT, Duration::from_secs(5)).expect("component should run");
+        assert_eq!(invocation.result["raw"], "");
+        let error = run(LOOP_COMPONENT_WAT, Duration::from_millis(50))
+            .err()
+            .expect("endless loop should be interrupted");
+        assert!(error.to_string().contains("timed out"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn functions_scaled_to_zero_can_add_replicas() {
+        let runtime = WasmRuntime::new().expect("engine should start");
+        let function = Arc::new(function(&runtime, ECHO_WAT, Duration::from_secs(5)));
+        assert!(runtime.register(function, 0).expect("register").is_empty());
+
+        let replica_id = runtime.add_replica("wasm-test").expect("replica should be added");
+        let invocation = runtime.invoke(&replica_id, &serde_json::json!({})).await.expect("invoke");
+        assert_eq!(invocation.result["ok"], true);
+        assert!(runtime.add_replica("unknown").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::workflow_error::WorkflowError;
+
+use super::json_path::JsonPath;
+
+const WORKFLOWS_DIR: &str = "workflows";
+const MAX_RETRY_ATTEMPTS: u32 = 10;
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_interval_ms() -> u64 {
+    1000
+}
+
+fn default_backoff_rate() -> f64 {
+    2.0
+}
+
+fn default_error_path() -> JsonPath {
+    JsonPath::parse("$.error").expect("static path is valid")
+}
+
+fn default_max_concurrency() -> usize {
+    10
+}
+
+/// A pipeline of function calls, read from `workflows/{name}.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowDefinition {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub steps: Vec<Step>,
+}
+
+/// One step. Its input is `inputPath` of the current data, its result is
+/// placed at `resultPath`, and `outputPath` of the combined data is passed on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Step {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: StepKind,
+    #[serde(default, rename = "inputPath")]
+    pub input_path: JsonPath,
+    #[serde(default, rename = "resultPath")]
+    pub result_path: JsonPath,
+    #[serde(default, rename = "outputPath")]
+    pub output_path: JsonPath,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub catch: Option<CatchPolicy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StepKind {
+    /// Invokes a function with the step input as payload.
+    Task { function: String },
+    /// Runs every branch on the step input concurrently; the result is the
+    /// array of branch outputs.
+    Parallel { branches: Vec<Vec<Step>> },
+    /// Runs `steps` on every element of the array at `itemsPath`; the result
+    /// is the array of outputs in item order.
+    Map {
+        #[serde(rename = "itemsPath")]
+        items_path: JsonPath,
+        steps: Vec<Step>,
+        #[serde(default = "default_max_concurrency", rename = "maxConcurrency")]
+        max_concurrency: usize,
+    },
+}
+
+impl StepKind {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Task { .. } => "task",
+            Self::Parallel { .. } => "parallel",
+            Self::Map { .. } => "map",
+        }
+    }
+}
+
+/// Attempts in total, the first one included, with exponential backoff.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    #[serde(default = "default_max_attempts", rename = "maxAttempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_interval_ms", rename = "intervalMs")]
+    pub interval_ms: u64,
+    #[serde(default = "default_backoff_rate", rename = "backoffRate")]
+    pub backoff_rate: f64,
+}
+
+/// Turns a failed step into data: `{ "step", "error" }` is placed at
+/// `resultPath` and the workflow continues with the next step.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatchPolicy {
+    #[serde(default = "default_error_path", rename = "resultPath")]
+    pub result_path: JsonPath,
+}
+
+impl WorkflowDefinition {
+    pub async fn load(name: &str) -> Result<Self> {
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(WorkflowError::NotFound(name.to_string()).into());
+        }
+        let path = PathBuf::from(WORKFLOWS_DIR).join(format!("{name}.json"));
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(WorkflowError::NotFound(name.to_string()).into());
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Не удалось прочитать '{}'", path.display()));
+            }
+        };
+        let mut definition: Self = serde_json::from_str(&content)
+            .map_err(|e| WorkflowError::InvalidDefinition(name.to_string(), e.to_string()))?;
+        definition.name = name.to_string();
+        definition.validate()?;
+        Ok(definition)
+    }
+
+    pub fn validate(&self) -> Result<(), WorkflowError> {
+        validate_steps(&self.name, &self.steps)
+    }
+
+    /// Functions invoked by the steps, nested ones included.
+    pub fn functions(&self) -> BTreeSet<&str> {
+        let mut functions = BTreeSet::new();
+        collect_functions(&self.steps, &mut functions);
+        functions
+    }
+}
+
+fn collect_functions<'a>(steps: &'a [Step], functions: &mut BTreeSet<&'a str>) {
+    for step in steps {
+        match &step.kind {
+            StepKind::Task { function } => {
+                functions.insert(function);
+            }
+            StepKind::Parallel { branches } => {
+                for branch in branches {
+                    collect_functions(branch, functions);
+                }
+            }
+            StepKind::Map { steps, .. } => collect_functions(steps, functions),
+        }
+    }
+}
+
+fn validate_steps(workflow: &str, steps: &[Step]) -> Result<(), WorkflowError> {
+    let invalid = |message: String| WorkflowError::InvalidDefinition(workflow.to_string(), message);
+    if steps.is_empty() {
+        return Err(invalid("список шагов пуст".to_string()));
+    }
+    let mut names = HashSet::new();
+    for step in steps {
+        if step.name.is_empty() || step.name.contains(['/', '[', ']']) {
+            return Err(invalid(format!("некорректное имя шага '{}'", step.name)));
+        }
+        if !names.insert(step.name.as_str()) {
+            return Err(invalid(format!("имя шага '{}' повторяется", step.name)));
+        }
+        if let Some(retry) = &step.retry
+            && (retry.max_attempts == 0
+                || retry.max_attempts > MAX_RETRY_ATTEMPTS
+                || !retry.backoff_rate.is_finite()
+                || retry.backoff_rate < 1.0)
+        {
+            return Err(invalid(format!(
+                "у шага '{}' 'maxAttempts' должен быть от 1 до {MAX_RETRY_ATTEMPTS}, а 'backoffRate' не меньше 1",
+                step.name
+            )));
+        }
+        match &step.kind {
+            StepKind::Task { function } if function.is_empty() => {
+                return Err(invalid(format!("у шага '{}' не задана функция", step.name)));
+            }
+            StepKind::Task { .. } => {}
+            StepKind::Parallel { branches } => {
+                if branches.is_empty() {
+                    return Err(invalid(format!("у шага '{}' нет веток", step.name)));
+                }
+                for branch in branches {
+                    validate_steps(workflow, branch)?;
+                }
+            }
+            StepKind::Map {
+                steps,
+                max_concurrency,
+                ..
+            } => {
+                if *max_concurrency == 0 {
+                    return Err(invalid(format!("у шага '{}' 'maxConcurrency' должен быть больше 0", step.name)));
+                }
+                validate_steps(workflow, steps)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{StepKind, WorkflowDefinition};
+
+    fn definition(value: serde_json::Value) -> WorkflowDefinition {
+        serde_json::from_value(value).expect("definition should parse")
+    }
+
+    #[test]
+    fn definitions_parse_all_step_types() {
+        let workflow = definition(json!({
+            "steps": [
+                { "name": "validate", "type": "task", "function": "validate", "resultPath": "$.validation",
+                  "retry": { "maxAttempts": 2 }, "catch": {} },
+                { "name": "enrich", "type": "parallel", "branches": [
+                    [{ "name": "price", "type": "task", "function": "price" }],
+                    [{ "name": "stock", "type": "task", "function": "stock" }]
+                ] },
+                { "name": "resize", "type": "map", "itemsPath": "$.images",
+                  "steps": [{ "name": "thumb", "type": "task", "function": "thumb" }] }
+            ]
+        }));
+        workflow.validate().expect("definition should be valid");
+        assert_eq!(workflow.steps[0].result_path.to_string(), "$.validation");
+        assert_eq!(workflow.steps[0].catch.as_ref().unwrap().result_path.to_string(), "$.error");
+        assert!(matches!(
+            workflow.steps[2].kind,
+            StepKind::Map { max_concurrency: 10, .. }
+        ));
+        assert_eq!(
+            workflow.functions().into_iter().collect::<Vec<_>>(),
+            ["price", "stock", "thumb", "validate"]
+        );
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        for invalid in [
+            json!({ "steps": [] }),
+            json!({ "steps": [
+                { "name": "a", "type": "task", "function": "f" },
+                { "name": "a", "type": "task", "function": "g" }
+            ] }),
+            json!({ "steps": [{ "name": "a", "type": "parallel", "branches": [] }] }),
+            json!({ "steps": [{ "name": "a", "type": "task", "function": "f", "retry": { "maxAttempts": 0 } }] }),
+        ] {
+            assert!(definition(invalid.clone()).validate().is_err(), "{invalid}");
+        }
+        assert!(serde_json::from_value::<WorkflowDefinition>(json!({
+            "steps": [{ "name": "a", "type": "task", "function": "f", "inputPath": "order" }]
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow};
+use futures_util::{
+    StreamExt,
+    future::{BoxFuture, join_all},
+    stream,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    AppState,
+    errors::limit_error::LimitError,
+    invoke_chain::CallChain,
+    limits::{InvokeLimits, LimitScope, admit},
+};
+
+use super::definition::{Step, StepKind, WorkflowDefinition};
+
+fn unix_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Running,
+    Succeeded,
+    Failed,
+    /// Failed, with the error handed on by `catch`.
+    Caught,
+}
+
+/// Progress of one step. Steps inside `parallel` and `map` are recorded
+/// under `{step}[{branch or item}]/{inner step}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub step_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+    pub status: StepStatus,
+    pub attempts: u32,
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(rename = "startedAtUnixMs")]
+    pub started_at_unix_ms: u128,
+    #[serde(default, rename = "finishedAtUnixMs", skip_serializing_if = "Option::is_none")]
+    pub finished_at_unix_ms: Option<u128>,
+}
+
+/// The API key an execution was started with. Its limits apply to every
+/// function the execution calls, also after a resume.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionCaller {
+    #[serde(rename = "keyId")]
+    pub key_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<InvokeLimits>,
+}
+
+/// Persisted state of an execution. `data` and `nextStep` are the checkpoint
+/// an execution resumes from after a restart; the definition is kept so a
+/// resumed execution is not affected by later edits of the workflow file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowExecution {
+    pub id: String,
+    pub workflow: String,
+    pub status: ExecutionStatus,
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(rename = "startedAtUnixMs")]
+    pub started_at_unix_ms: u128,
+    #[serde(default, rename = "finishedAtUnixMs", skip_serializing_if = "Option::is_none")]
+    pub finished_at_unix_ms: Option<u128>,
+    /// Index of the next top-level step to run.
+    #[serde(rename = "nextStep")]
+    pub next_step: usize,
+    pub data: Value,
+    pub steps: Vec<StepRecord>,
+    pub definition: WorkflowDefinition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<ExecutionCaller>,
+}
+
+impl WorkflowExecution {
+    pub fn new(definition: WorkflowDefinition, input: Value, caller: Option<ExecutionCaller>) -> Self {
+        Self {
+            id: uuid::Uuid::now_v7().simple().to_string(),
+            workflow: definition.name.clone(),
+            status: ExecutionStatus::Running,
+            data: input.clone(),
+            input,
+            output: None,
+            error: None,
+            started_at_unix_ms: unix_ms(),
+            finished_at_unix_ms: None,
+            next_step: 0,
+            steps: Vec::new(),
+            definition,
+            caller,
+        }
+    }
+}
+
+/// What the engine needs from the platform: calling functions and
+/// persisting executions.
+pub trait WorkflowBackend: Send + Sync {
+    /// Calls a function within the limits of the function and of the
+    /// execution's caller; an exceeded limit fails with [`LimitError`].
+    fn invoke<'a>(
+        &'a self,
+        function_name: &'a str,
+        input: Value,
+        chain: &'a CallChain,
+        caller: Option<&'a ExecutionCaller>,
+    ) -> BoxFuture<'a, Result<Value>>;
+
+    fn save(&self, execution: &WorkflowExecution) -> Result<()>;
+}
+
+impl WorkflowBackend for AppState {
+    fn invoke<'a>(
+        &'a self,
+        function_name: &'a str,
+        input: Value,
+        chain: &'a CallChain,
+        caller: Option<&'a ExecutionCaller>,
+    ) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(async move {
+            let mut limit_scopes = Vec::new();
+            if let Some(limits) = self.function_manager.invoke_limits(function_name).await {
+                limit_scopes.push(LimitScope {
+                    key: format!("function:{function_name}"),
+                    limits,
+                });
+            }
+            if let Some(caller) = caller
+                && let Some(limits) = &caller.limits
+            {
+                limit_scopes.push(LimitScope {
+                    key: format!("apikey:{}", caller.key_id),
+                    limits: limits.clone(),
+                });
+            }
+            let _permit = admit(&self.redis_manager, &limit_scopes)?;
+            let outcome = self
+                .function_manager
+                .try_invoke_with_meta(function_name, input, &[], chain)
+                .await?;
+            Ok(outcome.result)
+        })
+    }
+
+    fn save(&self, execution: &WorkflowExecution) -> Result<()> {
+        self.redis_manager.save_workflow_execution(
+            &execution.id,
+            &execution.workflow,
+            execution.started_at_unix_ms,
+            execution.status == ExecutionStatus::Running,
+            &serde_json::to_string(execution)?,
+        )
+    }
+}
+
+/// Persists a new execution and runs it in the background.
+pub fn start_execution(
+    state: Arc<AppState>,
+    definition: WorkflowDefinition,
+    input: Value,
+    caller: Option<ExecutionCaller>,
+) -> Result<WorkflowExecution> {
+    let execution = WorkflowExecution::new(definition, input, caller);
+    state.save(&execution)?;
+    info!("Starting execution '{}' of workflow '{}'", execution.id, execution.workflow);
+    tokio::spawn(run(state, execution.clone()));
+    Ok(execution)
+}
+
+/// Picks up executions left running by a previous server process. Each
+/// continues from its last completed top-level step; a `parallel` or `map`
+/// step that was in progress is run again as a whole.
+pub async fn resume_executions(state: Arc<AppState>) {
+    let ids = match state.redis_manager.running_workflow_executions() {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!("Failed to list running workflow executions: {e}");
+            return;
+        }
+    };
+    for id in ids {
+        let execution = state
+            .redis_manager
+            .get_workflow_execution(&id)
+            .and_then(|record| {
+                let record = record.ok_or_else(|| anyhow!("record is missing"))?;
+                Ok(serde_json::from_str::<WorkflowExecution>(&record)?)
+            });
+        match execution {
+            Ok(execution) => {
+                info!(
+                    "Resuming execution '{id}' of workflow '{}' at step {}",
+                    execution.workflow, execution.next_step
+                );
+                tokio::spawn(run(Arc::clone(&state), execution));
+            }
+            Err(e) => error!("Failed to resume workflow execution '{id}': {e}"),
+        }
+    }
+}
+
+/// Runs the remaining steps of an execution and returns its final state.
+pub async fn run<B: WorkflowBackend + ?Sized>(backend: Arc<B>, execution: WorkflowExecution) -> WorkflowExecution {
+    let steps = execution.definition.steps.clone();
+    let start = execution.next_step;
+    let mut data = execution.data.clone();
+    let runner = Runner {
+        backend,
+        caller: execution.caller.clone(),
+        chain: CallChain {
+            id: execution.id.clone(),
+            depth: 0,
+            caller: None,
+        },
+        execution: Mutex::new(execution),
+    };
+
+    for (index, step) in steps.iter().enumerate().skip(start) {
+        match runner.step(step, String::new(), data.clone()).await {
+            Ok(next) => {
+                data = next;
+                runner.update(|execution| {
+                    execution.data = data.clone();
+                    execution.next_step = index + 1;
+                });
+            }
+            Err(e) => {
+                runner.update(|execution| {
+                    execution.status = ExecutionStatus::Failed;
+                    execution.error = Some(format!("{e:#}"));
+                    execution.finished_at_unix_ms = Some(unix_ms());
+                });
+                let execution = runner.execution().clone();
+                warn!("Execution '{}' of workflow '{}' failed: {e:#}", execution.id, execution.workflow);
+                return execution;
+            }
+        }
+    }
+
+    runner.update(|execution| {
+        execution.status = ExecutionStatus::Succeeded;
+        execution.output = Some(data);
+        execution.finished_at_unix_ms = Some(unix_ms());
+    });
+    let execution = runner.execution().clone();
+    info!("Execution '{}' of workflow '{}' succeeded", execution.id, execution.workflow);
+    execution
+}
+
+struct Runner<B: ?Sized> {
+    backend: Arc<B>,
+    /// Functions of one execution share a call chain named after it.
+    chain: CallChain,
+    caller: Option<ExecutionCaller>,
+    execution: Mutex<WorkflowExecution>,
+}
+
+impl<B: WorkflowBackend + ?Sized> Runner<B> {
+    fn execution(&self) -> MutexGuard<'_, WorkflowExecution> {
+        self.execution.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Applies a change and persists the execution. A failed save is only
+    /// logged: the execution goes on and the next save catches up.
+    fn update(&self, apply: impl FnOnce(&mut WorkflowExecution)) {
+        let mut execution = self.execution();
+        apply(&mut execution);
+        if let Err(e) = self.backend.save(&execution) {
+            warn!("Failed to persist workflow execution '{}': {e}", execution.id);
+        }
+    }
+
+    fn update_step(&self, path: &str, apply: impl FnOnce(&mut StepRecord)) {
+        self.update(|execution| {
+            if let Some(record) = execution.steps.iter_mut().find(|record| record.path == path) {
+                apply(record);
+            }
+        });
+    }
+
+    fn sequence<'a>(&'a self, steps: &'a [Step], prefix: String, data: Value) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(async move {
+            let mut data = data;
+            for step in steps {
+                data = self.step(step, prefix.clone(), data).await?;
+            }
+            Ok(data)
+        })
+    }
+
+    fn step<'a>(&'a self, step: &'a Step, prefix: String, data: Value) -> BoxFuture<'a, Result<Value>> {
+        Box::pin(async move {
+            let path = format!("{prefix}{}", step.name);
+            let input = step.input_path.get(&data).cloned();
+            let record = StepRecord {
+                path: path.clone(),
+                step_type: step.kind.type_name().to_string(),
+                function: match &step.kind {
+                    StepKind::Task { function } => Some(function.clone()),
+                    _ => None,
+                },
+                status: StepStatus::Running,
+                attempts: 0,
+                input: input.as_ref().cloned().unwrap_or(Value::Null),
+                output: None,
+                error: None,
+                started_at_unix_ms: unix_ms(),
+                finished_at_unix_ms: None,
+            };
+            self.update(|execution| {
+                execution.steps.retain(|existing| existing.path != path);
+                execution.steps.push(record);
+            });
+
+            let result = match input {
+                Ok(input) => self.attempts(step, &path, input).await,
+                Err(e) => Err(e),
+            };
+            let result = result.and_then(|result| {
+                let combined = step.result_path.set(data.clone(), result.clone())?;
+                Ok((result, step.output_path.get(&combined)?.clone()))
+            });
+
+            match result {
+                Ok((result, output)) => {
+                    self.update_step(&path, |record| {
+                        record.status = StepStatus::Succeeded;
+                        record.output = Some(result);
+                        record.finished_at_unix_ms = Some(unix_ms());
+                    });
+                    Ok(output)
+                }
+                Err(e) => {
+                    let message = format!("{e:#}");
+                    let status = if step.catch.is_some() {
+                        StepStatus::Caught
+                    } else {
+                        StepStatus::Failed
+                    };
+                    self.update_step(&path, |record| {
+                        record.status = status;
+                        record.error = Some(message.clone());
+                        record.finished_at_unix_ms = Some(unix_ms());
+                    });
+                    match &step.catch {
+                        Some(catch) => catch
+                            .result_path
+                            .set(data, serde_json::json!({ "step": path, "error": message })),
+                        None => Err(e.context(format!("Шаг '{path}' завершился ошибкой"))),
+                    }
+                }
+            }
+        })
+    }
+
+    async fn attempts(&self, step: &Step, path: &str, input: Value) -> Result<Value> {
+        let (max_attempts, mut delay, backoff_rate) = match &step.retry {
+            Some(retry) => (
+                retry.max_attempts,
+                Duration::from_millis(retry.interval_ms),
+                retry.backoff_rate,
+            ),
+            None => (1, Duration::ZERO, 1.0),
+        };
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            self.update_step(path, |record| record.attempts = attempt);
+            match self.execute(step, path, input.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(e) if attempt < max_attempts => {
+                    // A limited call is not retried before the limit frees up.
+                    let wait = match e.downcast_ref::<LimitError>() {
+                        Some(limited) => delay.max(Duration::from_secs(limited.retry_after_secs())),
+                        None => delay,
+                    };
+                    warn!("Step '{path}' of execution '{}' failed, retrying in {wait:?}: {e:#}", self.chain.id);
+                    tokio::time::sleep(wait).await;
+                    delay = delay.mul_f64(backoff_rate);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn execute(&self, step: &Step, path: &str, input: Value) -> Result<Value> {
+        match &step.kind {
+            StepKind::Task { function } => self.backend.invoke(function, input, &self.chain, self.caller.as_ref()).await,
+            StepKind::Parallel { branches } => {
+                let outputs = join_all(branches.iter().enumerate().map(|(index, branch)| {
+                    self.sequence(branch, format!("{path}[{index}]/"), input.clone())
+                }))
+                .await;
+                Ok(Value::Array(outputs.into_iter().collect::<Result<_>>()?))
+            }
+            StepKind::Map {
+                items_path,
+                steps,
+                max_concurrency,
+            } => {
+                let items = items_path
+                    .get(&input)?
+                    .as_array()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("'{items_path}' шага '{path}' не является массивом"))?;
+                let outputs = stream::iter(items.into_iter().enumerate().map(|(index, item)| {
+                    self.sequence(steps, format!("{path}[{index}]/"), item)
+                }))
+                .buffered(*max_concurrency)
+                .collect::<Vec<_>>()
+                .await;
+                Ok(Value::Array(outputs.into_iter().collect::<Result<_>>()?))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use anyhow::{Result, bail};
+    use futures_util::future::BoxFuture;
+    use serde_json::{Value, json};
+
+    use crate::{
+        errors::limit_error::LimitError, invoke_chain::CallChain, workflows::definition::WorkflowDefinition,
+    };
+
+    use super::{ExecutionCaller, ExecutionStatus, StepStatus, WorkflowBackend, WorkflowExecution, run};
+
+    #[derive(Default)]
+    struct FakeBackend {
+        flaky_calls: AtomicU32,
+        limited_calls: AtomicU32,
+        callers: Mutex<Vec<Option<String>>>,
+        saved: Mutex<Vec<ExecutionStatus>>,
+    }
+
+    impl WorkflowBackend for FakeBackend {
+        fn invoke<'a>(
+            &'a self,
+            function_name: &'a str,
+            input: Value,
+            _chain: &'a CallChain,
+            caller: Option<&'a ExecutionCaller>,
+        ) -> BoxFuture<'a, Result<Value>> {
+            Box::pin(async move {
+                let key_id = caller.map(|caller| caller.key_id.clone());
+                self.callers.lock().unwrap().push(key_id);
+                match function_name {
+                    "add-one" => Ok(json!(input.as_i64().unwrap_or_default() + 1)),
+                    "flaky" if self.flaky_calls.fetch_add(1, Ordering::SeqCst) < 2 => bail!("not yet"),
+                    "flaky" => Ok(json!("ok")),
+                    "limited" if self.limited_calls.fetch_add(1, Ordering::SeqCst) == 0 => {
+                        Err(LimitError::RateLimited {
+                            scope: "apikey:k1".to_string(),
+                            retry_after_secs: 1,
+                        }
+                        .into())
+                    }
+                    "limited" => Ok(json!("admitted")),
+                    _ => bail!("function '{function_name}' failed"),
+                }
+            })
+        }
+
+        fn save(&self, execution: &WorkflowExecution) -> Result<()> {
+            self.saved.lock().unwrap().push(execution.status);
+            Ok(())
+        }
+    }
+
+    fn execution(definition: Value, input: Value) -> WorkflowExecution {
+        let mut definition: WorkflowDefinition = serde_json::from_value(definition).unwrap();
+        definition.name = "test".to_string();
+        definition.validate().unwrap();
+        WorkflowExecution::new(definition, input, None)
+    }
+
+    #[tokio::test]
+    async fn runs_sequences_parallel_branches_and_maps() {
+        let backend = Arc::new(FakeBackend::default());
+        let execution = execution(
+            json!({ "steps": [
+                { "name": "inc", "type": "task", "function": "add-one", "inputPath": "$.n", "resultPath": "$.n" },
+                { "name": "fan", "type": "parallel", "inputPath": "$.n", "resultPath": "$.branches", "branches": [
+                    [{ "name": "a", "type": "task", "function": "add-one" }],
+                    [{ "name": "b", "type": "task", "function": "add-one" },
+                     { "name": "c", "type": "task", "function": "add-one" }]
+                ] },
+                { "name": "each", "type": "map", "itemsPath": "$.items", "resultPath": "$.mapped",
+                  "maxConcurrency": 2, "steps": [{ "name": "inc", "type": "task", "function": "add-one" }] },
+                { "name": "pick", "type": "task", "function": "add-one", "inputPath": "$.n",
+                  "resultPath": "$.last", "outputPath": "$.mapped" }
+            ] }),
+            json!({ "n": 1, "items": [10, 20, 30] }),
+        );
+
+        let finished = run(Arc::clone(&backend), execution).await;
+        assert_eq!(finished.status, ExecutionStatus::Succeeded, "{:?}", finished.error);
+        assert_eq!(finished.output, Some(json!([11, 21, 31])));
+        assert_eq!(finished.data, json!([11, 21, 31]));
+        let paths = finished.steps.iter().map(|step| step.path.as_str()).collect::<Vec<_>>();
+        assert!(paths.contains(&"fan[1]/c"), "{paths:?}");
+        assert!(paths.contains(&"each[2]/inc"), "{paths:?}");
+        let fan = finished.steps.iter().find(|step| step.path == "fan").unwrap();
+        assert_eq!(fan.output, Some(json!([3, 4])));
+        assert_eq!(backend.saved.lock().unwrap().last(), Some(&ExecutionStatus::Succeeded));
+    }
+
+    #[tokio::test]
+    async fn retries_catches_and_resumes_from_the_checkpoint() {
+        let backend = Arc::new(FakeBackend::default());
+        let definition = json!({ "steps": [
+            { "name": "flaky", "type": "task", "function": "flaky", "resultPath": "$.flaky",
+              "retry": { "maxAttempts": 3, "intervalMs": 1 } },
+            { "name": "broken", "type": "task", "function": "broken", "resultPath": "$.broken",
+              "catch": { "resultPath": "$.failure" } },
+            { "name": "fatal", "type": "task", "function": "broken" }
+        ] });
+
+        let finished = run(Arc::clone(&backend), execution(definition.clone(), json!({}))).await;
+        assert_eq!(finished.status, ExecutionStatus::Failed);
+        assert_eq!(finished.next_step, 2);
+        assert_eq!(finished.steps[0].attempts, 3);
+        assert_eq!(finished.data["flaky"], "ok");
+        assert_eq!(finished.data["failure"]["step"], "broken");
+        assert_eq!(finished.steps[1].status, StepStatus::Caught);
+        assert!(finished.error.unwrap().contains("fatal"));
+
+        let mut resumed = execution(definition, json!({}));
+        resumed.next_step = 2;
+        resumed.data = json!({ "resumed": true });
+        let finished = run(backend, resumed).await;
+        assert_eq!(finished.steps.len(), 1);
+        assert_eq!(finished.steps[0].path, "fatal");
+    }
+
+    #[tokio::test]
+    async fn limited_tasks_wait_for_the_limit_before_a_retry() {
+        let backend = Arc::new(FakeBackend::default());
+        let mut execution = execution(
+            json!({ "steps": [
+                { "name": "limited", "type": "task", "function": "limited",
+                  "retry": { "maxAttempts": 2, "intervalMs": 1 } }
+            ] }),
+            json!({}),
+        );
+        execution.caller = Some(ExecutionCaller {
+            key_id: "k1".to_string(),
+            limits: None,
+        });
+        let resumed: WorkflowExecution = serde_json::from_value(serde_json::to_value(&execution).unwrap()).unwrap();
+
+        let started = std::time::Instant::now();
+        let finished = run(Arc::clone(&backend), resumed).await;
+        assert_eq!(finished.status, ExecutionStatus::Succeeded, "{:?}", finished.error);
+        assert_eq!(finished.output, Some(json!("admitted")));
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+        assert_eq!(*backend.callers.lock().unwrap(), vec![Some("k1".to_string()); 2]);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt::Display;
+
+use anyhow::{Result, anyhow, bail};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Field(String),
+    Index(usize),
+}
+
+/// The subset of JSONPath workflows use to pick and place data: `$`, fields
+/// (`$.order.items`) and array indexes (`$.items[0]`). The default is `$`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        let invalid = || anyhow!("Некорректный JSON path '{path}'");
+        let mut rest = path.trim().strip_prefix('$').ok_or_else(invalid)?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                    .unwrap_or(after_dot.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Field(after_dot[..end].to_string()));
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let (index, after) = after_bracket.split_once(']').ok_or_else(invalid)?;
+                segments.push(Segment::Index(index.parse().map_err(|_| invalid())?));
+                rest = after;
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Self(segments))
+    }
+
+    pub fn get<'a>(&self, value: &'a Value) -> Result<&'a Value> {
+        let mut current = value;
+        for segment in &self.0 {
+            current = match segment {
+                Segment::Field(name) => current.get(name),
+                Segment::Index(index) => current.get(index),
+            }
+            .ok_or_else(|| anyhow!("Путь '{self}' не найден в данных"))?;
+        }
+        Ok(current)
+    }
+
+    /// Places `value` at the path inside `target`, creating missing objects
+    /// on the way; the root path replaces `target` altogether.
+    pub fn set(&self, target: Value, value: Value) -> Result<Value> {
+        let Some((last, parents)) = self.0.split_last() else {
+            return Ok(value);
+        };
+        let mut target = target;
+        let mut current = &mut target;
+        for segment in parents {
+            current = child_mut(current, segment, self)?;
+        }
+        match last {
+            Segment::Field(name) => {
+                if current.is_null() {
+                    *current = Value::Object(Map::new());
+                }
+                let Value::Object(object) = current else {
+                    bail!("Нельзя записать '{self}': родитель не является объектом");
+                };
+                object.insert(name.clone(), value);
+            }
+            Segment::Index(index) => {
+                let slot = current
+                    .get_mut(*index)
+                    .ok_or_else(|| anyhow!("Нельзя записать '{self}': индекс вне массива"))?;
+                *slot = value;
+            }
+        }
+        Ok(target)
+    }
+}
+
+fn child_mut<'a>(current: &'a mut Value, segment: &Segment, path: &JsonPath) -> Result<&'a mut Value> {
+    match segment {
+        Segment::Field(name) => {
+            if current.is_null() {
+                *current = Value::Object(Map::new());
+            }
+            let Value::Object(object) = current else {
+                bail!("Нельзя записать '{path}': '{name}' лежит не в объекте");
+            };
+            Ok(object.entry(name.clone()).or_insert(Value::Null))
+        }
+        Segment::Index(index) => current
+            .get_mut(*index)
+            .ok_or_else(|| anyhow!("Нельзя записать '{path}': индекс {index} вне массива")),
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$")?;
+        for segment in &self.0 {
+            match segment {
+                Segment::Field(name) => write!(f, ".{name}")?,
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for JsonPath {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for JsonPath {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        Self::parse(&path).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::JsonPath;
+
+    #[test]
+    fn paths_read_and_write_nested_values() {
+        let data = json!({ "order": { "items": [{ "sku": "a" }, { "sku": "b" }] } });
+        let sku = JsonPath::parse("$.order.items[1].sku").unwrap();
+        assert_eq!(sku.get(&data).unwrap(), "b");
+        assert_eq!(sku.to_string(), "$.order.items[1].sku");
+        assert!(JsonPath::parse("$.order.missing").unwrap().get(&data).is_err());
+
+        let data = JsonPath::parse("$.result.total").unwrap().set(data, json!(42)).unwrap();
+        assert_eq!(data["result"]["total"], 42);
+        assert_eq!(data["order"]["items"][0]["sku"], "a");
+        assert_eq!(JsonPath::default().set(data, json!("replaced")).unwrap(), "replaced");
+    }
+
+    #[test]
+    fn malformed_paths_are_rejected() {
+        for invalid in ["", "order", "$.", "$..a", "$[x]", "$.a[1", "$ a"] {
+            assert!(JsonPath::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod definition;
+pub mod engine;
+pub mod json_path;